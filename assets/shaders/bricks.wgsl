@@ -29,15 +29,18 @@ struct InstanceInput {
     @location(12) color:          vec4<f32>, 
     @location(13) size:          vec3<f32>,
     @location(14) stud_layout:         u32,
+    // Roughness, specular, emissive
+    @location(15) material:      vec3<f32>,
 }
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
-    @location(0) color: vec3<f32>,
+    @location(0) color: vec4<f32>,
     @location(1) tex_coords: vec2<f32>,
     @location(2) stud_index: u32,
     @location(3) world_normal: vec3<f32>,
     @location(4) world_position: vec3<f32>,
+    @location(5) material: vec3<f32>,
 }
 
 @vertex
@@ -65,7 +68,8 @@ fn vs_main(
     out.world_position = world_position.xyz; 
     out.world_normal = normal_matrix * model.normal;
     out.clip_position = camera.view_proj * world_position; 
-    out.color = instance.color.xyz;
+    out.color = instance.color;
+    out.material = instance.material;
 
     out.tex_coords = model.tex_coords * vec2<f32>(
         instance.size[model.tex_scale.x],
@@ -97,13 +101,20 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
 
     let view_dir = normalize(camera.view_pos.xyz - in.world_position);
     let reflect_dir = reflect(-light.direction, norm);
-    let spec = pow(max(dot(view_dir, reflect_dir), 0.0), 32.0);
+    // Rougher surfaces get a wider, dimmer highlight. Plastic (0.25) is a shininess of 32
+    let roughness = max(in.material.x, 0.01);
+    let shininess = 2.0 / (roughness * roughness);
+    let spec = pow(max(dot(view_dir, reflect_dir), 0.0), shininess);
 
-    let specular_strength = vec3<f32>(1.0, 1.0, 1.0) * spec; 
+    let specular_strength = vec3<f32>(1.0, 1.0, 1.0) * spec * in.material.y; 
     let diffuse_strength = max(dot(norm, -light.direction), 0.0); 
     let ambient_strength = vec3<f32>(1.0, 1.0, 1.0) * 0.1;
 
-    let color = mix(vec4<f32>(in.color, 1.0), vec4<f32>(object_color.rgb, 1.0), object_color.a);
-    return color * vec4<f32>(ambient_strength + diffuse_strength + specular_strength, 1.0);
+    let color = mix(vec4<f32>(in.color.rgb, 1.0), vec4<f32>(object_color.rgb, 1.0), object_color.a);
+    let lit = color * vec4<f32>(ambient_strength + diffuse_strength + specular_strength, 1.0);
+    // Emissive parts show their own color whatever the light does
+    let shaded = mix(lit, color, in.material.z);
+    // Opaque parts have an alpha of 1, the rest go through the blending pipeline
+    return vec4<f32>(shaded.rgb, in.color.a);
 }
  
\ No newline at end of file
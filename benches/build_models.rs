@@ -0,0 +1,56 @@
+/*
+    Times build_models on walls of bricks, run with `cargo bench --bench build_models`.
+
+    Bricks sit side by side on a grid in stacks of ten, so every one has neighbours touching on all sides.
+*/
+use bevy_ecs::prelude::*;
+use freebricks::{
+    common::{model_graph::build_models, state::State},
+    ecs::{common::Position, parts::Part, physics::Physical},
+    physics::PhysicsState,
+};
+use glam::Vec3;
+use std::time::{Duration, Instant};
+
+const SIZES: [usize; 3] = [10_000, 65_000, 130_000];
+const HEIGHT: usize = 10;
+const RUNS: usize = 3;
+
+fn scene(count: usize) -> World {
+    let mut world = World::new();
+    PhysicsState::consume(&mut world, PhysicsState::new());
+
+    let columns = count.div_ceil(HEIGHT);
+    let side = (columns as f32).sqrt().ceil() as usize;
+    let parts: Vec<_> = (0..count)
+        .map(|i| {
+            let (column, y) = (i / HEIGHT, i % HEIGHT);
+            let (x, z) = (column % side, column / side);
+            (
+                Part::Brick,
+                Physical,
+                Position(Vec3::new(x as f32 * 4.0, y as f32, z as f32 * 2.0)),
+            )
+        })
+        .collect();
+    world.spawn_batch(parts);
+    world
+}
+
+fn main() {
+    for count in SIZES {
+        let mut best = Duration::MAX;
+        for _ in 0..RUNS {
+            let mut world = scene(count);
+            let mut schedule = Schedule::default();
+            schedule.add_systems(build_models);
+            // Initialize up front so only the system is timed
+            schedule.initialize(&mut world).unwrap();
+
+            let start = Instant::now();
+            schedule.run(&mut world);
+            best = best.min(start.elapsed());
+        }
+        println!("build_models {:>7} bricks: {:>10.2?}", count, best);
+    }
+}
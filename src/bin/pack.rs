@@ -0,0 +1,55 @@
+use anyhow::{Context, Result, bail};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+
+use freebricks::common::asset_pack::{PACK_EXTENSION, build_pack};
+
+/// Bundle an asset directory into an archive AssetCache::init can load.
+///     cargo run --bin pack -- [--no-compress] <assets dir> [output]
+fn run() -> Result<()> {
+    let mut compress = true;
+    let mut paths = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--no-compress" => compress = false,
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let (dir, output) = match paths.as_slice() {
+        [dir] => (dir.clone(), dir.with_extension(PACK_EXTENSION)),
+        [dir, output] => (dir.clone(), output.clone()),
+        _ => bail!("Usage: pack [--no-compress] <assets dir> [output]"),
+    };
+    if !dir.is_dir() {
+        bail!("{} isn't a directory", dir.display());
+    }
+
+    let file =
+        File::create(&output).with_context(|| format!("Couldn't create {}", output.display()))?;
+    let mut writer = BufWriter::new(file);
+    let entries = build_pack(&dir, &mut writer, compress)?;
+    writer.flush()?;
+
+    let stored: u64 = entries.iter().map(|e| e.stored_len).sum();
+    let len: u64 = entries.iter().map(|e| e.len).sum();
+    println!(
+        "Packed {} assets from {} into {}, {} bytes ({} uncompressed)",
+        entries.len(),
+        dir.display(),
+        output.display(),
+        stored,
+        len
+    );
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{:#}", e);
+        std::process::exit(1);
+    }
+}
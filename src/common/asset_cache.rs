@@ -1,58 +1,396 @@
 use anyhow::Result;
 use bevy_ecs::prelude::*;
+use image::{ImageFormat, Rgba, RgbaImage};
 use std::{
-    collections::HashMap,
-    fs::{self, File, metadata},
-    io::Read,
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    hash::{Hash, Hasher},
+    io::{self, Cursor},
+    marker::PhantomData,
     path::{Path, PathBuf},
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant, SystemTime},
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    common::{
+        asset_pack::AssetPack,
+        mesh::{FALLBACK_MESH, GLB_EXTENSION, Mesh, OBJ_EXTENSION},
+    },
+    render::texture::STUD_LAYERS,
+    scene::{
+        SCENE_VERSION,
+        prefab::{PREFAB_EXTENSION, Prefab},
+    },
 };
-use tracing::info;
 
 pub enum Asset {
     Image(Vec<u8>),
     Shader(String),
+    Prefab(Prefab),
+    Mesh(Mesh),
 }
 
 impl Asset {
     /// Given a filepath, parse it if it's a relevant asset, otherwise return nothing.
-    pub fn from_file(path: &Path, mut file: File) -> Option<Self> {
-        let metadata = metadata(path).expect(format!("Error loading metadata for file").as_str());
-        /*
-           Check different matches here
-        */
-        let mut asset: Option<Self> = None;
-
-        if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
-            if ext == "png" {
-                let mut buffer = vec![0; metadata.len() as usize];
-                file.read(&mut buffer)
-                    .expect("Error reading texture for file");
-
-                asset = Some(Asset::Image(buffer));
-            } else if ext == "wgsl" {
-                let mut shader_source = String::new();
-                file.read_to_string(&mut shader_source)
-                    .expect("Error reading shader {}");
-
-                asset = Some(Asset::Shader(shader_source));
+    pub fn from_file(path: &Path) -> Result<Option<Self>, AssetError> {
+        if !Self::is_relevant(path) {
+            return Ok(None);
+        }
+        let bytes = fs::read(path).map_err(|e| AssetError::io(path, e))?;
+        Self::from_bytes(path, bytes).map(Some)
+    }
+
+    /// Does the extension belong to an asset we know how to parse
+    pub fn is_relevant(path: &Path) -> bool {
+        matches!(
+            path.extension().and_then(|s| s.to_str()),
+            Some("png" | "wgsl" | PREFAB_EXTENSION | OBJ_EXTENSION | GLB_EXTENSION)
+        )
+    }
+
+    /// Parse the contents of a relevant file, `path` is only used for the extension and errors
+    pub fn from_bytes(path: &Path, bytes: Vec<u8>) -> Result<Self, AssetError> {
+        let ext = path.extension().and_then(|s| s.to_str());
+        let asset = if ext == Some("png") {
+            // Decode once up front so a corrupt image never reaches the renderer
+            image::load_from_memory(&bytes).map_err(|e| AssetError::invalid(path, e))?;
+            Asset::Image(bytes)
+        } else if ext == Some(GLB_EXTENSION) {
+            Asset::Mesh(Mesh::from_glb(&bytes).map_err(|e| AssetError::invalid(path, e))?)
+        } else {
+            let source = String::from_utf8(bytes).map_err(|e| AssetError::invalid(path, e))?;
+            if ext == Some("wgsl") {
+                Asset::Shader(source)
+            } else if ext == Some(OBJ_EXTENSION) {
+                Asset::Mesh(Mesh::from_obj(&source).map_err(|e| AssetError::invalid(path, e))?)
+            } else {
+                Asset::Prefab(Prefab::from_ron(&source).map_err(|e| AssetError::invalid(path, e))?)
             }
+        };
+        Ok(asset)
+    }
+}
+
+/// Name a file is cached under, its path without the top directory
+pub(crate) fn asset_name(path: &Path) -> String {
+    path.components()
+        .skip(1)
+        .collect::<PathBuf>()
+        .to_string_lossy()
+        .to_string()
+}
+
+/*
+    Load errors
+*/
+
+#[derive(Debug, Clone)]
+pub struct AssetError {
+    /// File on disk, or the cache name for lookups
+    pub path: PathBuf,
+    pub cause: AssetErrorCause,
+}
+
+#[derive(Debug, Clone)]
+pub enum AssetErrorCause {
+    /// Nothing was loaded under this name
+    Missing,
+    /// Loaded, but as another kind of asset
+    WrongKind { expected: &'static str },
+    /// File couldn't be read
+    Io(Arc<io::Error>),
+    /// File was read but couldn't be parsed
+    Invalid(String),
+    /// Archive entry doesn't match its hash
+    Tampered(String),
+}
+
+impl AssetError {
+    fn io(path: &Path, e: io::Error) -> Self {
+        AssetError {
+            path: path.to_path_buf(),
+            cause: AssetErrorCause::Io(Arc::new(e)),
+        }
+    }
+
+    fn invalid(path: &Path, e: impl fmt::Display) -> Self {
+        AssetError {
+            path: path.to_path_buf(),
+            cause: AssetErrorCause::Invalid(format!("{:#}", e)),
+        }
+    }
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.path.display())?;
+        match &self.cause {
+            AssetErrorCause::Missing => write!(f, "no such asset"),
+            AssetErrorCause::WrongKind { expected } => write!(f, "not a {}", expected),
+            AssetErrorCause::Io(e) => write!(f, "couldn't read file, {}", e),
+            AssetErrorCause::Invalid(e) => write!(f, "couldn't parse file, {}", e),
+            AssetErrorCause::Tampered(e) => write!(f, "archive entry is corrupt, {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AssetError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.cause {
+            AssetErrorCause::Io(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/*
+    Typed handles
+*/
+
+/// Kinds of asset a Handle can point at
+pub trait AssetKind: 'static {
+    type Data: ?Sized;
+    const NAME: &'static str;
+
+    fn get(asset: &Asset) -> Option<&Self::Data>;
+    /// Built-in stand-in for entries that are missing or failed to load
+    fn fallback() -> &'static Self::Data;
+}
+
+/// WGSL source
+pub struct Shader;
+/// Encoded image bytes, guaranteed to decode
+pub struct Image;
+
+/// Draws whatever it's given flat magenta, only needs a position at location 0
+pub const FALLBACK_SHADER: &str = "
+@vertex
+fn vs_main(@location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(position.xy * 0.05, 0.5, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0, 0.0, 1.0, 1.0);
+}
+";
+
+/// Magenta and black checker, square layers stacked like textures/studs.png
+static FALLBACK_IMAGE: LazyLock<Vec<u8>> = LazyLock::new(|| {
+    let image = RgbaImage::from_fn(16, 16 * STUD_LAYERS, |x, y| {
+        if (x / 4 + y / 4) % 2 == 0 {
+            Rgba([0xFF, 0x00, 0xFF, 0xFF])
+        } else {
+            Rgba([0x00, 0x00, 0x00, 0xFF])
+        }
+    });
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .expect("Couldn't encode fallback image");
+    png
+});
+
+static FALLBACK_PREFAB: Prefab = Prefab {
+    version: SCENE_VERSION,
+    parts: Vec::new(),
+    connections: Vec::new(),
+};
+
+impl AssetKind for Shader {
+    type Data = str;
+    const NAME: &'static str = "shader";
+
+    fn get(asset: &Asset) -> Option<&str> {
+        match asset {
+            Asset::Shader(source) => Some(source),
+            _ => None,
+        }
+    }
+
+    fn fallback() -> &'static str {
+        FALLBACK_SHADER
+    }
+}
+
+impl AssetKind for Image {
+    type Data = [u8];
+    const NAME: &'static str = "image";
+
+    fn get(asset: &Asset) -> Option<&[u8]> {
+        match asset {
+            Asset::Image(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    fn fallback() -> &'static [u8] {
+        &FALLBACK_IMAGE
+    }
+}
+
+impl AssetKind for Prefab {
+    type Data = Prefab;
+    const NAME: &'static str = "prefab";
+
+    fn get(asset: &Asset) -> Option<&Prefab> {
+        match asset {
+            Asset::Prefab(prefab) => Some(prefab),
+            _ => None,
+        }
+    }
+
+    fn fallback() -> &'static Prefab {
+        &FALLBACK_PREFAB
+    }
+}
+
+impl AssetKind for Mesh {
+    type Data = Mesh;
+    const NAME: &'static str = "mesh";
+
+    fn get(asset: &Asset) -> Option<&Mesh> {
+        match asset {
+            Asset::Mesh(mesh) => Some(mesh),
+            _ => None,
+        }
+    }
+
+    fn fallback() -> &'static Mesh {
+        &FALLBACK_MESH
+    }
+}
+
+/// Typed name of an entry in the AssetCache.
+/// Resolving it never fails, entries that are missing or broken resolve to the fallback.
+pub struct Handle<T: AssetKind> {
+    name: String,
+    kind: PhantomData<fn() -> T>,
+}
+
+impl<T: AssetKind> Handle<T> {
+    pub fn new(name: &str) -> Self {
+        Handle {
+            name: name.to_string(),
+            kind: PhantomData,
         }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
 
-        return asset;
+impl<T: AssetKind> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Handle::new(&self.name)
     }
 }
 
+impl<T: AssetKind> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handle<{}>({})", T::NAME, self.name)
+    }
+}
+
+impl<T: AssetKind> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl<T: AssetKind> Eq for Handle<T> {}
+
+impl<T: AssetKind> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+    }
+}
+
+/*
+    Cache
+*/
+
+/// How often AssetCache::watch looks at the asset directory
+pub const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
 #[derive(Resource)]
 pub struct AssetCache {
     pub map: HashMap<String, Asset>,
+    /// Entries reloaded by the last poll, cleared at the start of every AssetCache::watch
+    pub changed: HashSet<String>,
+    /// Files whose last load failed, keyed by entry name
+    errors: HashMap<String, AssetError>,
+    /// Directory to poll, archives are read once and never polled
+    root: Option<PathBuf>,
+    modified: HashMap<String, SystemTime>,
+    last_poll: Instant,
+}
+
+impl Default for AssetCache {
+    /// Empty cache that isn't backed by a directory
+    fn default() -> Self {
+        AssetCache {
+            map: HashMap::new(),
+            changed: HashSet::new(),
+            errors: HashMap::new(),
+            root: None,
+            modified: HashMap::new(),
+            last_poll: Instant::now(),
+        }
+    }
 }
 
 impl AssetCache {
-    /// Recursively load a directory for relevant assets
-    pub fn init(dir: &str) -> Result<Self> {
-        let mut map: HashMap<String, Asset> = HashMap::new();
+    /// Recursively load a directory for relevant assets, or every entry of an asset archive.
+    /// Files that fail to load are logged and skipped, only an unreadable directory or
+    ///     archive is an error.
+    pub fn init(path: &str) -> Result<Self> {
+        let path = PathBuf::from(path);
+        if path.is_file() {
+            return Self::from_pack(&AssetPack::open(&path)?);
+        }
 
-        let mut paths = vec![PathBuf::from(dir)];
+        let mut cache = AssetCache {
+            root: Some(path),
+            ..Default::default()
+        };
+        cache.poll()?;
+        cache.changed.clear();
+        Ok(cache)
+    }
+
+    /// Load every entry of an archive, entries that fail their hash are skipped like broken files
+    pub fn from_pack(pack: &AssetPack) -> Result<Self> {
+        let mut cache = AssetCache::default();
+        for entry in &pack.entries {
+            let asset = pack
+                .read(entry)
+                .and_then(|bytes| Asset::from_bytes(&pack.path.join(&entry.name), bytes));
+            match asset {
+                Ok(asset) => {
+                    info!("Inserting asset {}", entry.name);
+                    cache.map.insert(entry.name.clone(), asset);
+                }
+                Err(e) => {
+                    error!("Skipping asset {}", e);
+                    cache.errors.insert(entry.name.clone(), e);
+                }
+            }
+        }
+        Ok(cache)
+    }
+
+    /// Reload every file modified since the last poll and mark it in `changed`
+    pub fn poll(&mut self) -> Result<()> {
+        self.last_poll = Instant::now();
+        let Some(root) = self.root.clone() else {
+            return Ok(());
+        };
+
+        let mut paths = vec![root];
         while !paths.is_empty() {
             let path = paths.pop().unwrap();
 
@@ -63,50 +401,115 @@ impl AssetCache {
 
                 if path.is_dir() {
                     paths.push(path);
-                } else {
-                    let file = File::open(&path)?;
-
-                    if let Some(asset) = Asset::from_file(&path, file) {
-                        let new_name = path
-                            .clone()
-                            .components()
-                            .skip(1)
-                            .collect::<PathBuf>()
-                            .to_string_lossy()
-                            .to_string();
-
-                        info!("Inserting asset {}", new_name);
-                        map.insert(new_name, asset);
+                    continue;
+                }
+
+                let new_name = asset_name(&path);
+
+                // A file that vanished or can't be read is picked up again on a later poll
+                let modified = match entry.metadata().and_then(|metadata| metadata.modified()) {
+                    Ok(modified) => modified,
+                    Err(e) => {
+                        warn!("Couldn't check {} for changes, {}", new_name, e);
+                        continue;
+                    }
+                };
+                if self.modified.get(&new_name) == Some(&modified) {
+                    continue;
+                }
+                self.modified.insert(new_name.clone(), modified);
+
+                match Asset::from_file(&path) {
+                    Ok(Some(asset)) => {
+                        if self.map.contains_key(&new_name) {
+                            info!("Reloading asset {}", new_name);
+                        } else {
+                            info!("Inserting asset {}", new_name);
+                        }
+                        self.errors.remove(&new_name);
+                        self.map.insert(new_name.clone(), asset);
+                        self.changed.insert(new_name);
+                    }
+                    Ok(None) => {}
+                    // Keep whatever loaded last, a half-saved file shouldn't wipe it
+                    Err(e) => {
+                        error!("Skipping asset {}", e);
+                        self.errors.insert(new_name, e);
                     }
                 }
             }
         }
-        Ok(AssetCache { map: map })
+        Ok(())
+    }
+
+    /// Polls the asset directory every WATCH_INTERVAL.
+    /// Run before anything that reacts to `changed`, entries only stay marked for one run.
+    pub fn watch(mut cache: ResMut<AssetCache>) {
+        let cache = cache.bypass_change_detection();
+        cache.changed.clear();
+        if cache.last_poll.elapsed() < WATCH_INTERVAL {
+            return;
+        }
+        if let Err(e) = cache.poll() {
+            error!("Couldn't poll asset directory: {:#}", e);
+        }
+    }
+
+    /// Was this entry reloaded by the last poll
+    pub fn was_reloaded(&self, name: &str) -> bool {
+        self.changed.contains(name)
+    }
+
+    /// Handle to a loaded entry, or why there isn't one
+    pub fn load<T: AssetKind>(&self, name: &str) -> Result<Handle<T>, AssetError> {
+        if let Some(e) = self.errors.get(name) {
+            return Err(e.clone());
+        }
+        let cause = match self.map.get(name) {
+            Some(asset) if T::get(asset).is_some() => return Ok(Handle::new(name)),
+            Some(_) => AssetErrorCause::WrongKind { expected: T::NAME },
+            None => AssetErrorCause::Missing,
+        };
+        Err(AssetError {
+            path: PathBuf::from(name),
+            cause,
+        })
+    }
+
+    /// Same as load, but logs failures and hands out a handle that resolves to the fallback
+    ///     until the entry shows up.
+    pub fn load_or_fallback<T: AssetKind>(&self, name: &str) -> Handle<T> {
+        if let Err(e) = self.load::<T>(name) {
+            error!("Using fallback {} for {}", T::NAME, e);
+        }
+        Handle::new(name)
+    }
+
+    /// Resolve a handle, falling back to the built-in asset
+    pub fn get<T: AssetKind>(&self, handle: &Handle<T>) -> &T::Data {
+        self.try_get(handle).unwrap_or_else(|| T::fallback())
+    }
+
+    /// Resolve a handle, without falling back
+    pub fn try_get<T: AssetKind>(&self, handle: &Handle<T>) -> Option<&T::Data> {
+        self.map.get(&handle.name).and_then(T::get)
     }
 
     /// Shorthand to get a shader to prevent having to do match every time
     pub fn get_shader(&self, name: &str) -> Option<&String> {
-        if let Some(asset) = self.map.get(name) {
-            match asset {
-                Asset::Shader(src) => {
-                    return Some(src);
-                }
-                _ => {}
-            }
+        match self.map.get(name) {
+            Some(Asset::Shader(source)) => Some(source),
+            _ => None,
         }
-        return None;
     }
 
     /// Shorthand to get an image to prevent having to do a match every time
     pub fn get_image(&self, name: &str) -> Option<&[u8]> {
-        if let Some(asset) = self.map.get(name) {
-            match asset {
-                Asset::Image(tex) => {
-                    return Some(tex);
-                }
-                _ => {}
-            }
-        }
-        return None;
+        self.try_get(&Handle::<Image>::new(name))
+    }
+
+    /// Shorthand to get a prefab to prevent having to do a match every time
+    pub fn get_prefab(&self, name: &str) -> Option<&Prefab> {
+        self.try_get(&Handle::<Prefab>::new(name))
     }
 }
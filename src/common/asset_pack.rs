@@ -0,0 +1,253 @@
+/*
+    Packed asset archive, everything AssetCache::init would read from a directory in one file.
+
+    "FBPK" | version: u32 | count: u32 | entries | toc hash: [u8; 32] | data
+
+    entry:
+        name length: u16 | name: utf8 | offset: u64 | stored length: u64 | length: u64 |
+        compressed: u8 | hash: [u8; 32]
+
+    Integers are little endian, offsets are from the start of the data section.
+    Hashes are SHA-256, the toc hash covers everything before it and each entry hash covers
+    the entry after decompression. Entries are only stored deflated when that's smaller.
+*/
+use anyhow::{Context, Result, bail};
+use flate2::{Compression, read::DeflateDecoder, write::DeflateEncoder};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::common::asset_cache::{Asset, AssetError, AssetErrorCause};
+
+pub const PACK_EXTENSION: &str = "fbpack";
+pub const PACK_MAGIC: &[u8; 4] = b"FBPK";
+pub const PACK_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackEntry {
+    /// Same name AssetCache gives the file when loading the directory
+    pub name: String,
+    pub offset: u64,
+    pub stored_len: u64,
+    pub len: u64,
+    pub compressed: bool,
+    pub hash: [u8; 32],
+}
+
+pub struct AssetPack {
+    pub path: PathBuf,
+    pub entries: Vec<PackEntry>,
+    data: Vec<u8>,
+}
+
+impl AssetPack {
+    /// Read an archive and check its table of contents, entries are checked when they're read
+    pub fn open(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path).with_context(|| format!("Couldn't read {}", path.display()))?;
+        Self::from_bytes(path, bytes)
+    }
+
+    pub fn from_bytes(path: &Path, bytes: Vec<u8>) -> Result<Self> {
+        let mut reader = &bytes[..];
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic).context("Archive too short")?;
+        if &magic != PACK_MAGIC {
+            bail!("{} isn't an asset archive", path.display());
+        }
+        let version = read_u32(&mut reader)?;
+        if version == 0 || version > PACK_VERSION {
+            bail!(
+                "Unsupported archive version {}, expected at most {}",
+                version,
+                PACK_VERSION
+            );
+        }
+
+        let count = read_u32(&mut reader)?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let name_len = read_u16(&mut reader)? as usize;
+            let mut name = vec![0; name_len];
+            reader.read_exact(&mut name)?;
+            let name = String::from_utf8(name).context("Entry name isn't utf8")?;
+
+            let offset = read_u64(&mut reader)?;
+            let stored_len = read_u64(&mut reader)?;
+            let len = read_u64(&mut reader)?;
+            let compressed = read_u8(&mut reader)? != 0;
+            let mut hash = [0; 32];
+            reader.read_exact(&mut hash)?;
+
+            entries.push(PackEntry {
+                name,
+                offset,
+                stored_len,
+                len,
+                compressed,
+                hash,
+            });
+        }
+
+        let toc_len = bytes.len() - reader.len();
+        let mut toc_hash = [0; 32];
+        reader
+            .read_exact(&mut toc_hash)
+            .context("Archive is missing its toc hash")?;
+        if Sha256::digest(&bytes[..toc_len]).as_slice() != toc_hash {
+            bail!("Table of contents of {} was modified", path.display());
+        }
+
+        let data = reader.to_vec();
+        for entry in &entries {
+            let end = entry.offset.checked_add(entry.stored_len);
+            if end.is_none_or(|end| end > data.len() as u64) {
+                bail!("Entry {} runs past the end of the archive", entry.name);
+            }
+        }
+
+        Ok(AssetPack {
+            path: path.to_path_buf(),
+            entries,
+            data,
+        })
+    }
+
+    /// Decompress an entry and check it against its hash
+    pub fn read(&self, entry: &PackEntry) -> Result<Vec<u8>, AssetError> {
+        let path = self.path.join(&entry.name);
+        let stored = &self.data[entry.offset as usize..(entry.offset + entry.stored_len) as usize];
+
+        let bytes = if entry.compressed {
+            // Stored lengths aren't trusted for allocating, one byte past it shows a longer entry
+            let mut bytes = Vec::new();
+            DeflateDecoder::new(stored)
+                .take(entry.len.saturating_add(1))
+                .read_to_end(&mut bytes)
+                .map_err(|e| AssetError {
+                    path: path.clone(),
+                    cause: AssetErrorCause::Tampered(format!("couldn't inflate, {}", e)),
+                })?;
+            bytes
+        } else {
+            stored.to_vec()
+        };
+
+        if bytes.len() as u64 != entry.len || Sha256::digest(&bytes).as_slice() != entry.hash {
+            return Err(AssetError {
+                path,
+                cause: AssetErrorCause::Tampered("hash doesn't match".to_string()),
+            });
+        }
+        Ok(bytes)
+    }
+}
+
+/// Name an entry is stored under, its path inside the packed directory with `/` separators
+fn entry_name(dir: &Path, path: &Path) -> Result<String> {
+    let relative = path
+        .strip_prefix(dir)
+        .with_context(|| format!("{} isn't under {}", path.display(), dir.display()))?;
+    let parts: Vec<_> = relative
+        .components()
+        .map(|part| part.as_os_str().to_string_lossy())
+        .collect();
+    Ok(parts.join("/"))
+}
+
+/// Pack every relevant asset under `dir` into `writer`, returns the entries written
+pub fn build_pack(dir: &Path, writer: &mut impl Write, compress: bool) -> Result<Vec<PackEntry>> {
+    let mut files = Vec::new();
+    let mut paths = vec![dir.to_path_buf()];
+    while let Some(path) = paths.pop() {
+        for entry in fs::read_dir(&path)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                paths.push(path);
+            } else if Asset::is_relevant(&path) {
+                files.push(path);
+            }
+        }
+    }
+    // Same input, same archive
+    files.sort();
+
+    let mut entries = Vec::new();
+    let mut data = Vec::new();
+    for path in files {
+        let bytes = fs::read(&path)?;
+        let mut stored = bytes.clone();
+        let mut compressed = false;
+        if compress {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+            encoder.write_all(&bytes)?;
+            let deflated = encoder.finish()?;
+            if deflated.len() < bytes.len() {
+                stored = deflated;
+                compressed = true;
+            }
+        }
+
+        entries.push(PackEntry {
+            name: entry_name(dir, &path)?,
+            offset: data.len() as u64,
+            stored_len: stored.len() as u64,
+            len: bytes.len() as u64,
+            compressed,
+            hash: Sha256::digest(&bytes).into(),
+        });
+        data.extend_from_slice(&stored);
+    }
+
+    let mut toc = Vec::new();
+    toc.extend_from_slice(PACK_MAGIC);
+    toc.extend_from_slice(&PACK_VERSION.to_le_bytes());
+    toc.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    for entry in &entries {
+        let name_len = u16::try_from(entry.name.len())
+            .with_context(|| format!("Asset name {} is too long", entry.name))?;
+        toc.extend_from_slice(&name_len.to_le_bytes());
+        toc.extend_from_slice(entry.name.as_bytes());
+        toc.extend_from_slice(&entry.offset.to_le_bytes());
+        toc.extend_from_slice(&entry.stored_len.to_le_bytes());
+        toc.extend_from_slice(&entry.len.to_le_bytes());
+        toc.push(entry.compressed as u8);
+        toc.extend_from_slice(&entry.hash);
+    }
+    let toc_hash = Sha256::digest(&toc);
+
+    writer.write_all(&toc)?;
+    writer.write_all(&toc_hash)?;
+    writer.write_all(&data)?;
+    Ok(entries)
+}
+
+/*
+    Helper functions
+*/
+
+fn read_u8(reader: &mut &[u8]) -> Result<u8> {
+    let mut buf = [0; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16(reader: &mut &[u8]) -> Result<u16> {
+    let mut buf = [0; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32(reader: &mut &[u8]) -> Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut &[u8]) -> Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
@@ -1,5 +1,10 @@
 use crate::{
-    common::{asset_cache::AssetCache, model_graph::*, state::State},
+    common::{
+        asset_cache::AssetCache,
+        model_graph::*,
+        selector::{QSelect, Selector},
+        state::State,
+    },
     ecs::{common::*, parts::*, physics::*},
     physics::PhysicsState,
     render::{
@@ -12,17 +17,21 @@ use crate::{
 use anyhow::Result;
 use bevy_ecs::prelude::*;
 use glam::Vec3;
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
 use tracing::error;
 use winit::{dpi::PhysicalSize, window::Window};
 
-#[derive(Component)]
-pub struct Tag1;
+const ASSET_DIRECTORY: &str = "assets";
+const ASSET_ARCHIVE: &str = "assets.fbpack";
+
+const BASEPLATE: &str = "Baseplate";
 
-pub fn foobar(mut commands: Commands, mut count: Local<u64>, test: Query<(Entity, &Tag1)>) {
+pub fn foobar(mut commands: Commands, mut count: Local<u64>, parts: Query<QSelect>) {
     if *count == 140 {
-        let (e, _) = test.iter().next().unwrap();
-        commands.entity(e).despawn();
+        let selector = Selector::new().named(BASEPLATE);
+        if let Some(baseplate) = selector.iter(&parts).next() {
+            commands.entity(baseplate.entity).despawn();
+        }
     }
     *count += 1;
 }
@@ -43,7 +52,13 @@ impl Game {
 
         let mut world = World::new();
 
-        let asset_cache = AssetCache::init("assets").expect("Unable to load from asset directory");
+        // Shipped builds carry an archive instead of the loose directory
+        let assets = if Path::new(ASSET_ARCHIVE).is_file() {
+            ASSET_ARCHIVE
+        } else {
+            ASSET_DIRECTORY
+        };
+        let asset_cache = AssetCache::init(assets).expect("Unable to load assets");
 
         world.insert_resource(asset_cache);
 
@@ -74,6 +89,9 @@ impl Game {
                 .chain(),
         );
         update_schedule.add_systems((foobar,).chain());
+        // Hot reload of assets/, pipelines pick up edited shaders and textures
+        update_schedule
+            .add_systems((AssetCache::watch, SceneTree::reload, DebugDraw::reload).chain());
 
         post_update_schedule.add_systems(
             (
@@ -82,6 +100,7 @@ impl Game {
                 SceneTree::remove_bricks,
                 SceneTree::add_bricks,
                 SceneTree::update_bricks,
+                SceneTree::sort_transparent,
             )
                 .chain(),
         );
@@ -100,7 +119,7 @@ impl Game {
             Size(Vec3::new(20.0, 1.0, 20.0)),
             Physical,
             Anchor,
-            Tag1,
+            Name::new(BASEPLATE),
         ));
 
         parts.push((
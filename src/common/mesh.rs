@@ -0,0 +1,424 @@
+/*
+    Triangle meshes for Part::Mesh, loaded from OBJ or binary glTF.
+
+    Meshes are fitted into the unit cube around the origin when loaded, the same space as the
+    brick mesh, so a part's Size stretches it to its bounding box for rendering and physics.
+    Triangles are counter-clockwise like both file formats, the renderer flips them itself.
+*/
+use anyhow::{Context, Result, anyhow, bail};
+use glam::{Mat4, Quat, Vec3};
+use serde_json::Value;
+use std::{collections::HashMap, sync::LazyLock};
+
+use crate::{
+    render::bricks::{INDICES, VERTICES},
+    scene::gltf::{
+        CHUNK_BIN, CHUNK_JSON, FLOAT, GLB_MAGIC, UNSIGNED_BYTE, UNSIGNED_INT, UNSIGNED_SHORT,
+    },
+};
+
+pub const OBJ_EXTENSION: &str = "obj";
+pub const GLB_EXTENSION: &str = "glb";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub indices: Vec<u32>,
+}
+
+/// The brick cube, stands in for meshes that failed to load
+pub static FALLBACK_MESH: LazyLock<Mesh> = LazyLock::new(|| Mesh {
+    positions: VERTICES.iter().map(|v| Vec3::from(v.position)).collect(),
+    normals: VERTICES.iter().map(|v| Vec3::from(v.normals)).collect(),
+    // Brick indices are clockwise
+    indices: INDICES
+        .chunks_exact(3)
+        .flat_map(|t| [t[0] as u32, t[2] as u32, t[1] as u32])
+        .collect(),
+});
+
+impl Mesh {
+    /// Parse Wavefront OBJ source, faces are fanned into triangles.
+    /// Flat normals are generated when the file doesn't give every vertex one.
+    pub fn from_obj(source: &str) -> Result<Self> {
+        let mut positions: Vec<Vec3> = Vec::new();
+        let mut normals: Vec<Vec3> = Vec::new();
+        let mut corners: Vec<(usize, Option<usize>)> = Vec::new();
+
+        for (line_number, line) in source.lines().enumerate() {
+            let mut tokens = line.split_whitespace();
+            let line_number = line_number + 1;
+            match tokens.next() {
+                Some("v") => positions.push(parse_vec3(tokens, line_number)?),
+                Some("vn") => normals.push(parse_vec3(tokens, line_number)?),
+                Some("f") => {
+                    let face = tokens
+                        .map(|token| {
+                            parse_corner(token, positions.len(), normals.len())
+                                .with_context(|| format!("Bad face on line {}", line_number))
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    if face.len() < 3 {
+                        bail!("Face on line {} has fewer than 3 vertices", line_number);
+                    }
+                    for i in 1..face.len() - 1 {
+                        corners.extend([face[0], face[i], face[i + 1]]);
+                    }
+                }
+                // Texture coordinates, groups, materials and comments don't matter here
+                _ => {}
+            }
+        }
+
+        let mesh = if corners.iter().all(|(_, normal)| normal.is_some()) {
+            let mut lookup: HashMap<(usize, Option<usize>), u32> = HashMap::new();
+            let mut mesh = Mesh::empty();
+            for corner in corners {
+                let index = *lookup.entry(corner).or_insert_with(|| {
+                    mesh.positions.push(positions[corner.0]);
+                    mesh.normals.push(normals[corner.1.unwrap()]);
+                    mesh.positions.len() as u32 - 1
+                });
+                mesh.indices.push(index);
+            }
+            mesh
+        } else {
+            let corners: Vec<Vec3> = corners.iter().map(|(p, _)| positions[*p]).collect();
+            Mesh::flat(&corners)
+        };
+        mesh.fit()
+    }
+
+    /// Parse a binary glTF, every triangle primitive in the default scene is merged
+    ///     with its node transforms applied.
+    pub fn from_glb(bytes: &[u8]) -> Result<Self> {
+        let (document, bin) = read_glb(bytes)?;
+
+        let root_nodes: Vec<usize> = match document["scenes"].as_array() {
+            Some(scenes) => {
+                let scene = document["scene"].as_u64().unwrap_or(0) as usize;
+                let nodes = scenes
+                    .get(scene)
+                    .ok_or(anyhow!("Default scene {} doesn't exist", scene))?;
+                indices_of(&nodes["nodes"])
+            }
+            // No scenes, every node is drawn on its own
+            None => (0..json_len(&document["nodes"])).collect(),
+        };
+
+        let mut mesh = Mesh::empty();
+        let mut stack: Vec<(usize, Mat4)> = root_nodes
+            .into_iter()
+            .map(|n| (n, Mat4::IDENTITY))
+            .collect();
+        let mut visited = 0;
+        while let Some((index, parent)) = stack.pop() {
+            // Nodes have at most one parent, seeing more than there are means a cycle
+            visited += 1;
+            if visited > json_len(&document["nodes"]) {
+                bail!("Node hierarchy has a cycle");
+            }
+            let node = &document["nodes"][index];
+            if node.is_null() {
+                bail!("Node {} doesn't exist", index);
+            }
+            let transform = parent * node_transform(node)?;
+            for child in indices_of(&node["children"]) {
+                stack.push((child, transform));
+            }
+            let Some(mesh_index) = node["mesh"].as_u64() else {
+                continue;
+            };
+
+            let normal_matrix = transform.inverse().transpose();
+            let primitives = document["meshes"][mesh_index as usize]["primitives"]
+                .as_array()
+                .ok_or(anyhow!("Mesh {} has no primitives", mesh_index))?;
+            for primitive in primitives {
+                // Triangles are the default, skip points and lines
+                if primitive["mode"].as_u64().unwrap_or(4) != 4 {
+                    continue;
+                }
+                let attributes = &primitive["attributes"];
+                let accessor = attributes["POSITION"]
+                    .as_u64()
+                    .ok_or(anyhow!("Primitive has no positions"))?;
+                let positions: Vec<Vec3> = read_vec3s(&document, bin, accessor as usize)?
+                    .into_iter()
+                    .map(|p| transform.transform_point3(p))
+                    .collect();
+                let indices = match primitive["indices"].as_u64() {
+                    Some(accessor) => read_indices(&document, bin, accessor as usize)?,
+                    None => (0..positions.len() as u32).collect(),
+                };
+                if let Some(index) = indices.iter().find(|&&i| i as usize >= positions.len()) {
+                    bail!("Index {} is out of range", index);
+                }
+
+                match attributes["NORMAL"].as_u64() {
+                    Some(accessor) => {
+                        let offset = mesh.positions.len() as u32;
+                        let normals = read_vec3s(&document, bin, accessor as usize)?;
+                        if normals.len() != positions.len() {
+                            bail!("Primitive has a different number of normals and positions");
+                        }
+                        mesh.positions.extend(positions);
+                        mesh.normals.extend(
+                            normals
+                                .into_iter()
+                                .map(|n| normal_matrix.transform_vector3(n).normalize_or_zero()),
+                        );
+                        mesh.indices.extend(indices.iter().map(|i| i + offset));
+                    }
+                    None => {
+                        let corners: Vec<Vec3> =
+                            indices.iter().map(|&i| positions[i as usize]).collect();
+                        mesh.append(Mesh::flat(&corners));
+                    }
+                }
+            }
+        }
+        mesh.fit()
+    }
+
+    /// Triangles as index triples
+    pub fn triangles(&self) -> impl Iterator<Item = [u32; 3]> + '_ {
+        self.indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]])
+    }
+
+    fn empty() -> Self {
+        Mesh {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    /// Unindexed triangles with face normals
+    fn flat(corners: &[Vec3]) -> Self {
+        let mut mesh = Mesh::empty();
+        for triangle in corners.chunks_exact(3) {
+            let normal = (triangle[1] - triangle[0])
+                .cross(triangle[2] - triangle[0])
+                .normalize_or_zero();
+            for &corner in triangle {
+                mesh.indices.push(mesh.positions.len() as u32);
+                mesh.positions.push(corner);
+                mesh.normals.push(normal);
+            }
+        }
+        mesh
+    }
+
+    fn append(&mut self, other: Mesh) {
+        let offset = self.positions.len() as u32;
+        self.positions.extend(other.positions);
+        self.normals.extend(other.normals);
+        self.indices
+            .extend(other.indices.iter().map(|i| i + offset));
+    }
+
+    /// Scale and move into the unit cube around the origin
+    fn fit(mut self) -> Result<Self> {
+        if self.indices.is_empty() {
+            bail!("Mesh has no triangles");
+        }
+        let (min, max) = self
+            .positions
+            .iter()
+            .fold((Vec3::MAX, Vec3::MIN), |(min, max), &p| {
+                (min.min(p), max.max(p))
+            });
+        let center = (min + max) / 2.0;
+        // Flat along an axis, nothing to stretch
+        let extent = (max - min).max(Vec3::splat(f32::EPSILON));
+
+        for position in &mut self.positions {
+            *position = (*position - center) / extent;
+        }
+        for normal in &mut self.normals {
+            *normal = (*normal * extent).normalize_or_zero();
+        }
+        Ok(self)
+    }
+}
+
+/*
+    Helper functions
+*/
+
+fn parse_vec3<'a>(mut tokens: impl Iterator<Item = &'a str>, line_number: usize) -> Result<Vec3> {
+    let mut next = || -> Result<f32> {
+        tokens
+            .next()
+            .ok_or(anyhow!("Missing coordinate on line {}", line_number))?
+            .parse()
+            .with_context(|| format!("Bad coordinate on line {}", line_number))
+    };
+    Ok(Vec3::new(next()?, next()?, next()?))
+}
+
+/// One face corner, `v`, `v/vt`, `v//vn` or `v/vt/vn`, as zero based position and normal
+fn parse_corner(token: &str, positions: usize, normals: usize) -> Result<(usize, Option<usize>)> {
+    let mut parts = token.split('/');
+    let position = resolve_index(parts.next().unwrap_or(""), positions)?;
+    let normal = match parts.nth(1) {
+        Some(normal) if !normal.is_empty() => Some(resolve_index(normal, normals)?),
+        _ => None,
+    };
+    Ok((position, normal))
+}
+
+/// OBJ indices start at 1, negative ones count back from the latest element
+fn resolve_index(token: &str, count: usize) -> Result<usize> {
+    let index: i64 = token.parse().context("Index isn't a number")?;
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+    if resolved < 0 || resolved >= count as i64 {
+        bail!("Index {} is out of range", index);
+    }
+    Ok(resolved as usize)
+}
+
+/// JSON document and BIN chunk of a .glb
+fn read_glb(bytes: &[u8]) -> Result<(Value, &[u8])> {
+    let u32_at = |offset: usize| -> Result<u32> {
+        let word = bytes
+            .get(offset..offset + 4)
+            .ok_or(anyhow!("glb is truncated"))?;
+        Ok(u32::from_le_bytes(word.try_into().unwrap()))
+    };
+    if bytes.get(0..4) != Some(GLB_MAGIC) {
+        bail!("Not a binary glTF");
+    }
+
+    let mut document = None;
+    let mut bin: &[u8] = &[];
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let length = u32_at(offset)? as usize;
+        let kind = &bytes[offset + 4..offset + 8];
+        let chunk = bytes
+            .get(offset + 8..offset + 8 + length)
+            .ok_or(anyhow!("glb chunk runs past the end of the file"))?;
+        if kind == CHUNK_JSON {
+            document = Some(serde_json::from_slice(chunk).context("Bad glTF JSON")?);
+        } else if kind == CHUNK_BIN {
+            bin = chunk;
+        }
+        offset += 8 + length;
+    }
+    Ok((document.ok_or(anyhow!("glb has no JSON chunk"))?, bin))
+}
+
+fn json_len(value: &Value) -> usize {
+    value.as_array().map_or(0, Vec::len)
+}
+
+fn indices_of(value: &Value) -> Vec<usize> {
+    value
+        .as_array()
+        .map(|values| {
+            values
+                .iter()
+                .filter_map(|v| v.as_u64().map(|v| v as usize))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn floats<const N: usize>(value: &Value) -> Option<[f32; N]> {
+    let values = value.as_array()?;
+    if values.len() != N {
+        return None;
+    }
+    let mut out = [0.0; N];
+    for (out, value) in out.iter_mut().zip(values) {
+        *out = value.as_f64()? as f32;
+    }
+    Some(out)
+}
+
+fn node_transform(node: &Value) -> Result<Mat4> {
+    if !node["matrix"].is_null() {
+        let matrix = floats::<16>(&node["matrix"]).ok_or(anyhow!("Bad node matrix"))?;
+        return Ok(Mat4::from_cols_array(&matrix));
+    }
+    let translation = floats::<3>(&node["translation"]).unwrap_or([0.0; 3]);
+    let rotation = floats::<4>(&node["rotation"]).unwrap_or([0.0, 0.0, 0.0, 1.0]);
+    let scale = floats::<3>(&node["scale"]).unwrap_or([1.0; 3]);
+    Ok(Mat4::from_scale_rotation_translation(
+        Vec3::from(scale),
+        Quat::from_array(rotation),
+        Vec3::from(translation),
+    ))
+}
+
+/// Bytes of every element of an accessor, `size` being the tightly packed element size
+fn accessor_elements<'a>(
+    document: &Value,
+    bin: &'a [u8],
+    index: usize,
+    size: usize,
+) -> Result<(Vec<&'a [u8]>, u64)> {
+    let accessor = &document["accessors"][index];
+    let view = &document["bufferViews"][accessor["bufferView"]
+        .as_u64()
+        .ok_or(anyhow!("Accessor {} has no buffer view", index))?
+        as usize];
+    if view["buffer"].as_u64() != Some(0) {
+        bail!("Only the glb's own buffer is supported");
+    }
+
+    let count = accessor["count"].as_u64().unwrap_or(0) as usize;
+    let stride = view["byteStride"].as_u64().map_or(size, |s| s as usize);
+    let start = view["byteOffset"].as_u64().unwrap_or(0) as usize
+        + accessor["byteOffset"].as_u64().unwrap_or(0) as usize;
+    let elements = (0..count)
+        .map(|i| {
+            let offset = start + i * stride;
+            bin.get(offset..offset + size)
+                .ok_or(anyhow!("Accessor {} runs past the buffer", index))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok((elements, accessor["componentType"].as_u64().unwrap_or(0)))
+}
+
+fn read_vec3s(document: &Value, bin: &[u8], index: usize) -> Result<Vec<Vec3>> {
+    let (elements, component) = accessor_elements(document, bin, index, 12)?;
+    if component != FLOAT as u64 || document["accessors"][index]["type"] != "VEC3" {
+        bail!("Accessor {} isn't a float VEC3", index);
+    }
+    Ok(elements
+        .into_iter()
+        .map(|e| {
+            let f = |i: usize| f32::from_le_bytes(e[i * 4..i * 4 + 4].try_into().unwrap());
+            Vec3::new(f(0), f(1), f(2))
+        })
+        .collect())
+}
+
+fn read_indices(document: &Value, bin: &[u8], index: usize) -> Result<Vec<u32>> {
+    let component = document["accessors"][index]["componentType"]
+        .as_u64()
+        .unwrap_or(0) as u32;
+    let size = match component {
+        UNSIGNED_BYTE => 1,
+        UNSIGNED_SHORT => 2,
+        UNSIGNED_INT => 4,
+        _ => bail!("Accessor {} isn't an index type", index),
+    };
+    let (elements, _) = accessor_elements(document, bin, index, size)?;
+    Ok(elements
+        .into_iter()
+        .map(|e| match size {
+            1 => e[0] as u32,
+            2 => u16::from_le_bytes([e[0], e[1]]) as u32,
+            _ => u32::from_le_bytes(e.try_into().unwrap()),
+        })
+        .collect())
+}
@@ -1,4 +1,8 @@
 pub mod asset_cache;
+pub mod asset_pack;
 pub mod game;
+pub mod mesh;
 pub mod model_graph;
+pub mod selector;
+pub mod spatial;
 pub mod state;
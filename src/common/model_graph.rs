@@ -1,26 +1,48 @@
 use bevy_platform::collections::{HashMap, HashSet};
-use std::collections::VecDeque;
+use std::{collections::VecDeque, ops::DerefMut};
 
 use crate::{
+    common::spatial::{SpatialIndex, part_bounds},
     ecs::{
-        common::{Position, Size},
+        common::{Position, Rotation, Size},
         model::*,
         parts::*,
-        physics::{Anchor, Anchored},
+        physics::{Anchor, Anchored, BodyHandle},
     },
-    physics::AnchorMap,
+    physics::{AnchorMap, PhysicsState},
     utils::graph::is_connected,
 };
 use bevy_ecs::prelude::*;
+use glam::{Mat3, Quat, Vec3};
 use petgraph::{
-    matrix_graph::UnMatrix,
+    graph::{NodeIndex, UnGraph},
     prelude::UnGraphMap,
-    visit::{IntoNodeIdentifiers, NodeIndexable, VisitMap, Visitable},
+    unionfind::UnionFind,
+    visit::{EdgeRef, IntoNodeIdentifiers, VisitMap, Visitable},
 };
+use rapier3d::prelude::{ColliderHandle, RigidBodyHandle, RigidBodyType};
+
+/// Default distance between neighbouring studs
+pub const STUD_PITCH: f32 = 1.0;
+/// Slack when comparing stud grids, in studs
+const ALIGN_EPSILON: f32 = 1e-3;
+/// Slack when checking faces meet, turned parts only land close to each other
+const CONTACT_EPSILON: f32 = 1e-3;
+/// How far off a right angle a turn can be and still count as one
+const ROTATION_EPSILON: f32 = 1e-4;
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+/// Distance between neighbouring studs, parts only snap where their stud grids line up
+pub struct StudPitch(pub f32);
+
+impl Default for StudPitch {
+    fn default() -> Self {
+        StudPitch(STUD_PITCH)
+    }
+}
 
-/// Function for seeing if bricks snap together
-///
-/// TODO: CHECK STUDS
+/// Function for seeing if bricks snap together.
+/// Studs are what each part can connect with, see Part::connecting_studs
 fn touch_check(
     pos_a: &Position,
     size_a: &Size,
@@ -28,6 +50,7 @@ fn touch_check(
     pos_b: &Position,
     size_b: &Size,
     part_b: &StudInfo,
+    pitch: f32,
 ) -> bool {
     let a_min = pos_a.0 - (size_a.0 / 2.0);
     let a_max = pos_a.0 + (size_a.0 / 2.0);
@@ -35,83 +58,161 @@ fn touch_check(
     let b_min = pos_b.0 - (size_b.0 / 2.0);
     let b_max = pos_b.0 + (size_b.0 / 2.0);
 
-    let touch = a_min.x <= b_max.x
-        && a_max.x >= b_min.x
-        && a_min.y <= b_max.y
-        && a_max.y >= b_min.y
-        && a_min.z <= b_max.z
-        && a_max.z >= b_min.z;
+    let touch =
+        a_min.cmple(b_max + CONTACT_EPSILON).all() && a_max.cmpge(b_min - CONTACT_EPSILON).all();
 
     if !touch {
         return false;
     }
 
     // Previous checks collision, this more directly checks if they actually "snap" together.
-    // lol this is a mess
+    // Opposing faces can meet on any axis
+    (0..3).any(|axis| {
+        // b is on the positive side of a
+        let a_b_snap = f32::abs(a_max[axis] - b_min[axis]) < CONTACT_EPSILON
+            && part_a.face(axis, true).connects(part_b.face(axis, false));
+
+        // a is on the positive side of b
+        let b_a_snap = f32::abs(a_min[axis] - b_max[axis]) < CONTACT_EPSILON
+            && part_a.face(axis, false).connects(part_b.face(axis, true));
+
+        (a_b_snap || b_a_snap)
+            && [(axis + 1) % 3, (axis + 2) % 3]
+                .iter()
+                .all(|&u| studs_align(a_min[u], a_max[u], b_min[u], b_max[u], pitch))
+    })
+}
 
-    let a_b_snap = (f32::abs(a_min.y - b_max.y) < f32::EPSILON)
-        && ((part_b.bottom == StudType::Inlet && part_a.top == StudType::Outlet)
-            || (part_b.bottom == StudType::Outlet && part_a.top == StudType::Inlet));
+/// Whether two parts snap together, turned or not
+fn snaps(
+    a: (&Position, &Rotation, &Size, &StudInfo),
+    b: (&Position, &Rotation, &Size, &StudInfo),
+    pitch: f32,
+) -> bool {
+    if a.1.is_near_identity() && b.1.is_near_identity() {
+        touch_check(a.0, a.2, a.3, b.0, b.2, b.3, pitch)
+    } else {
+        turned_touch_check(a, b, pitch)
+    }
+}
 
-    let b_a_snap = (f32::abs(a_max.y - b_min.y) < f32::EPSILON)
-        && ((part_a.bottom == StudType::Inlet && part_b.top == StudType::Outlet)
-            || (part_a.bottom == StudType::Outlet && part_b.top == StudType::Inlet));
-    // Need some check if studs actually align
+/// Function for seeing if turned bricks snap together.
+/// b is moved into a's frame, where both are axis aligned if b is turned a multiple of 90° from a.
+/// Otherwise their stud grids can't line up (or no faces are coplanar) and they don't snap
+fn turned_touch_check(
+    a: (&Position, &Rotation, &Size, &StudInfo),
+    b: (&Position, &Rotation, &Size, &StudInfo),
+    pitch: f32,
+) -> bool {
+    let (pos_a, rot_a, size_a, studs_a) = a;
+    let (pos_b, rot_b, size_b, studs_b) = b;
 
-    return a_b_snap || b_a_snap;
+    let inverse = rot_a.0.inverse();
+    let Some(axes) = right_angle_axes(inverse * rot_b.0) else {
+        return false;
+    };
+
+    // b's size and faces along a's axes
+    let mut size = Vec3::ZERO;
+    let mut studs = StudInfo::FLAT;
+    for (i, &(axis, positive)) in axes.iter().enumerate() {
+        size[axis] = size_b.0[i];
+        *studs.face_mut(axis, positive) = studs_b.face(i, true);
+        *studs.face_mut(axis, !positive) = studs_b.face(i, false);
+    }
+
+    touch_check(
+        &Position(Vec3::ZERO),
+        size_a,
+        studs_a,
+        &Position(inverse * (pos_b.0 - pos_a.0)),
+        &Size(size),
+        &studs,
+        pitch,
+    )
+}
+
+/// Axis and direction each local axis ends up along, None unless the turn is one of the 24 right angled ones
+pub(crate) fn right_angle_axes(rotation: Quat) -> Option<[(usize, bool); 3]> {
+    let matrix = Mat3::from_quat(rotation);
+    let mut axes = [(0, true); 3];
+    for (i, axis) in axes.iter_mut().enumerate() {
+        let column = matrix.col(i);
+        let nearest = column.abs().max_position();
+        if column[nearest].abs() < 1.0 - ROTATION_EPSILON {
+            return None;
+        }
+        *axis = (nearest, column[nearest] > 0.0);
+    }
+    Some(axes)
+}
+
+/// Whether two faces share a stud along one axis of the face.
+/// Studs start at each part's min corner, so both grids have to be a whole number of pitches apart
+fn studs_align(a_min: f32, a_max: f32, b_min: f32, b_max: f32, pitch: f32) -> bool {
+    let offset = (a_min - b_min) / pitch;
+    if (offset - offset.round()).abs() > ALIGN_EPSILON {
+        return false;
+    }
+    // Where each part's last whole stud ends
+    let a_end = a_min + ((a_max - a_min) / pitch + ALIGN_EPSILON).floor() * pitch;
+    let b_end = b_min + ((b_max - b_min) / pitch + ALIGN_EPSILON).floor() * pitch;
+    a_min.max(b_min) + pitch <= a_end.min(b_end) + ALIGN_EPSILON * pitch
 }
 
 /// Given a world with bricks, subdivide into owned and not owned and insert models
 pub fn build_models(
     mut commands: Commands,
     mut anchors: ResMut<AnchorMap>,
+    mut index: ResMut<SpatialIndex>,
+    pitch: Res<StudPitch>,
     parts: Query<QPartWorldInit>,
     is_anchor: Query<&Anchor>,
 ) {
+    for part in &parts {
+        let (min, max) = part_bounds(part.position, part.rotation, part.size);
+        index.insert(part.entity, min, max);
+    }
+
     let part_info: Vec<_> = parts
         .iter()
-        .filter_map(|part| {
-            /*
-               Would be an enhancement to allow for rotated bricks, but for now don't handle
-               Just requires clever mathematics (getting centroids and reverse rotation/translating)
-            */
-            if !part.rotation.is_near_identity() {
-                return None;
-            } else {
-                return Some((
-                    part.entity,
-                    part.position,
-                    part.size,
-                    part.studs,
-                    is_anchor.get(part.entity).is_ok(),
-                ));
-            }
+        .map(|part| {
+            (
+                part.entity,
+                part.position,
+                part.size,
+                part.part.connecting_studs(part.studs, part.size.0),
+                is_anchor.get(part.entity).is_ok(),
+                part.rotation,
+            )
         })
         .collect();
 
-    /*
-
-       Seems like this only supports 2^16 nodes for now. OK
-
-       bool represents anchored edge
-    */
-    let mut graph: UnMatrix<Entity, bool> = UnMatrix::with_capacity(part_info.len());
+    // bool represents anchored edge
+    let mut graph: UnGraph<Entity, bool> = UnGraph::with_capacity(part_info.len(), 0);
     let nodes: Vec<_> = part_info.iter().map(|x| graph.add_node(x.0)).collect();
+    let node_of: HashMap<Entity, usize> = part_info
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (x.0, i))
+        .collect();
 
-    // This goes across entire scene and connects edges where bricks snap together
-    // O(n^2) for now, could use BVH down the line.
-
-    for i in 0..part_info.len() {
-        for j in (0..i).chain((i + 1)..part_info.len()) {
-            let part_a = part_info.get(i).unwrap();
+    // Connects edges where bricks snap together, only parts whose bounds touch are checked
+    for (i, part_a) in part_info.iter().enumerate() {
+        for neighbour in index.neighbours(part_a.0) {
+            // Each pair once
+            let Some(&j) = node_of.get(&neighbour).filter(|&&j| j > i) else {
+                continue;
+            };
             let part_b = part_info.get(j).unwrap();
             let node_a = nodes.get(i).unwrap();
             let node_b = nodes.get(j).unwrap();
 
-            if graph.has_edge(*node_a, *node_b) {
-                continue;
-            }
-            let check = touch_check(part_a.1, part_a.2, part_a.3, part_b.1, part_b.2, part_b.3);
+            let check = snaps(
+                (part_a.1, part_a.5, part_a.2, &part_a.3),
+                (part_b.1, part_b.5, part_b.2, &part_b.3),
+                pitch.0,
+            );
             // We don't add edges to anchor<->anchor because they don't make models !
             if check && !(part_a.4 && part_b.4) {
                 graph.add_edge(*node_a, *node_b, part_a.4 || part_b.4);
@@ -135,7 +236,7 @@ pub fn build_models(
             continue;
         }
 
-        let start_node = graph.from_index(i);
+        let start_node = NodeIndex::new(i);
 
         let mut part_set = HashSet::new();
         // New graph time and anchored bricks!
@@ -153,7 +254,9 @@ pub fn build_models(
                 part_set.insert(a_info.0);
             }
 
-            for (a, b, &anchored) in graph.edges(node) {
+            // Undirected edges always start at node
+            for edge in graph.edges(node) {
+                let (a, b, &anchored) = (edge.source(), edge.target(), edge.weight());
                 let b_info = part_info.get(b.index()).unwrap();
 
                 *dirty.get_mut(a.index()).unwrap() = true;
@@ -241,6 +344,219 @@ pub fn build_models(
     anchors.anchors = anchor_sources;
 }
 
+/// Connect parts added after build_models to the parts they snap onto.
+/// Runs after PhysicsState::add_bricks, so new parts already have a collider (and a body unless they're anchors).
+/// Everything new parts link together ends up in one model, the biggest model already there or a new one
+///     handle_submodel builds a body for. Colliders of the other parts and models move onto its body.
+pub fn join_parts(
+    mut commands: Commands,
+    mut state: ResMut<PhysicsState>,
+    mut anchor_map: ResMut<AnchorMap>,
+    (index, pitch): (Res<SpatialIndex>, Res<StudPitch>),
+    new_parts: Query<Entity, FPartJoin>,
+    parts: Query<QPartJoin>,
+    mut models: Query<(&mut Model, &Children, &BodyHandle)>,
+) {
+    let state = state.deref_mut();
+
+    // Snapped pairs of unanchored parts, and anchors each part snapped onto
+    let mut links = Vec::new();
+    let mut new_anchors: HashMap<Entity, HashSet<Entity>> = HashMap::new();
+    for entity in &new_parts {
+        let Ok(a) = parts.get(entity) else {
+            continue;
+        };
+        let studs_a = a.part.connecting_studs(a.studs, a.size.0);
+        for neighbour in index.neighbours(entity) {
+            let Ok(b) = parts.get(neighbour) else {
+                continue;
+            };
+            let studs_b = b.part.connecting_studs(b.studs, b.size.0);
+            if !snaps(
+                (a.position, a.rotation, a.size, &studs_a),
+                (b.position, b.rotation, b.size, &studs_b),
+                pitch.0,
+            ) {
+                continue;
+            }
+            match (a.anchor, b.anchor) {
+                (true, true) => (),
+                (true, false) => {
+                    new_anchors.entry(b.entity).or_default().insert(a.entity);
+                }
+                (false, true) => {
+                    new_anchors.entry(a.entity).or_default().insert(b.entity);
+                }
+                (false, false) => links.push((a.entity, b.entity)),
+            }
+        }
+    }
+
+    // Linked parts are grouped by their model, or by themselves when they're in none
+    let group = |entity: Entity| {
+        parts
+            .get(entity)
+            .ok()
+            .and_then(|part| part.child_of)
+            .map_or(entity, |child_of| child_of.parent())
+    };
+    let mut keys = Vec::new();
+    let mut key_index: HashMap<Entity, usize> = HashMap::new();
+    for &(a, b) in &links {
+        for key in [group(a), group(b)] {
+            key_index.entry(key).or_insert_with(|| {
+                keys.push(key);
+                keys.len() - 1
+            });
+        }
+    }
+    let mut sets = UnionFind::new(keys.len());
+    for &(a, b) in &links {
+        sets.union(key_index[&group(a)], key_index[&group(b)]);
+    }
+    let mut members: HashMap<usize, Vec<Entity>> = HashMap::new();
+    let mut member_links: HashMap<usize, Vec<(Entity, Entity)>> = HashMap::new();
+    for (i, &key) in keys.iter().enumerate() {
+        members.entry(sets.find(i)).or_default().push(key);
+    }
+    for &(a, b) in &links {
+        let root = sets.find(key_index[&group(a)]);
+        member_links.entry(root).or_default().push((a, b));
+    }
+
+    // Model each group ends up in, and the models spawned for loose parts
+    let mut merged_into: HashMap<Entity, Entity> = HashMap::new();
+    let mut spawned = HashSet::new();
+    for (root, keys) in members {
+        // Parts already sharing a model
+        if keys.len() < 2 {
+            continue;
+        }
+        let target = keys
+            .iter()
+            .copied()
+            .filter(|&key| models.contains(key))
+            .max_by_key(|&key| models.get(key).map_or(0, |(_, children, _)| children.len()));
+
+        let mut graph: UnGraphMap<Entity, ()> = UnGraphMap::new();
+        let mut anchors = HashSet::new();
+        let mut moved = Vec::new();
+        let mut emptied = Vec::new();
+        for &key in keys.iter().filter(|&&key| Some(key) != target) {
+            if let Ok((model, children, _)) = models.get(key) {
+                for node in model.graph.nodes() {
+                    graph.add_node(node);
+                }
+                for (a, b, _) in model.graph.all_edges() {
+                    graph.add_edge(a, b, ());
+                }
+                anchors.extend(model.anchors.iter().copied());
+                moved.extend(children.iter());
+                emptied.push(key);
+            } else if let Ok(part) = parts.get(key) {
+                graph.add_node(key);
+                anchors.extend(part.anchored.iter().flat_map(|anchored| anchored.0.iter()));
+                moved.push(key);
+            }
+        }
+        for (a, b) in member_links.remove(&root).unwrap_or_default() {
+            graph.add_edge(a, b, ());
+        }
+
+        let model = match target {
+            Some(target) => {
+                let Ok((mut model, _, body)) = models.get_mut(target) else {
+                    continue;
+                };
+                for &part in &moved {
+                    let Ok(part) = parts.get(part) else {
+                        continue;
+                    };
+                    reparent(state, part.shape.0, body.0);
+                    if part.child_of.is_none() {
+                        commands.entity(part.entity).remove::<BodyHandle>();
+                    }
+                }
+                for node in graph.nodes() {
+                    model.graph.add_node(node);
+                }
+                for (a, b, _) in graph.all_edges() {
+                    model.graph.add_edge(a, b, ());
+                }
+                model.anchors.extend(anchors);
+                if !model.anchors.is_empty() {
+                    fix_body(state, body.0);
+                }
+                commands.entity(target).add_children(&moved);
+                target
+            }
+            None => {
+                // Anchors picked up this run, handle_submodel makes the body fixed
+                for node in graph.nodes() {
+                    anchors.extend(new_anchors.get(&node).into_iter().flatten());
+                }
+                for &part in &moved {
+                    commands.entity(part).remove::<BodyHandle>();
+                }
+                let model = commands
+                    .spawn(Model {
+                        graph,
+                        anchors,
+                        dirty: false,
+                    })
+                    .add_children(&moved)
+                    .id();
+                spawned.insert(model);
+                model
+            }
+        };
+        // After their children moved, so they aren't despawned with them
+        for key in emptied {
+            commands.entity(key).despawn();
+        }
+        for key in keys {
+            merged_into.insert(key, model);
+        }
+    }
+
+    for (entity, added) in new_anchors {
+        let Ok(part) = parts.get(entity) else {
+            continue;
+        };
+        let mut sources = part
+            .anchored
+            .map(|anchored| anchored.0.clone())
+            .unwrap_or_default();
+        if added.is_subset(&sources) {
+            continue;
+        }
+        sources.extend(added.iter().copied());
+        commands.entity(entity).insert(Anchored(sources));
+        for &anchor in &added {
+            anchor_map.anchors.entry(anchor).or_default().insert(entity);
+        }
+
+        let model = merged_into
+            .get(&group(entity))
+            .copied()
+            .or(part.child_of.map(|child_of| child_of.parent()));
+        match model {
+            Some(model) if spawned.contains(&model) => (),
+            Some(model) => {
+                if let Ok((mut model, _, body)) = models.get_mut(model) {
+                    model.anchors.extend(added);
+                    fix_body(state, body.0);
+                }
+            }
+            None => {
+                if let Some(body) = part.body {
+                    fix_body(state, body.0);
+                }
+            }
+        }
+    }
+}
+
 pub fn handle_part_of_model_deletion(
     trigger: Trigger<OnRemove, ChildOf>,
     child_of: Query<&ChildOf>,
@@ -322,3 +638,28 @@ pub fn handle_model_transform(mut commands: Commands, models: Query<QModel, Chan
         }
     }
 }
+
+/// Move a collider onto another body, keeping it where it is in the world
+fn reparent(state: &mut PhysicsState, shape: ColliderHandle, body: RigidBodyHandle) {
+    let (Some(collider), Some(parent)) = (state.colliders.get(shape), state.rigid_bodies.get(body))
+    else {
+        return;
+    };
+    let world = *collider.position();
+    let relative = parent.position().inverse() * world;
+
+    state
+        .colliders
+        .set_parent(shape, Some(body), &mut state.rigid_bodies);
+    if let Some(collider) = state.colliders.get_mut(shape) {
+        collider.set_position_wrt_parent(relative);
+        collider.set_position(world);
+    }
+}
+
+/// Bodies holding anchored parts don't move
+fn fix_body(state: &mut PhysicsState, body: RigidBodyHandle) {
+    if let Some(body) = state.rigid_bodies.get_mut(body) {
+        body.set_body_type(RigidBodyType::Fixed, true);
+    }
+}
@@ -0,0 +1,125 @@
+/*
+    Finding parts without marker components.
+
+    A Selector is a list of conditions on a part's Name, Tags, Color, Part kind and bounds,
+    a part has to meet every one of them. `select` looks parts up once on a World,
+    `iter` filters a Query<QSelect> so systems get matching parts live every run.
+*/
+use bevy_ecs::{prelude::*, query::QueryData};
+use glam::Vec3;
+
+use crate::{
+    common::spatial::part_bounds,
+    ecs::{
+        common::{Color, Position, Rotation, Size, Tags},
+        parts::Part,
+    },
+};
+
+#[derive(QueryData)]
+#[query_data(derive(Debug))]
+pub struct QSelect {
+    pub entity: Entity,
+    pub part: &'static Part,
+    pub position: &'static Position,
+    pub rotation: &'static Rotation,
+    pub size: &'static Size,
+    pub color: &'static Color,
+    pub name: Option<&'static Name>,
+    pub tags: Option<&'static Tags>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Selector {
+    pub name: Option<String>,
+    /// Every one of these has to be on the part
+    pub tags: Vec<String>,
+    pub color: Option<[u8; 4]>,
+    pub part: Option<Part>,
+    /// Min and max corners, parts whose bounds overlap it match
+    pub region: Option<(Vec3, Vec3)>,
+}
+
+impl Selector {
+    pub fn new() -> Self {
+        Selector::default()
+    }
+
+    pub fn named(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn tagged(mut self, tag: &str) -> Self {
+        self.tags.push(tag.to_string());
+        self
+    }
+
+    pub fn colored(mut self, color: [u8; 4]) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    pub fn part(mut self, part: Part) -> Self {
+        self.part = Some(part);
+        self
+    }
+
+    pub fn within(mut self, min: Vec3, max: Vec3) -> Self {
+        self.region = Some((min.min(max), min.max(max)));
+        self
+    }
+
+    pub fn matches(&self, item: &QSelectItem) -> bool {
+        if let Some(name) = &self.name
+            && item.name.is_none_or(|n| n.as_str() != name)
+        {
+            return false;
+        }
+        if !self
+            .tags
+            .iter()
+            .all(|tag| item.tags.is_some_and(|tags| tags.has(tag)))
+        {
+            return false;
+        }
+        if self.color.is_some_and(|color| color != item.color.0)
+            || self.part.is_some_and(|part| part != *item.part)
+        {
+            return false;
+        }
+        if let Some((min, max)) = self.region {
+            let (part_min, part_max) = part_bounds(item.position, item.rotation, item.size);
+            if part_min.cmpgt(max).any() || part_max.cmplt(min).any() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Matching parts of a query, for systems
+    pub fn iter<'a, 'w, 's>(
+        &'a self,
+        query: &'a Query<'w, 's, QSelect>,
+    ) -> impl Iterator<Item = QSelectItem<'a>> + 'a {
+        query.iter().filter(|item| self.matches(item))
+    }
+
+    /// Every matching part in the world, in no particular order
+    pub fn select(&self, world: &mut World) -> Vec<Entity> {
+        world
+            .query::<QSelect>()
+            .iter(world)
+            .filter(|item| self.matches(item))
+            .map(|item| item.entity)
+            .collect()
+    }
+
+    pub fn first(&self, world: &mut World) -> Option<Entity> {
+        world
+            .query::<QSelect>()
+            .iter(world)
+            .find(|item| self.matches(item))
+            .map(|item| item.entity)
+    }
+}
@@ -0,0 +1,185 @@
+/*
+    Uniform grid over part bounds.
+
+    Every part's turned bounds are bucketed into cubic cells a few studs wide, so finding what's near
+    a box only looks at the cells it covers instead of the whole scene. build_models fills it on load,
+    SpatialIndex::update keeps it in step with moved, resized and deleted parts afterwards.
+    Parts spanning a lot of cells (baseplates) are kept on the side and checked by every query.
+*/
+use bevy_ecs::prelude::*;
+use bevy_platform::collections::HashMap;
+use glam::{IVec3, Mat3, Vec3};
+
+use crate::ecs::{
+    common::{Position, Rotation, Size},
+    parts::{FPartMove, Part},
+};
+
+/// Cell width in studs, a default brick's long side
+pub const CELL_SIZE: f32 = 4.0;
+/// Parts covering more cells than this skip the grid
+const MAX_CELLS: i64 = 512;
+/// Bounds are padded so parts meeting on a cell border land in both cells,
+/// and turned parts a rounding error apart still touch
+const PADDING: f32 = 1e-3;
+
+#[derive(Resource, Debug)]
+pub struct SpatialIndex {
+    cell_size: f32,
+    cells: HashMap<IVec3, Vec<Entity>>,
+    /// Min and max corners of every indexed part
+    bounds: HashMap<Entity, (Vec3, Vec3)>,
+    oversized: Vec<Entity>,
+}
+
+impl Default for SpatialIndex {
+    fn default() -> Self {
+        SpatialIndex::new(CELL_SIZE)
+    }
+}
+
+impl SpatialIndex {
+    pub fn new(cell_size: f32) -> Self {
+        SpatialIndex {
+            cell_size,
+            cells: HashMap::new(),
+            bounds: HashMap::new(),
+            oversized: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bounds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bounds.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.bounds.clear();
+        self.oversized.clear();
+    }
+
+    pub fn bounds(&self, entity: Entity) -> Option<(Vec3, Vec3)> {
+        self.bounds.get(&entity).copied()
+    }
+
+    /// Add a part or move it to new bounds
+    pub fn insert(&mut self, entity: Entity, min: Vec3, max: Vec3) {
+        if let Some(old) = self.bounds.get(&entity).copied() {
+            if old == (min, max) {
+                return;
+            }
+            self.remove(entity);
+        }
+        self.bounds.insert(entity, (min, max));
+        match self.cell_range(min, max) {
+            Some((low, high)) => {
+                for cell in cells(low, high) {
+                    self.cells.entry(cell).or_default().push(entity);
+                }
+            }
+            None => self.oversized.push(entity),
+        }
+    }
+
+    pub fn remove(&mut self, entity: Entity) -> bool {
+        let Some((min, max)) = self.bounds.remove(&entity) else {
+            return false;
+        };
+        match self.cell_range(min, max) {
+            Some((low, high)) => {
+                for cell in cells(low, high) {
+                    if let Some(list) = self.cells.get_mut(&cell) {
+                        list.retain(|&e| e != entity);
+                        if list.is_empty() {
+                            self.cells.remove(&cell);
+                        }
+                    }
+                }
+            }
+            None => self.oversized.retain(|&e| e != entity),
+        }
+        true
+    }
+
+    /// Parts whose bounds overlap or touch the box, sorted
+    pub fn query(&self, min: Vec3, max: Vec3) -> Vec<Entity> {
+        let overlaps = |entity: &Entity| {
+            let (part_min, part_max) = self.bounds[entity];
+            part_min.cmple(max + PADDING).all() && part_max.cmpge(min - PADDING).all()
+        };
+
+        let mut found: Vec<Entity> = match self.cell_range(min, max) {
+            Some((low, high)) => cells(low, high)
+                .filter_map(|cell| self.cells.get(&cell))
+                .flatten()
+                .copied()
+                .collect(),
+            // Big boxes are cheaper to test part by part
+            None => self.bounds.keys().copied().collect(),
+        };
+        found.extend(&self.oversized);
+        found.sort_unstable();
+        found.dedup();
+        found.retain(overlaps);
+        found
+    }
+
+    /// Parts containing a point
+    pub fn query_point(&self, point: Vec3) -> Vec<Entity> {
+        self.query(point, point)
+    }
+
+    /// Parts touching an indexed part, not including itself
+    pub fn neighbours(&self, entity: Entity) -> Vec<Entity> {
+        let Some((min, max)) = self.bounds(entity) else {
+            return Vec::new();
+        };
+        let mut found = self.query(min, max);
+        found.retain(|&e| e != entity);
+        found
+    }
+
+    /// Keep indexed parts in step with the world
+    pub fn update(
+        mut index: ResMut<SpatialIndex>,
+        parts: Query<(Entity, &Position, &Rotation, &Size), FPartMove>,
+        mut removed: RemovedComponents<Part>,
+    ) {
+        for entity in removed.read() {
+            index.remove(entity);
+        }
+        for (entity, position, rotation, size) in parts {
+            let (min, max) = part_bounds(position, rotation, size);
+            index.insert(entity, min, max);
+        }
+    }
+
+    /// Cells covered by a box, None when it covers too many
+    fn cell_range(&self, min: Vec3, max: Vec3) -> Option<(IVec3, IVec3)> {
+        let low = ((min - PADDING) / self.cell_size).floor().as_ivec3();
+        let high = ((max + PADDING) / self.cell_size).floor().as_ivec3();
+        let count = (high - low + IVec3::ONE).as_i64vec3();
+        (count.x * count.y * count.z <= MAX_CELLS).then_some((low, high))
+    }
+}
+
+fn cells(low: IVec3, high: IVec3) -> impl Iterator<Item = IVec3> {
+    (low.x..=high.x).flat_map(move |x| {
+        (low.y..=high.y).flat_map(move |y| (low.z..=high.z).map(move |z| IVec3::new(x, y, z)))
+    })
+}
+
+/// Axis aligned box around a turned part
+pub fn part_bounds(position: &Position, rotation: &Rotation, size: &Size) -> (Vec3, Vec3) {
+    let rotation = Mat3::from_quat(rotation.0);
+    let half = Mat3::from_cols(
+        rotation.x_axis.abs(),
+        rotation.y_axis.abs(),
+        rotation.z_axis.abs(),
+    ) * (size.0 / 2.0);
+    (position.0 - half, position.0 + half)
+}
@@ -1,6 +1,7 @@
 use bevy_derive::{Deref, DerefMut};
 use bevy_ecs::prelude::*;
 use glam::{Quat, Vec3};
+use std::collections::BTreeSet;
 
 #[derive(Component, Debug, Deref, DerefMut)]
 pub struct Position(pub Vec3);
@@ -37,3 +38,22 @@ impl Default for Color {
         Color([128, 128, 128, 255])
     }
 }
+
+/// Free-form labels for finding parts, see common::selector.
+/// Names use bevy's Name component
+#[derive(Component, Debug, Default, Clone, PartialEq, Deref, DerefMut)]
+pub struct Tags(pub BTreeSet<String>);
+
+impl Tags {
+    pub fn new<I, S>(tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Tags(tags.into_iter().map(Into::into).collect())
+    }
+
+    pub fn has(&self, tag: &str) -> bool {
+        self.0.contains(tag)
+    }
+}
@@ -0,0 +1,64 @@
+use bevy_ecs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// What a part is made of, decides both how it looks and how it collides.
+/// Fits in a nibble like StudType
+pub enum Material {
+    #[default]
+    Plastic = 0x00,
+    Wood = 0x01,
+    Metal = 0x02,
+    /// Barely any friction
+    Ice = 0x03,
+    Glass = 0x04,
+    /// Glows in its own color, ignoring the light
+    Neon = 0x05,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaterialProperties {
+    pub friction: f32,
+    pub restitution: f32,
+    /// Relative to plastic, which has rapier's default of 1
+    pub density: f32,
+    /// 0 is a mirror, 1 has no highlight to speak of
+    pub roughness: f32,
+    pub specular: f32,
+    /// How much of the color is drawn without lighting
+    pub emissive: f32,
+}
+
+impl Material {
+    pub const ALL: [Material; 6] = [
+        Material::Plastic,
+        Material::Wood,
+        Material::Metal,
+        Material::Ice,
+        Material::Glass,
+        Material::Neon,
+    ];
+
+    pub fn from_u8(value: u8) -> Option<Material> {
+        Material::ALL.get(value as usize).copied()
+    }
+
+    pub fn properties(&self) -> MaterialProperties {
+        let (friction, restitution, density, roughness, specular, emissive) = match self {
+            Material::Plastic => (0.5, 0.4, 1.0, 0.25, 1.0, 0.0),
+            Material::Wood => (0.7, 0.3, 0.7, 0.7, 0.2, 0.0),
+            Material::Metal => (0.4, 0.2, 7.8, 0.15, 1.5, 0.0),
+            Material::Ice => (0.02, 0.1, 0.9, 0.1, 1.2, 0.0),
+            Material::Glass => (0.4, 0.3, 2.5, 0.05, 2.0, 0.0),
+            Material::Neon => (0.5, 0.4, 1.0, 0.5, 0.0, 1.0),
+        };
+        MaterialProperties {
+            friction,
+            restitution,
+            density,
+            roughness,
+            specular,
+            emissive,
+        }
+    }
+}
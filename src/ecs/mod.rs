@@ -1,4 +1,5 @@
 pub mod common;
+pub mod material;
 pub mod model;
 pub mod parts;
 pub mod physics;
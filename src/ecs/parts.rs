@@ -1,34 +1,160 @@
-use crate::ecs::{common::*, physics::*, render::*};
+use crate::ecs::{common::*, material::Material, physics::*, render::*};
 use bevy_ecs::query::QueryData;
 use bevy_ecs::{prelude::*, query::QueryFilter};
+use glam::{Quat, Vec3};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::FRAC_PI_2;
 
-#[derive(Component, Debug, Default, PartialEq, Eq)]
-#[require(StudInfo, Position, Rotation, Color, Size, BufferIndex, Physical)]
-// Encompasses Brick, Wedge, Ball and Mesh
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[require(
+    StudInfo,
+    Material,
+    Position,
+    Rotation,
+    Color,
+    Size,
+    BufferIndex,
+    Physical
+)]
+// Encompasses Brick, Wedge, Ball, Mesh and Cylinder
 pub enum Part {
     #[default]
     Brick,
-    // TODO ----
+    /// Brick with its top sloping down towards Z-, only the bottom has studs
     Wedge,
+    /// Sphere as wide as the smallest side of its Size, never snaps to anything
     Ball,
+    /// Drawn with the mesh asset in its MeshRef
     Mesh,
+    /// Round along the longest side of its Size, only the caps snap
+    Cylinder,
 }
 
-#[derive(Debug, PartialEq, Eq)]
-/// Handling flat, outlet and inlet for now.
-/// In theory should support 16 possible types
+impl Part {
+    /// Studs this part can actually snap with.
+    /// Only a wedge's front and bottom are whole faces, and only a cylinder's caps are flat
+    pub fn connecting_studs(&self, studs: &StudInfo, size: Vec3) -> StudInfo {
+        match self {
+            Part::Wedge => StudInfo {
+                front: studs.front,
+                bottom: studs.bottom,
+                ..StudInfo::FLAT
+            },
+            Part::Ball => StudInfo::FLAT,
+            Part::Cylinder => {
+                let axis = cylinder_axis(size);
+                let mut caps = StudInfo::FLAT;
+                for positive in [true, false] {
+                    *caps.face_mut(axis, positive) = studs.face(axis, positive);
+                }
+                caps
+            }
+            _ => *studs,
+        }
+    }
+
+    /// Size the part is actually drawn and simulated at, balls and cylinders stay round
+    pub fn shape_size(&self, size: Vec3) -> Vec3 {
+        match self {
+            Part::Ball => Vec3::splat(size.min_element()),
+            Part::Cylinder => {
+                let axis = cylinder_axis(size);
+                let diameter = (0..3)
+                    .filter(|&i| i != axis)
+                    .map(|i| size[i])
+                    .fold(f32::MAX, f32::min);
+                let mut shape = Vec3::splat(diameter);
+                shape[axis] = size[axis];
+                shape
+            }
+            _ => size,
+        }
+    }
+
+    /// Turns the part's mesh, which is built around Y, onto the axis its Size asks for
+    pub fn shape_rotation(&self, size: Vec3) -> Quat {
+        match (self, cylinder_axis(size)) {
+            (Part::Cylinder, 0) => Quat::from_rotation_z(-FRAC_PI_2),
+            (Part::Cylinder, 2) => Quat::from_rotation_x(FRAC_PI_2),
+            _ => Quat::IDENTITY,
+        }
+    }
+}
+
+/// Index of the longest side, Y wins ties so equal sides stand upright
+pub fn cylinder_axis(size: Vec3) -> usize {
+    if size.y >= size.x && size.y >= size.z {
+        1
+    } else if size.x >= size.z {
+        0
+    } else {
+        2
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Surface of a face, the value is its layer in the stud texture.
+/// Fits in a nibble, 0x0A-0x0F are free for new types (their layers are blank)
 pub enum StudType {
     Flat = 0x00,
     Outlet = 0x01,
     Inlet = 0x02,
+    /// Takes both outlets and inlets
+    Universal = 0x03,
+    Weld = 0x04,
+    Glue = 0x05,
+    Hinge = 0x06,
+    Motor = 0x07,
+    SteppingMotor = 0x08,
+    /// Flat without outlines
+    Smooth = 0x09,
+}
+
+impl StudType {
+    pub const ALL: [StudType; 10] = [
+        StudType::Flat,
+        StudType::Outlet,
+        StudType::Inlet,
+        StudType::Universal,
+        StudType::Weld,
+        StudType::Glue,
+        StudType::Hinge,
+        StudType::Motor,
+        StudType::SteppingMotor,
+        StudType::Smooth,
+    ];
+
+    pub fn from_u8(value: u8) -> Option<StudType> {
+        StudType::ALL.get(value as usize).copied()
+    }
+
+    /// Whether two touching faces snap together.
+    /// Weld and glue stick to any face, hinges and motors are joints so they never do
+    pub fn connects(self, other: StudType) -> bool {
+        use StudType::*;
+
+        match (self, other) {
+            (Hinge | Motor | SteppingMotor, _) | (_, Hinge | Motor | SteppingMotor) => false,
+            (Weld | Glue, _) | (_, Weld | Glue) => true,
+            (Outlet, Inlet | Universal) | (Inlet | Universal, Outlet) => true,
+            (Inlet, Universal) | (Universal, Inlet | Universal) => true,
+            _ => false,
+        }
+    }
 }
 
-#[derive(Component, Debug)]
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[require(Position, Rotation, Color, Size, BufferIndex, RenderMode, Physical)]
-/// Component for studtype for bottom and top (also hints at being a Brick)
+#[serde(default)]
+/// Component for the studtype of each face (also hints at being a Brick).
+/// Front is Z+ and right is X+, like the faces of the brick mesh
 pub struct StudInfo {
     pub top: StudType,
     pub bottom: StudType,
+    pub front: StudType,
+    pub back: StudType,
+    pub left: StudType,
+    pub right: StudType,
 }
 
 impl Default for StudInfo {
@@ -36,6 +162,82 @@ impl Default for StudInfo {
         StudInfo {
             top: StudType::Outlet,
             bottom: StudType::Inlet,
+            ..StudInfo::FLAT
+        }
+    }
+}
+
+impl StudInfo {
+    pub const FLAT: StudInfo = StudInfo {
+        top: StudType::Flat,
+        bottom: StudType::Flat,
+        front: StudType::Flat,
+        back: StudType::Flat,
+        left: StudType::Flat,
+        right: StudType::Flat,
+    };
+
+    /// Face whose normal points along +axis (or -axis), axis is 0 for X, 1 for Y and 2 for Z
+    pub fn face(&self, axis: usize, positive: bool) -> StudType {
+        match (axis, positive) {
+            (0, true) => self.right,
+            (0, false) => self.left,
+            (1, true) => self.top,
+            (1, false) => self.bottom,
+            (2, true) => self.front,
+            _ => self.back,
+        }
+    }
+
+    pub fn face_mut(&mut self, axis: usize, positive: bool) -> &mut StudType {
+        match (axis, positive) {
+            (0, true) => &mut self.right,
+            (0, false) => &mut self.left,
+            (1, true) => &mut self.top,
+            (1, false) => &mut self.bottom,
+            (2, true) => &mut self.front,
+            _ => &mut self.back,
+        }
+    }
+
+    /// Faces of the part after turning it by an axis-aligned rotation
+    pub fn rotated(&self, rotation: Quat) -> StudInfo {
+        let mut rotated = StudInfo::FLAT;
+        for axis in 0..3 {
+            for positive in [true, false] {
+                let mut normal = Vec3::ZERO;
+                normal[axis] = if positive { 1.0 } else { -1.0 };
+                let turned = (rotation * normal).round();
+                let to = (0..3).find(|&i| turned[i] != 0.0).unwrap_or(axis);
+                *rotated.face_mut(to, turned[to] > 0.0) = self.face(axis, positive);
+            }
+        }
+        rotated
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MeshCollider {
+    /// Solid, works for any body
+    #[default]
+    ConvexHull,
+    /// Follows concave shapes exactly, best kept to anchored parts
+    TriMesh,
+}
+
+#[derive(Component, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[require(Part = Part::Mesh)]
+/// Mesh asset a Part::Mesh is drawn and collides with, stretched to the part's Size
+pub struct MeshRef {
+    pub name: String,
+    pub collider: MeshCollider,
+}
+
+impl MeshRef {
+    pub fn new(name: &str) -> Self {
+        MeshRef {
+            name: name.to_string(),
+            collider: MeshCollider::default(),
         }
     }
 }
@@ -52,6 +254,7 @@ pub struct QPart {
     pub entity: Entity,
     pub part: &'static Part,
     pub studs: &'static StudInfo,
+    pub material: &'static Material,
     pub position: &'static Position,
     pub rotation: &'static Rotation,
     pub size: &'static Size,
@@ -63,6 +266,21 @@ pub struct QPart {
 #[derive(QueryData)]
 #[query_data(derive(Debug))]
 pub struct QPartWorldInit {
+    pub entity: Entity,
+    pub part: &'static Part,
+    pub studs: &'static StudInfo,
+    pub material: &'static Material,
+    pub position: &'static Position,
+    pub rotation: &'static Rotation,
+    pub size: &'static Size,
+    pub physical: &'static Physical,
+    pub mesh: Option<&'static MeshRef>,
+}
+
+#[derive(QueryData)]
+#[query_data(derive(Debug))]
+/// What joining a part onto its neighbours needs to know about them
+pub struct QPartJoin {
     pub entity: Entity,
     pub part: &'static Part,
     pub studs: &'static StudInfo,
@@ -70,6 +288,11 @@ pub struct QPartWorldInit {
     pub rotation: &'static Rotation,
     pub size: &'static Size,
     pub physical: &'static Physical,
+    pub shape: &'static ShapeHandle,
+    pub body: Option<&'static BodyHandle>,
+    pub anchor: Has<Anchor>,
+    pub anchored: Option<&'static Anchored>,
+    pub child_of: Option<&'static ChildOf>,
 }
 
 #[derive(QueryData)]
@@ -79,6 +302,7 @@ pub struct QPartRenderUpdate {
     pub entity: Entity,
     pub part: &'static Part,
     pub studs: &'static StudInfo,
+    pub material: &'static Material,
     pub position: &'static Position,
     pub rotation: &'static Rotation,
     pub size: &'static Size,
@@ -110,9 +334,25 @@ pub struct FPartChange {
     _c: With<Part>,
     _or: Or<(
         Changed<StudInfo>,
+        Changed<Material>,
         Changed<Position>,
         Changed<Rotation>,
         Changed<Size>,
         Changed<Color>,
     )>,
 }
+
+#[derive(QueryFilter)]
+/// Parts added since the last run that aren't connected to anything yet
+pub struct FPartJoin {
+    _c: Added<Part>,
+    _s: With<ShapeHandle>,
+    _u: (Without<ChildOf>, Without<Anchored>),
+}
+
+#[derive(QueryFilter)]
+/// Parts whose bounds may have changed
+pub struct FPartMove {
+    _c: With<Part>,
+    _or: Or<(Changed<Position>, Changed<Rotation>, Changed<Size>)>,
+}
@@ -3,7 +3,11 @@ use bevy_ecs::{prelude::*, query::QueryFilter};
 use bevy_platform::collections::HashSet;
 use rapier3d::prelude::*;
 
-use crate::ecs::common::{Position, Rotation, Size};
+use crate::ecs::{
+    common::{Position, Rotation, Size},
+    material::Material,
+    parts::{MeshRef, Part},
+};
 
 #[derive(Component, Debug, Default)]
 pub struct Physical;
@@ -30,10 +34,13 @@ pub struct ShapeHandle(pub ColliderHandle);
 #[query_data(mutable, derive(Debug))]
 pub struct QPhysics {
     pub entity: Entity,
+    pub part: &'static Part,
+    pub material: &'static Material,
     pub position: &'static Position,
     pub rotation: &'static Rotation,
     pub size: &'static Size,
     pub physical: &'static Physical,
+    pub mesh: Option<&'static MeshRef>,
 }
 
 /*  ---------------------
@@ -3,4 +3,5 @@ pub mod common;
 pub mod ecs;
 pub mod physics;
 pub mod render;
+pub mod scene;
 pub mod utils;
@@ -3,4 +3,5 @@ pub use physics_state::*;
 
 mod deletion;
 mod setup;
+pub(crate) use setup::model_body;
 mod transform;
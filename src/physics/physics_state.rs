@@ -9,7 +9,12 @@ use crate::physics::transform::{
     handle_subpart,
 };
 use crate::{
-    common::state::*,
+    common::{
+        asset_cache::AssetCache,
+        model_graph::{StudPitch, join_parts},
+        spatial::SpatialIndex,
+        state::*,
+    },
     ecs::{parts::*, physics::*},
     render::debug_draw::*,
 };
@@ -54,6 +59,8 @@ impl State<PhysicsState> for PhysicsState {
             anchors: HashMap::new(),
             delete_queue: VecDeque::new(),
         });
+        world.init_resource::<SpatialIndex>();
+        world.init_resource::<StudPitch>();
         world.add_observer(handle_shape_removal);
         world.add_observer(handle_body_removal);
     }
@@ -103,6 +110,8 @@ impl PhysicsState {
             Self::step,
             Self::write_debug.run_if(move || -> bool { debug_draw }),
             Self::add_bricks,
+            SpatialIndex::update,
+            join_parts,
             handle_subpart,
             handle_submodel,
             handle_anchor_queue,
@@ -142,6 +151,7 @@ impl PhysicsState {
     pub fn add_bricks(
         mut commands: Commands,
         mut state: ResMut<PhysicsState>,
+        assets: Option<Res<AssetCache>>,
         new_bricks: Query<QPartWorldInit, (FPartAdd, Without<ShapeHandle>, Without<BodyHandle>)>,
         is_anchor: Query<&Anchor>,
     ) {
@@ -150,14 +160,19 @@ impl PhysicsState {
         let rigid_bodies = &mut state.rigid_bodies;
 
         for brick in new_bricks {
-            let size = brick.size.0 / 2.0;
             let pos = brick.position;
             let (yaw, pitch, roll) = {
                 let (axis, angle) = brick.rotation.to_axis_angle();
                 (axis.x * angle, axis.y * angle, axis.z * angle)
             };
 
-            let shape_builder = ColliderBuilder::cuboid(size.x, size.y, size.z).restitution(0.4);
+            let shape_builder = part_collider(
+                *brick.part,
+                *brick.material,
+                brick.size.0,
+                brick.mesh,
+                assets.as_deref(),
+            );
 
             if is_anchor.get(brick.entity).is_ok() {
                 let shape = shape_builder
@@ -168,9 +183,7 @@ impl PhysicsState {
                 let handle = colliders.insert(shape);
                 commands.entity(brick.entity).insert(ShapeHandle(handle));
             } else {
-                let shape = ColliderBuilder::cuboid(size.x, size.y, size.z)
-                    .restitution(0.4)
-                    .build();
+                let shape = shape_builder.build();
 
                 let body = RigidBodyBuilder::dynamic()
                     .translation(vector![pos.x, pos.y, pos.z])
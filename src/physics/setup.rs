@@ -1,9 +1,14 @@
 use std::ops::DerefMut;
 
 use crate::{
+    common::{
+        asset_cache::{AssetCache, AssetKind, Handle},
+        mesh::Mesh,
+    },
     ecs::{
+        material::Material,
         model::{FModelAdd, QModel},
-        parts::FPartAdd,
+        parts::{FPartAdd, MeshCollider, MeshRef, Part, cylinder_axis},
         physics::{
             Anchor, BodyHandle, FAnchored, FUnanchored, QPhysics, QPhysicsReadOnlyItem, ShapeHandle,
         },
@@ -11,7 +16,9 @@ use crate::{
     physics::physics_state::PhysicsState,
 };
 use bevy_ecs::prelude::*;
+use glam::{Quat, Vec3};
 use rapier3d::prelude::*;
+use tracing::warn;
 
 /// Build physics information for parts not under a model.
 /// There are three types of parts we need to worry about.
@@ -24,71 +31,205 @@ use rapier3d::prelude::*;
 pub fn setup_parts(
     mut commands: Commands,
     mut state: ResMut<PhysicsState>,
+    assets: Option<Res<AssetCache>>,
     anchored: Query<QPhysics, (Without<ChildOf>, FAnchored, FPartAdd)>,
     unanchored: Query<QPhysics, (Without<ChildOf>, FUnanchored, FPartAdd)>,
     anchor: Query<QPhysics, (Without<ChildOf>, With<Anchor>, FPartAdd)>,
 ) {
     let state = state.deref_mut();
+    let assets = assets.as_deref();
 
     for part in &anchored {
-        build_body(&mut commands, state, part, RigidBodyBuilder::fixed());
+        build_body(
+            &mut commands,
+            state,
+            assets,
+            part,
+            RigidBodyBuilder::fixed(),
+        );
     }
 
     for part in &unanchored {
-        build_body(&mut commands, state, part, RigidBodyBuilder::dynamic());
+        build_body(
+            &mut commands,
+            state,
+            assets,
+            part,
+            RigidBodyBuilder::dynamic(),
+        );
     }
 
     for part in &anchor {
-        build_shape(&mut commands, state, part);
+        build_shape(&mut commands, state, assets, part);
     }
 }
 
 pub fn setup_models(
     mut commands: Commands,
     mut state: ResMut<PhysicsState>,
-    models: Query<QModel, FModelAdd>,
+    assets: Option<Res<AssetCache>>,
+    models: Query<QModel, (FModelAdd, Without<BodyHandle>)>,
     parts: Query<QPhysics>,
     children: Query<&Children>,
 ) -> Result<()> {
     let state = state.deref_mut();
 
     for item in models {
-        let mut shapes = Vec::new();
+        let mut child_parts = Vec::new();
         for &child_id in children.get(item.entity)? {
-            let child = parts.get(child_id)?;
-            let shape = get_shape(&child, true);
-            shapes.push((child_id, shape));
-        }
-        let body = if item.model.anchors.is_empty() {
-            RigidBodyBuilder::dynamic()
-        } else {
-            RigidBodyBuilder::fixed()
+            child_parts.push(parts.get(child_id)?);
         }
-        .user_data(item.entity.to_bits() as u128)
-        .build();
-
-        let body_handle = state.rigid_bodies.insert(body);
-        for (child, shape) in shapes {
-            let shape_handle =
-                state
-                    .colliders
-                    .insert_with_parent(shape, body_handle, &mut state.rigid_bodies);
-            commands.entity(child).insert(ShapeHandle(shape_handle));
+        let (body, shapes) = model_body(
+            state,
+            assets.as_deref(),
+            item.entity,
+            &child_parts,
+            !item.model.anchors.is_empty(),
+        );
+        for (part, shape) in child_parts.iter().zip(shapes) {
+            commands.entity(part.entity).insert(shape);
         }
-        commands.entity(item.entity).insert(BodyHandle(body_handle));
+        commands.entity(item.entity).insert(body);
     }
     Ok(())
 }
 
+/// Insert a rigid body for a model, holding a collider for each of its parts.
+/// Body sits at the origin so colliders keep their world transform.
+pub(crate) fn model_body(
+    state: &mut PhysicsState,
+    assets: Option<&AssetCache>,
+    model: Entity,
+    parts: &[QPhysicsReadOnlyItem],
+    fixed: bool,
+) -> (BodyHandle, Vec<ShapeHandle>) {
+    let body = if fixed {
+        RigidBodyBuilder::fixed()
+    } else {
+        RigidBodyBuilder::dynamic()
+    }
+    .user_data(model.to_bits() as u128)
+    .build();
+
+    let body_handle = state.rigid_bodies.insert(body);
+    let shapes = parts
+        .iter()
+        .map(|part| {
+            let shape = get_shape(part, assets, true);
+            ShapeHandle(state.colliders.insert_with_parent(
+                shape,
+                body_handle,
+                &mut state.rigid_bodies,
+            ))
+        })
+        .collect();
+    (BodyHandle(body_handle), shapes)
+}
+
+/// Collider matching a part's shape, size and material, not yet placed.
+pub(crate) fn part_collider(
+    part: Part,
+    material: Material,
+    size: Vec3,
+    mesh: Option<&MeshRef>,
+    assets: Option<&AssetCache>,
+) -> ColliderBuilder {
+    let properties = material.properties();
+    part_shape(part, size, mesh, assets)
+        .friction(properties.friction)
+        .restitution(properties.restitution)
+        .density(properties.density)
+}
+
 /*
     Helper functions
 */
 
-/// Shorthand util to get collider with relevant data in it
-fn get_shape(part: &QPhysicsReadOnlyItem, full: bool) -> Collider {
-    let size = part.size.0 / 2.0;
+/// Parts without a shape of their own, or whose mesh can't make a collider, are boxes.
+fn part_shape(
+    part: Part,
+    size: Vec3,
+    mesh: Option<&MeshRef>,
+    assets: Option<&AssetCache>,
+) -> ColliderBuilder {
+    if let Some(mesh_ref) = mesh {
+        let mesh = match assets {
+            Some(assets) => assets.get(&Handle::<Mesh>::new(&mesh_ref.name)),
+            None => Mesh::fallback(),
+        };
+        let points: Vec<Point<Real>> = mesh
+            .positions
+            .iter()
+            .map(|p| {
+                let p = *p * size;
+                point![p.x, p.y, p.z]
+            })
+            .collect();
+        let builder = match mesh_ref.collider {
+            MeshCollider::ConvexHull => ColliderBuilder::convex_hull(&points),
+            MeshCollider::TriMesh => {
+                ColliderBuilder::trimesh(points, mesh.triangles().collect()).ok()
+            }
+        };
+        match builder {
+            Some(builder) => return builder,
+            None => warn!(
+                "Couldn't build a {:?} collider for {}, using a box",
+                mesh_ref.collider, mesh_ref.name
+            ),
+        }
+    }
+
+    match part {
+        Part::Wedge => {
+            // Same as the wedge mesh, the top edge sits above the back (Z+)
+            let points: Vec<Point<Real>> = [
+                [-0.5, -0.5, -0.5],
+                [0.5, -0.5, -0.5],
+                [-0.5, -0.5, 0.5],
+                [0.5, -0.5, 0.5],
+                [-0.5, 0.5, 0.5],
+                [0.5, 0.5, 0.5],
+            ]
+            .iter()
+            .map(|p| {
+                let p = Vec3::from_array(*p) * size;
+                point![p.x, p.y, p.z]
+            })
+            .collect();
+            if let Some(builder) = ColliderBuilder::convex_hull(&points) {
+                return builder;
+            }
+        }
+        Part::Ball => {
+            return ColliderBuilder::ball(part.shape_size(size).x / 2.0);
+        }
+        Part::Cylinder => {
+            let axis = cylinder_axis(size);
+            let shape = part.shape_size(size);
+            let (half_height, radius) = (shape[axis] / 2.0, shape[(axis + 1) % 3] / 2.0);
+            let rotation = part.shape_rotation(size);
+            if rotation == Quat::IDENTITY {
+                return ColliderBuilder::cylinder(half_height, radius);
+            }
+            // The collider's own pose is the part's, so lying down it turns inside a compound
+            let (axis, angle) = rotation.to_axis_angle();
+            let turn = Isometry::rotation(vector![axis.x, axis.y, axis.z] * angle);
+            return ColliderBuilder::compound(vec![(
+                turn,
+                SharedShape::cylinder(half_height, radius),
+            )]);
+        }
+        _ => (),
+    }
 
-    let mut builder = ColliderBuilder::cuboid(size.x, size.y, size.z).restitution(0.4);
+    let size = size / 2.0;
+    ColliderBuilder::cuboid(size.x, size.y, size.z)
+}
+
+/// Shorthand util to get collider with relevant data in it
+fn get_shape(part: &QPhysicsReadOnlyItem, assets: Option<&AssetCache>, full: bool) -> Collider {
+    let mut builder = part_collider(*part.part, *part.material, part.size.0, part.mesh, assets);
     if full {
         let pos = part.position;
         let (yaw, pitch, roll) = {
@@ -103,8 +244,13 @@ fn get_shape(part: &QPhysicsReadOnlyItem, full: bool) -> Collider {
 }
 
 /// Add component for collision shape for a given part
-fn build_shape(commands: &mut Commands, state: &mut PhysicsState, part: QPhysicsReadOnlyItem) {
-    let shape = get_shape(&part, true);
+fn build_shape(
+    commands: &mut Commands,
+    state: &mut PhysicsState,
+    assets: Option<&AssetCache>,
+    part: QPhysicsReadOnlyItem,
+) {
+    let shape = get_shape(&part, assets, true);
     let shape_handle = state.colliders.insert(shape);
     commands
         .entity(part.entity)
@@ -115,6 +261,7 @@ fn build_shape(commands: &mut Commands, state: &mut PhysicsState, part: QPhysics
 fn build_body(
     commands: &mut Commands,
     state: &mut PhysicsState,
+    assets: Option<&AssetCache>,
     part: QPhysicsReadOnlyItem,
     builder: RigidBodyBuilder,
 ) {
@@ -125,7 +272,7 @@ fn build_body(
         (axis.x * angle, axis.y * angle, axis.z * angle)
     };
 
-    let shape = get_shape(&part, false);
+    let shape = get_shape(&part, assets, false);
 
     let body = builder
         .translation(vector![pos.x, pos.y, pos.z])
@@ -1,7 +1,7 @@
 /*
     Information about bricks relevant to the rendering engine.
 */
-use crate::ecs::{common::*, parts::*};
+use crate::ecs::{common::*, material::Material, parts::*};
 use bytemuck::{Pod, Zeroable};
 use glam::Affine3A;
 
@@ -11,12 +11,22 @@ use glam::Affine3A;
 
 impl Part {
     pub fn to_uniform(
+        &self,
         position: &Position,
         rotation: &Rotation,
         size: &Size,
         color: &Color,
+        studs: &StudInfo,
+        material: &Material,
     ) -> BrickUniform {
-        let transform = Affine3A::from_scale_rotation_translation(size.0, rotation.0, position.0);
+        // Meshes are scaled in their own frame, before being turned onto the part's axes
+        let shape_rotation = self.shape_rotation(size.0);
+        let size = (shape_rotation.inverse() * self.shape_size(size.0)).abs();
+        let transform = Affine3A::from_scale_rotation_translation(
+            size,
+            rotation.0 * shape_rotation,
+            position.0,
+        );
 
         let normals = transform.matrix3.inverse().transpose().to_cols_array_2d();
 
@@ -24,12 +34,52 @@ impl Part {
             model: transform.to_cols_array_2d(),
             normal: normals,
             color: color.0,
-            size: size.0.to_array(),
-            stud_layout: 0x210000, // To-do, conversion func
+            size: size.to_array(),
+            stud_layout: studs.stud_layout(),
+            material: material.shading(),
         }
     }
 }
 
+impl Material {
+    /// Roughness, specular and emissive, in the order bricks.wgsl reads them
+    pub fn shading(&self) -> [f32; 3] {
+        let properties = self.properties();
+        [
+            properties.roughness,
+            properties.specular,
+            properties.emissive,
+        ]
+    }
+}
+
+/// Nibble of each face in stud_layout, in the order of the face groups in VERTICES
+pub const STUD_FACE_FRONT: u32 = 0;
+pub const STUD_FACE_RIGHT: u32 = 1;
+pub const STUD_FACE_BACK: u32 = 2;
+pub const STUD_FACE_LEFT: u32 = 3;
+pub const STUD_FACE_TOP: u32 = 4;
+pub const STUD_FACE_BOTTOM: u32 = 5;
+
+impl StudInfo {
+    /// Pack the stud texture layer of every face, the shader picks the nibble for a
+    ///     vertex from its face group (vertex_index / 4).
+    pub fn stud_layout(&self) -> u32 {
+        [
+            (self.front, STUD_FACE_FRONT),
+            (self.right, STUD_FACE_RIGHT),
+            (self.back, STUD_FACE_BACK),
+            (self.left, STUD_FACE_LEFT),
+            (self.top, STUD_FACE_TOP),
+            (self.bottom, STUD_FACE_BOTTOM),
+        ]
+        .iter()
+        .fold(0, |layout, &(stud, face)| {
+            layout | (stud as u32) << (face * 4)
+        })
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct BrickVertex {
@@ -47,6 +97,7 @@ pub struct BrickUniform {
     pub color: [u8; 4],
     pub size: [f32; 3],
     pub stud_layout: u32,
+    pub material: [f32; 3],
 }
 
 impl BrickVertex {
@@ -150,6 +201,12 @@ impl BrickUniform {
                     shader_location: 14,
                     format: wgpu::VertexFormat::Uint32,
                 },
+                // Material
+                wgpu::VertexAttribute {
+                    offset: size_of::<[f32; 26]>() as wgpu::BufferAddress,
+                    shader_location: 15,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
             ],
         }
     }
@@ -317,3 +374,139 @@ pub const INDICES: &[u16] = &[
     16, 17, 18, 16, 18, 19, // Bottom face
     20, 21, 22, 20, 22, 23,
 ];
+
+/// Stud layers drawn on a wedge, only the full faces (front and bottom) can have studs.
+/// The slope replaces the top, the sides are triangles and the back collapses.
+pub const WEDGE_STUD_MASK: u32 = 0xF << (STUD_FACE_FRONT * 4) | 0xF << (STUD_FACE_BOTTOM * 4);
+
+/// The brick cube with its top back edge (Z-) dropped onto the bottom.
+/// The top face becomes the slope rising towards Z+ and the back face collapses,
+///     so the vertex groups (and stud layers) stay in the same order as VERTICES.
+pub fn wedge_vertices() -> Vec<BrickVertex> {
+    let slope = [
+        0.0,
+        std::f32::consts::FRAC_1_SQRT_2,
+        -std::f32::consts::FRAC_1_SQRT_2,
+    ];
+    VERTICES
+        .iter()
+        .enumerate()
+        .map(|(i, vertex)| {
+            let mut vertex = *vertex;
+            if vertex.position[1] > 0.0 && vertex.position[2] < 0.0 {
+                vertex.position[1] = -0.5;
+            }
+            // Top face
+            if i / 4 == 4 {
+                vertex.normals = slope;
+            }
+            vertex
+        })
+        .collect()
+}
+
+/// Rings from pole to pole and segments around the ball mesh
+const BALL_RINGS: u32 = 16;
+const BALL_SEGMENTS: u32 = 32;
+
+/// UV sphere fitting the unit cube, wound clockwise like the brick.
+/// Balls have no studs so every vertex samples the flat layer.
+pub fn ball_mesh() -> (Vec<BrickVertex>, Vec<u32>) {
+    use std::f32::consts::{PI, TAU};
+
+    let mut vertices = Vec::new();
+    for ring in 0..=BALL_RINGS {
+        let theta = PI * ring as f32 / BALL_RINGS as f32;
+        for segment in 0..=BALL_SEGMENTS {
+            let phi = TAU * segment as f32 / BALL_SEGMENTS as f32;
+            let normal = [
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin(),
+            ];
+            vertices.push(BrickVertex {
+                position: normal.map(|n| n * 0.5),
+                normals: normal,
+                tex_coords: [
+                    segment as f32 / BALL_SEGMENTS as f32,
+                    ring as f32 / BALL_RINGS as f32,
+                ],
+                tex_scale: [0, 0],
+            });
+        }
+    }
+
+    let mut indices = Vec::new();
+    let row = BALL_SEGMENTS + 1;
+    for ring in 0..BALL_RINGS {
+        for segment in 0..BALL_SEGMENTS {
+            let a = ring * row + segment;
+            let (b, c, d) = (a + row, a + row + 1, a + 1);
+            // The quads touching the poles are triangles
+            if ring != 0 {
+                indices.extend([a, b, d]);
+            }
+            if ring != BALL_RINGS - 1 {
+                indices.extend([d, b, c]);
+            }
+        }
+    }
+    (vertices, indices)
+}
+
+/// Sides around the cylinder mesh
+const CYLINDER_SEGMENTS: u32 = 32;
+
+/// Cylinder along Y fitting the unit cube, wound clockwise like the brick.
+/// Sides come first, then the top and bottom caps around their centres.
+pub fn cylinder_mesh() -> (Vec<BrickVertex>, Vec<u32>) {
+    use std::f32::consts::TAU;
+
+    let vertex = |position: [f32; 3], normals: [f32; 3], tex_coords: [f32; 2]| BrickVertex {
+        position,
+        normals,
+        tex_coords,
+        tex_scale: [0, 0],
+    };
+    let corner = |segment: u32| {
+        let phi = TAU * segment as f32 / CYLINDER_SEGMENTS as f32;
+        (phi.cos(), phi.sin())
+    };
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for segment in 0..=CYLINDER_SEGMENTS {
+        let (x, z) = corner(segment);
+        let u = segment as f32 / CYLINDER_SEGMENTS as f32;
+        vertices.push(vertex([x * 0.5, 0.5, z * 0.5], [x, 0.0, z], [u, 0.0]));
+        vertices.push(vertex([x * 0.5, -0.5, z * 0.5], [x, 0.0, z], [u, 1.0]));
+    }
+    for segment in 0..CYLINDER_SEGMENTS {
+        let (top, bottom) = (segment * 2, segment * 2 + 1);
+        let (next_top, next_bottom) = (top + 2, bottom + 2);
+        indices.extend([top, bottom, next_top, next_top, bottom, next_bottom]);
+    }
+
+    for (y, normal) in [(0.5, 1.0), (-0.5, -1.0)] {
+        let centre = vertices.len() as u32;
+        vertices.push(vertex([0.0, y, 0.0], [0.0, normal, 0.0], [0.5, 0.5]));
+        for segment in 0..CYLINDER_SEGMENTS {
+            let (x, z) = corner(segment);
+            vertices.push(vertex(
+                [x * 0.5, y, z * 0.5],
+                [0.0, normal, 0.0],
+                [x * 0.5 + 0.5, z * 0.5 + 0.5],
+            ));
+        }
+        for segment in 0..CYLINDER_SEGMENTS {
+            let a = centre + 1 + segment;
+            let b = centre + 1 + (segment + 1) % CYLINDER_SEGMENTS;
+            if normal > 0.0 {
+                indices.extend([centre, a, b]);
+            } else {
+                indices.extend([centre, b, a]);
+            }
+        }
+    }
+    (vertices, indices)
+}
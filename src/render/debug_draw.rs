@@ -3,9 +3,10 @@ use bytemuck::{Pod, Zeroable};
 use glam::Vec3;
 use rapier3d::prelude::*;
 use std::{borrow::Cow, fmt::Debug, mem::size_of};
+use tracing::{error, info};
 
 use crate::{
-    common::asset_cache::AssetCache,
+    common::asset_cache::{AssetCache, AssetKind, Handle, Shader},
     render::{
         camera::Camera,
         render_state::{RenderPassInfo, RenderState},
@@ -14,10 +15,13 @@ use crate::{
 };
 
 const MAX_DEBUG_VERTICES: u64 = 1024 * 1024;
+const DEBUG_SHADER: &str = "shaders/debug_draw.wgsl";
 
 #[derive(Resource)]
 pub struct DebugDraw {
     pipeline: wgpu::RenderPipeline,
+    layout: wgpu::PipelineLayout,
+    shader: Handle<Shader>,
     buffer: wgpu::Buffer,
     lines: Vec<Vec3>,
 }
@@ -28,9 +32,7 @@ impl DebugDraw {
     pub fn init(world: &mut World) {
         let asset_cache = world.get_resource::<AssetCache>().unwrap();
 
-        let shader_source = asset_cache
-            .get_shader("shaders/debug_draw.wgsl")
-            .expect("Couldn't load debug shader");
+        let shader = asset_cache.load_or_fallback::<Shader>(DEBUG_SHADER);
 
         let render_state = world
             .get_resource::<RenderState>()
@@ -56,14 +58,65 @@ impl DebugDraw {
             push_constant_ranges: &[],
         });
 
+        let pipeline = render_state
+            .validated(|device| {
+                Self::create_pipeline(device, config.format, &layout, asset_cache.get(&shader))
+            })
+            .unwrap_or_else(|e| {
+                error!(
+                    "Using fallback debug shader, {} failed: {}",
+                    DEBUG_SHADER, e
+                );
+                Self::create_pipeline(device, config.format, &layout, Shader::fallback())
+            });
+
+        world.insert_resource(DebugDraw {
+            pipeline: pipeline,
+            layout,
+            shader,
+            buffer: buffer,
+            lines: Vec::new(),
+        });
+    }
+
+    /// Rebuild the pipeline when the debug shader was reloaded, keeping the old one on errors
+    pub fn reload(
+        state: Res<RenderState>,
+        assets: Res<AssetCache>,
+        mut debug_draw: ResMut<DebugDraw>,
+    ) {
+        if !assets.was_reloaded(debug_draw.shader.name()) {
+            return;
+        }
+        let Some(source) = assets.try_get(&debug_draw.shader) else {
+            return;
+        };
+        let pipeline = state.validated(|device| {
+            Self::create_pipeline(device, state.config.format, &debug_draw.layout, source)
+        });
+        match pipeline {
+            Ok(pipeline) => {
+                info!("Rebuilt debug pipeline");
+                debug_draw.pipeline = pipeline;
+            }
+            Err(e) => error!("Keeping old debug pipeline, {} failed: {}", DEBUG_SHADER, e),
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        layout: &wgpu::PipelineLayout,
+        shader_source: &str,
+    ) -> wgpu::RenderPipeline {
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Debug Shader"),
             source: wgpu::ShaderSource::Wgsl(Cow::from(shader_source)).into(),
         });
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Debug Pipeline"),
-            layout: Some(&layout),
+            layout: Some(layout),
             vertex: wgpu::VertexState {
                 module: &shader,
                 entry_point: Some("vs_main"),
@@ -75,7 +128,7 @@ impl DebugDraw {
                 entry_point: Some("fs_main"),
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
+                    format,
                     blend: Some(wgpu::BlendState::REPLACE),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
@@ -103,13 +156,7 @@ impl DebugDraw {
             },
             multiview: None,
             cache: None,
-        });
-
-        world.insert_resource(DebugDraw {
-            pipeline: pipeline,
-            buffer: buffer,
-            lines: Vec::new(),
-        });
+        })
     }
 
     /// Renders the debug draw lines onto the scene
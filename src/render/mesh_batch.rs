@@ -0,0 +1,182 @@
+/*
+    Instanced draws of a single mesh.
+
+    Every mesh used by a Part::Mesh, and every primitive that isn't the brick cube (wedges),
+    gets its own vertex and index buffers next to an instance buffer of BrickUniforms,
+    drawn with the brick pipeline in one call.
+    A batched part's BufferIndex is its slot in `instances`, removal swaps the last one in.
+*/
+use bevy_ecs::prelude::*;
+use std::{mem::size_of, ops::Range};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    common::mesh::Mesh,
+    render::bricks::{BrickUniform, BrickVertex},
+};
+
+const INITIAL_CAPACITY: usize = 64;
+
+pub struct MeshBatch {
+    pub vb: wgpu::Buffer,
+    pub ib: wgpu::Buffer,
+    pub index_count: u32,
+    pub instances: Vec<BrickUniform>,
+    /// Part owning each instance
+    pub entities: Vec<Entity>,
+    instance_buffer: wgpu::Buffer,
+    capacity: usize,
+    dirty: bool,
+    /// Applied to every instance's stud_layout
+    stud_mask: u32,
+}
+
+impl MeshBatch {
+    /// Stud layers are picked by vertex index, which only lines up on the brick layout,
+    ///     so mesh assets never get studs
+    pub fn new(device: &wgpu::Device, mesh: &Mesh) -> Self {
+        let (vertices, indices) = mesh_vertices(mesh);
+        Self::from_vertices(device, &vertices, &indices, 0)
+    }
+
+    /// Batch for vertices already in the brick layout
+    pub fn from_vertices(
+        device: &wgpu::Device,
+        vertices: &[BrickVertex],
+        indices: &[u32],
+        stud_mask: u32,
+    ) -> Self {
+        let (vb, ib, index_count) = Self::create_mesh_buffers(device, vertices, indices);
+        MeshBatch {
+            vb,
+            ib,
+            index_count,
+            instances: Vec::new(),
+            entities: Vec::new(),
+            instance_buffer: Self::create_instance_buffer(device, INITIAL_CAPACITY),
+            capacity: INITIAL_CAPACITY,
+            dirty: false,
+            stud_mask,
+        }
+    }
+
+    /// Swap the mesh out, keeping every instance
+    pub fn set_mesh(&mut self, device: &wgpu::Device, mesh: &Mesh) {
+        let (vertices, indices) = mesh_vertices(mesh);
+        (self.vb, self.ib, self.index_count) =
+            Self::create_mesh_buffers(device, &vertices, &indices);
+    }
+
+    /// Add an instance, returns its index
+    pub fn push(&mut self, entity: Entity, uniform: BrickUniform) -> u32 {
+        self.instances.push(self.masked(uniform));
+        self.entities.push(entity);
+        self.dirty = true;
+        self.instances.len() as u32 - 1
+    }
+
+    pub fn set(&mut self, index: u32, uniform: BrickUniform) {
+        let uniform = self.masked(uniform);
+        if let Some(instance) = self.instances.get_mut(index as usize) {
+            *instance = uniform;
+            self.dirty = true;
+        }
+    }
+
+    /// Remove an instance, returns the part that was moved into its slot
+    pub fn remove(&mut self, index: u32) -> Option<Entity> {
+        let index = index as usize;
+        if index >= self.instances.len() {
+            return None;
+        }
+        self.instances.swap_remove(index);
+        self.entities.swap_remove(index);
+        self.dirty = true;
+        self.entities.get(index).copied()
+    }
+
+    /// Write instances if anything changed, growing the buffer when it's full
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if !self.dirty {
+            return;
+        }
+        if self.instances.len() > self.capacity {
+            self.capacity = self.instances.len().next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, self.capacity);
+        }
+        queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&self.instances),
+        );
+        self.dirty = false;
+    }
+
+    /// Expects the brick pipeline and bind groups to be set
+    pub fn draw(&self, pass: &mut wgpu::RenderPass<'static>) {
+        if self.instances.is_empty() {
+            return;
+        }
+        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        self.draw_mesh(pass, 0..self.instances.len() as u32);
+    }
+
+    /// Draw the mesh for instances of whichever instance buffer is bound
+    pub fn draw_mesh(&self, pass: &mut wgpu::RenderPass<'static>, instances: Range<u32>) {
+        pass.set_vertex_buffer(0, self.vb.slice(..));
+        pass.set_index_buffer(self.ib.slice(..), wgpu::IndexFormat::Uint32);
+        pass.draw_indexed(0..self.index_count, 0, instances);
+    }
+
+    pub fn masked(&self, uniform: BrickUniform) -> BrickUniform {
+        BrickUniform {
+            stud_layout: uniform.stud_layout & self.stud_mask,
+            ..uniform
+        }
+    }
+
+    fn create_mesh_buffers(
+        device: &wgpu::Device,
+        vertices: &[BrickVertex],
+        indices: &[u32],
+    ) -> (wgpu::Buffer, wgpu::Buffer, u32) {
+        let vb = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Mesh Vertex Buffer"),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let ib = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Mesh Index Buffer"),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        (vb, ib, indices.len() as u32)
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Mesh Instance Buffer"),
+            size: (size_of::<BrickUniform>() * capacity) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+}
+
+/// Vertices in the brick layout and indices wound clockwise like the brick mesh.
+/// Meshes have no studs, their texture coordinates all sample the flat layer.
+pub fn mesh_vertices(mesh: &Mesh) -> (Vec<BrickVertex>, Vec<u32>) {
+    let vertices = mesh
+        .positions
+        .iter()
+        .zip(&mesh.normals)
+        .map(|(position, normal)| BrickVertex {
+            position: position.to_array(),
+            normals: normal.to_array(),
+            tex_coords: [0.0, 0.0],
+            tex_scale: [0, 0],
+        })
+        .collect();
+    let indices = mesh.triangles().flat_map(|[a, b, c]| [a, c, b]).collect();
+    (vertices, indices)
+}
@@ -4,6 +4,8 @@ pub use render_state::*;
 pub mod bricks;
 pub mod camera;
 pub mod debug_draw;
+pub mod mesh_batch;
 pub mod queries;
 pub mod scene_tree;
 pub mod texture;
+pub mod transparent;
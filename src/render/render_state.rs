@@ -137,6 +137,17 @@ impl RenderState {
         })
     }
 
+    /// Create GPU objects inside a validation error scope.
+    /// wgpu's default handler panics on errors, this hands them back (e.g. a WGSL compile error).
+    pub fn validated<T>(&self, create: impl FnOnce(&wgpu::Device) -> T) -> Result<T> {
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let value = create(&self.device);
+        match pollster::block_on(self.device.pop_error_scope()) {
+            Some(error) => Err(anyhow::anyhow!("{}", error)),
+            None => Ok(value),
+        }
+    }
+
     /// Resize window
     /// also used as a hint to start the surface for wgpu
     pub fn resize(&mut self, _width: u32, _height: u32) {
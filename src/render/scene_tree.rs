@@ -1,4 +1,8 @@
-use std::{borrow::Cow, collections::VecDeque, u16};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
+    u16,
+};
 
 /*
 
@@ -6,23 +10,38 @@ use std::{borrow::Cow, collections::VecDeque, u16};
 
 */
 use crate::{
-    common::asset_cache::AssetCache,
+    common::{
+        asset_cache::{AssetCache, AssetKind, Handle, Image, Shader},
+        mesh::Mesh,
+    },
     ecs::{parts::*, render::BufferIndex},
     render::{
         bricks::*,
         camera::*,
+        mesh_batch::MeshBatch,
         render_state::{RenderPassInfo, RenderState},
         texture::*,
+        transparent::{MeshKey, TransparentSet, is_transparent},
     },
 };
 use bevy_ecs::prelude::*;
+use tracing::{error, info};
 use wgpu::util::DeviceExt;
 
 const MAX_INSTANCE_BUFFER_COUNT: usize = u16::MAX as usize;
 
+const BRICK_SHADER: &str = "shaders/bricks.wgsl";
+const STUD_TEXTURE: &str = "textures/studs.png";
+
 #[derive(Resource)]
 pub struct SceneTree {
     pub pipeline: wgpu::RenderPipeline,
+    /// Blends instead of replacing and leaves the depth buffer alone
+    pub transparent_pipeline: wgpu::RenderPipeline,
+    pub pipeline_layout: wgpu::PipelineLayout,
+    pub texture_layout: wgpu::BindGroupLayout,
+    pub shader: Handle<Shader>,
+    pub studs: Handle<Image>,
     pub brick_vb: wgpu::Buffer,
     pub brick_ib: wgpu::Buffer,
     pub brick_ibos: Vec<wgpu::Buffer>,
@@ -30,6 +49,12 @@ pub struct SceneTree {
     pub texture_bg: wgpu::BindGroup,
     pub bricks: Vec<BrickUniform>,
     pub clean_queue: VecDeque<u32>,
+    /// Part::Mesh instances, keyed by mesh asset
+    pub meshes: HashMap<String, MeshBatch>,
+    /// Primitives with their own mesh, every other part is drawn as a brick
+    pub primitives: HashMap<Part, MeshBatch>,
+    /// Parts with alpha below 255 from every mesh, drawn last
+    pub transparent: TransparentSet,
 }
 
 impl SceneTree {
@@ -44,13 +69,9 @@ impl SceneTree {
             .get_resource::<AssetCache>()
             .expect("SceneTree::init(), expected Asset Cache");
 
-        let brick_diffuse = asset_cache
-            .get_image("textures/studs.png")
-            .expect("Couldn't get brick texture");
-
-        let shader_source = asset_cache
-            .get_shader("shaders/bricks.wgsl")
-            .expect("Couldn't get brick texture");
+        // Missing or broken files render with the fallbacks instead of stopping the game
+        let studs = asset_cache.load_or_fallback::<Image>(STUD_TEXTURE);
+        let shader = asset_cache.load_or_fallback::<Shader>(BRICK_SHADER);
 
         let device = &render_state.device;
         let queue = &render_state.queue;
@@ -72,7 +93,15 @@ impl SceneTree {
            Brick Texture Layout
         */
 
-        let brick_texture = Texture::create_brick_texture(&brick_diffuse, device, queue);
+        let brick_texture = Texture::create_brick_texture(asset_cache.get(&studs), device, queue)
+            .or_else(|e| {
+                error!(
+                    "Using fallback stud texture, {} failed: {}",
+                    STUD_TEXTURE, e
+                );
+                Texture::create_brick_texture(Image::fallback(), device, queue)
+            })
+            .expect("Fallback texture should always load");
         let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Brick Texture Layout"),
             entries: &[
@@ -95,20 +124,7 @@ impl SceneTree {
             ],
         });
 
-        let texture_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Brick Texture Group"),
-            layout: &texture_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&brick_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&brick_texture.sampler),
-                },
-            ],
-        });
+        let texture_group = Self::create_texture_group(device, &texture_layout, &brick_texture);
 
         /*
            Scene Kit Layout
@@ -184,27 +200,179 @@ impl SceneTree {
                 push_constant_ranges: &[],
             });
 
+        let (render_pipeline, transparent_pipeline) = render_state
+            .validated(|device| {
+                Self::create_pipelines(
+                    device,
+                    config.format,
+                    &render_pipeline_layout,
+                    asset_cache.get(&shader),
+                )
+            })
+            .unwrap_or_else(|e| {
+                error!(
+                    "Using fallback brick shader, {} failed: {}",
+                    BRICK_SHADER, e
+                );
+                Self::create_pipelines(
+                    device,
+                    config.format,
+                    &render_pipeline_layout,
+                    Shader::fallback(),
+                )
+            });
+
+        let bricks: Vec<BrickUniform> = Vec::with_capacity(MAX_INSTANCE_BUFFER_COUNT);
+
+        let brick_indices: Vec<u32> = INDICES.iter().map(|&i| i as u32).collect();
+        let (ball_vertices, ball_indices) = ball_mesh();
+        let (cylinder_vertices, cylinder_indices) = cylinder_mesh();
+        let primitives = HashMap::from([
+            (
+                Part::Wedge,
+                MeshBatch::from_vertices(
+                    device,
+                    &wedge_vertices(),
+                    &brick_indices,
+                    WEDGE_STUD_MASK,
+                ),
+            ),
+            (
+                Part::Ball,
+                MeshBatch::from_vertices(device, &ball_vertices, &ball_indices, 0),
+            ),
+            (
+                Part::Cylinder,
+                MeshBatch::from_vertices(device, &cylinder_vertices, &cylinder_indices, 0),
+            ),
+        ]);
+
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Brick Instance Buffer"),
+            size: (std::mem::size_of::<BrickUniform>() * MAX_INSTANCE_BUFFER_COUNT) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        world.add_observer(Self::handle_index_removal);
+        world.insert_resource(Self {
+            pipeline: render_pipeline,
+            transparent_pipeline,
+            pipeline_layout: render_pipeline_layout,
+            texture_layout,
+            shader,
+            studs,
+            brick_vb: vb,
+            brick_ib: ib,
+            brick_ibos: vec![instance_buffer],
+            scene_bg: scene_kit_group,
+            texture_bg: texture_group,
+            bricks: bricks,
+            clean_queue: VecDeque::new(),
+            meshes: HashMap::new(),
+            primitives,
+            transparent: TransparentSet::default(),
+        });
+    }
+
+    /// Rebuild the pipeline or texture when their assets were reloaded.
+    /// Broken shaders and images are logged and the previous ones are kept.
+    pub fn reload(state: Res<RenderState>, assets: Res<AssetCache>, mut st: ResMut<SceneTree>) {
+        let st = st.as_mut();
+
+        if assets.was_reloaded(st.shader.name())
+            && let Some(source) = assets.try_get(&st.shader)
+        {
+            let pipelines = state.validated(|device| {
+                Self::create_pipelines(device, state.config.format, &st.pipeline_layout, source)
+            });
+            match pipelines {
+                Ok((pipeline, transparent_pipeline)) => {
+                    info!("Rebuilt brick pipeline");
+                    st.pipeline = pipeline;
+                    st.transparent_pipeline = transparent_pipeline;
+                }
+                Err(e) => error!("Keeping old brick pipeline, {} failed: {}", BRICK_SHADER, e),
+            }
+        }
+
+        if assets.was_reloaded(st.studs.name())
+            && let Some(image) = assets.try_get(&st.studs)
+        {
+            let group = state
+                .validated(|device| Texture::create_brick_texture(image, device, &state.queue))
+                .and_then(|texture| texture)
+                .map(|texture| {
+                    Self::create_texture_group(&state.device, &st.texture_layout, &texture)
+                });
+            match group {
+                Ok(group) => {
+                    info!("Reloaded stud texture");
+                    st.texture_bg = group;
+                }
+                Err(e) => error!("Keeping old stud texture, {} failed: {}", STUD_TEXTURE, e),
+            }
+        }
+
+        for (name, batch) in st.meshes.iter_mut() {
+            if assets.was_reloaded(name)
+                && let Some(mesh) = assets.try_get(&Handle::<Mesh>::new(name))
+            {
+                info!("Reloaded mesh {}", name);
+                batch.set_mesh(&state.device, mesh);
+            }
+        }
+    }
+
+    /// Opaque and transparent pipelines, both from the brick shader
+    fn create_pipelines(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        layout: &wgpu::PipelineLayout,
+        shader_source: &str,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Some Shader"),
             source: wgpu::ShaderSource::Wgsl(Cow::from(shader_source)),
         });
+        (
+            Self::create_pipeline(device, format, layout, &shader, false),
+            Self::create_pipeline(device, format, layout, &shader, true),
+        )
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        transparent: bool,
+    ) -> wgpu::RenderPipeline {
+        let (label, blend) = if transparent {
+            (
+                "SceneTree Transparent Pipeline",
+                wgpu::BlendState::ALPHA_BLENDING,
+            )
+        } else {
+            ("SceneTree Pipeline", wgpu::BlendState::REPLACE)
+        };
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("SceneTree Pipeline"),
-            layout: Some(&render_pipeline_layout),
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(layout),
             vertex: wgpu::VertexState {
-                module: &shader,
+                module: shader,
                 entry_point: Some("vs_main"),
                 buffers: &[BrickVertex::desc(), BrickUniform::desc_instancing()],
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
             },
             fragment: Some(wgpu::FragmentState {
-                module: &shader,
+                module: shader,
                 entry_point: Some("fs_main"),
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
+                    format,
+                    blend: Some(blend),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
             }),
@@ -219,7 +387,8 @@ impl SceneTree {
             },
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
+                // Transparent parts are sorted instead, they still hide behind opaque ones
+                depth_write_enabled: !transparent,
                 depth_compare: wgpu::CompareFunction::Less,
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
@@ -231,49 +400,54 @@ impl SceneTree {
             },
             multiview: None,
             cache: None,
-        });
-
-        let bricks: Vec<BrickUniform> = Vec::with_capacity(MAX_INSTANCE_BUFFER_COUNT);
-
-        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Brick Instance Buffer"),
-            size: (std::mem::size_of::<BrickUniform>() * MAX_INSTANCE_BUFFER_COUNT) as u64,
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+        })
+    }
 
-        world.add_observer(Self::handle_index_removal);
-        world.insert_resource(Self {
-            pipeline: render_pipeline,
-            brick_vb: vb,
-            brick_ib: ib,
-            brick_ibos: vec![instance_buffer],
-            scene_bg: scene_kit_group,
-            texture_bg: texture_group,
-            bricks: bricks,
-            clean_queue: VecDeque::new(),
-        });
+    fn create_texture_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: &Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Brick Texture Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+        })
     }
 
     pub fn handle_index_removal(
         trigger: Trigger<OnRemove, BufferIndex>,
-        indices: Query<&BufferIndex>,
+        mut indices: Query<(&mut BufferIndex, &Part, Option<&MeshRef>)>,
         mut st: ResMut<SceneTree>,
     ) {
-        let Ok(b_index) = indices.get(trigger.target()) else {
+        let Ok((b_index, part, mesh)) = indices.get(trigger.target()) else {
             return;
         };
 
         let Some(index) = b_index.0 else {
             return;
         };
-        st.clean_queue.push_back(index);
+
+        if let Some(moved) = st.remove_part(trigger.target(), part, mesh, index)
+            && let Ok((mut moved_index, _, _)) = indices.get_mut(moved)
+        {
+            moved_index.0 = Some(index);
+        }
     }
 
     /// Adjust possible instance and uniform buffers on event of objects being deleted
     pub fn remove_bricks(
         mut st: ResMut<SceneTree>,
-        mut query: Query<QPartRenderUpdate>,
+        mut query: Query<(QPartRenderUpdate, Option<&MeshRef>)>,
         //mut er: EventReader<RenderCleanup>,
     ) {
         if st.clean_queue.is_empty() {
@@ -281,19 +455,20 @@ impl SceneTree {
         }
 
         let indices: Vec<u32> = st.clean_queue.drain(..).collect();
-
-        let mut index: u32 = 0;
-        st.bricks.retain_mut(|_| {
-            let remove = !indices.contains(&index);
-            index += 1;
-            remove
-        });
-
-        index = 0;
-
-        for mut bi in query.iter_mut() {
-            bi.buffer_index.0 = Some(index);
-            index += 1;
+        let shift = remove_brick_slots(&mut st.bricks, &indices);
+
+        // Bricks pushed back from the transparent set aren't in query order, keep each one's slot
+        for (mut bi, mesh) in query.iter_mut() {
+            let Some(index) = bi.buffer_index.0 else {
+                continue;
+            };
+            if st.transparent.owns(bi.entity, index) || st.is_batched(bi.part, mesh) {
+                continue;
+            }
+            let shifted = shift(index);
+            if shifted != index {
+                bi.buffer_index.0 = Some(shifted);
+            }
         }
     }
 
@@ -301,50 +476,95 @@ impl SceneTree {
     /// Reorders the BufferIndex component to its position in the instance buffer
     pub fn add_bricks(
         state: Res<RenderState>,
+        assets: Res<AssetCache>,
         mut st: ResMut<SceneTree>,
-        mut query: Query<QPartRenderUpdate, FPartAdd>,
+        mut query: Query<(QPartRenderUpdate, Option<&MeshRef>), FPartAdd>,
     ) {
         let queue = &state.queue;
 
-        for mut brick in query.iter_mut() {
-            // Give buffer index the size of the vector for now until we need multiple buffers
-            brick.buffer_index.0 = Some(st.bricks.len() as u32);
-
-            st.bricks.push(Part::to_uniform(
+        for (mut brick, mesh) in query.iter_mut() {
+            if let Some(mesh) = mesh {
+                st.meshes.entry(mesh.name.clone()).or_insert_with(|| {
+                    let handle = assets.load_or_fallback::<Mesh>(&mesh.name);
+                    MeshBatch::new(&state.device, assets.get(&handle))
+                });
+            }
+            let uniform = brick.part.to_uniform(
                 brick.position,
                 brick.rotation,
                 brick.size,
                 brick.color,
-            ));
+                brick.studs,
+                brick.material,
+            );
+            brick.buffer_index.0 = Some(st.push_part(brick.entity, brick.part, mesh, uniform));
         }
         // Again, using this until we have multiple buffers.
         if let Some(buffer) = st.brick_ibos.first() {
             queue.write_buffer(buffer, 0, bytemuck::cast_slice(&st.bricks));
         }
+        st.upload_batches(&state.device, queue);
     }
 
     pub fn update_bricks(
+        mut commands: Commands,
         scene: Res<RenderState>,
         mut st: ResMut<SceneTree>,
-        query: Query<QPart, FPartChange>,
+        query: Query<(QPart, Option<&MeshRef>), FPartChange>,
     ) {
         #[allow(unused)]
         let device = &scene.device;
         #[allow(unused)]
         let queue = &scene.queue;
 
-        for brick in query.iter() {
-            let index = brick.buffer_index.0.unwrap();
-
-            if let Some(uniform) = st.bricks.get_mut(index as usize) {
-                *uniform =
-                    Part::to_uniform(brick.position, brick.rotation, brick.size, brick.color);
+        // Indices given out below, their BufferIndex only changes once commands run
+        let mut moved_indices: HashMap<Entity, u32> = HashMap::new();
+
+        for (brick, mesh) in query.iter() {
+            let Some(index) = moved_indices
+                .get(&brick.entity)
+                .copied()
+                .or(brick.buffer_index.0)
+            else {
+                continue;
+            };
+            let uniform = brick.part.to_uniform(
+                brick.position,
+                brick.rotation,
+                brick.size,
+                brick.color,
+                brick.studs,
+                brick.material,
+            );
+
+            let transparent = st.transparent.owns(brick.entity, index);
+            if transparent != is_transparent(brick.color.0) {
+                // Alpha crossed 255, move the part between the opaque and transparent sets
+                if let Some(moved) = st.remove_part(brick.entity, brick.part, mesh, index) {
+                    moved_indices.insert(moved, index);
+                }
+                let new_index = st.push_part(brick.entity, brick.part, mesh, uniform);
+                moved_indices.insert(brick.entity, new_index);
+            } else if transparent {
+                let uniform = match st.batch_mut(brick.part, mesh) {
+                    Some(batch) => batch.masked(uniform),
+                    None => uniform,
+                };
+                st.transparent.set(index, uniform);
+            } else if let Some(batch) = st.batch_mut(brick.part, mesh) {
+                batch.set(index, uniform);
+            } else if let Some(brick) = st.bricks.get_mut(index as usize) {
+                *brick = uniform;
             }
         }
+        for (entity, index) in moved_indices {
+            commands.entity(entity).try_insert(BufferIndex(Some(index)));
+        }
         // Full update of instance buffer
         if let Some(buffer) = st.brick_ibos.first() {
             queue.write_buffer(buffer, 0, bytemuck::cast_slice(&st.bricks));
         }
+        st.upload_batches(device, queue);
     }
 
     pub fn render(scene_tree: ResMut<SceneTree>, mut info: ResMut<RenderPassInfo>) {
@@ -370,5 +590,140 @@ impl SceneTree {
         }
         // Coupled with assert, this needs to be refactored once we "split up" scenes.
         pass.draw_indexed(0..36, 0, 0..scene_tree.bricks.len() as _);
+
+        for batch in scene_tree.primitives.values() {
+            batch.draw(pass);
+        }
+        for batch in scene_tree.meshes.values() {
+            batch.draw(pass);
+        }
+
+        // Back to front over everything opaque
+        let transparent = &scene_tree.transparent;
+        if let Some(buffer) = transparent.instance_buffer()
+            && !transparent.runs().is_empty()
+        {
+            pass.set_pipeline(&scene_tree.transparent_pipeline);
+            pass.set_vertex_buffer(1, buffer.slice(..));
+            for (key, instances) in transparent.runs() {
+                let batch = match key {
+                    MeshKey::Brick => {
+                        pass.set_vertex_buffer(0, scene_tree.brick_vb.slice(..));
+                        pass.set_index_buffer(
+                            scene_tree.brick_ib.slice(..),
+                            wgpu::IndexFormat::Uint16,
+                        );
+                        pass.draw_indexed(0..36, 0, instances.clone());
+                        continue;
+                    }
+                    MeshKey::Primitive(part) => scene_tree.primitives.get(part),
+                    MeshKey::Asset(name) => scene_tree.meshes.get(name),
+                };
+                if let Some(batch) = batch {
+                    batch.draw_mesh(pass, instances.clone());
+                }
+            }
+        }
     }
+
+    /// Sort transparent parts back to front for this frame's camera
+    pub fn sort_transparent(
+        state: Res<RenderState>,
+        camera: Res<Camera>,
+        mut st: ResMut<SceneTree>,
+    ) {
+        st.transparent.sort(camera.view);
+        st.transparent.upload(&state.device, &state.queue);
+    }
+
+    /*
+        Helper functions
+    */
+
+    /// Parts with a mesh asset or their own primitive mesh aren't in the brick buffer
+    fn is_batched(&self, part: &Part, mesh: Option<&MeshRef>) -> bool {
+        mesh.is_some() || self.primitives.contains_key(part)
+    }
+
+    /// Batch drawing this part, None for parts drawn as bricks
+    fn batch_mut(&mut self, part: &Part, mesh: Option<&MeshRef>) -> Option<&mut MeshBatch> {
+        match mesh {
+            Some(mesh) => self.meshes.get_mut(&mesh.name),
+            None => self.primitives.get_mut(part),
+        }
+    }
+
+    /// Put a part's uniform where it's drawn from, returns its BufferIndex
+    fn push_part(
+        &mut self,
+        entity: Entity,
+        part: &Part,
+        mesh: Option<&MeshRef>,
+        uniform: BrickUniform,
+    ) -> u32 {
+        if is_transparent(uniform.color) {
+            let key = MeshKey::new(part, mesh, self.primitives.contains_key(part));
+            let uniform = match self.batch_mut(part, mesh) {
+                Some(batch) => batch.masked(uniform),
+                None => uniform,
+            };
+            return self.transparent.push(entity, key, uniform);
+        }
+        if let Some(batch) = self.batch_mut(part, mesh) {
+            return batch.push(entity, uniform);
+        }
+
+        // Give buffer index the size of the vector for now until we need multiple buffers
+        self.bricks.push(uniform);
+        self.bricks.len() as u32 - 1
+    }
+
+    /// Take a part's uniform out, returns the part moved into its slot.
+    /// Sets fill the hole right away, bricks wait for remove_bricks to reorder them
+    fn remove_part(
+        &mut self,
+        entity: Entity,
+        part: &Part,
+        mesh: Option<&MeshRef>,
+        index: u32,
+    ) -> Option<Entity> {
+        if self.transparent.owns(entity, index) {
+            return self.transparent.remove(index);
+        }
+        if let Some(batch) = self.batch_mut(part, mesh) {
+            return batch.remove(index);
+        }
+        // Hidden until it's gone from the buffer
+        if let Some(brick) = self.bricks.get_mut(index as usize) {
+            *brick = bytemuck::Zeroable::zeroed();
+        }
+        self.clean_queue.push_back(index);
+        None
+    }
+
+    fn upload_batches(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        for batch in self.primitives.values_mut().chain(self.meshes.values_mut()) {
+            batch.upload(device, queue);
+        }
+    }
+}
+
+/// Drop removed slots from the opaque brick buffer.
+/// Returns where a kept slot ends up, moved down by the removed slots below it
+pub fn remove_brick_slots(
+    bricks: &mut Vec<BrickUniform>,
+    removed: &[u32],
+) -> impl Fn(u32) -> u32 + use<> {
+    let mut removed = removed.to_vec();
+    removed.sort_unstable();
+    removed.dedup();
+
+    let mut index: u32 = 0;
+    bricks.retain(|_| {
+        let keep = removed.binary_search(&index).is_err();
+        index += 1;
+        keep
+    });
+
+    move |index| index - removed.partition_point(|&slot| slot < index) as u32
 }
@@ -6,6 +6,9 @@ use image::GenericImageView;
 
 */
 
+/// Layers in the stud texture, every value a StudType nibble can hold
+pub const STUD_LAYERS: u32 = 16;
+
 pub struct Texture {
     #[allow(unused)]
     pub texture: wgpu::Texture,
@@ -66,17 +69,27 @@ impl Texture {
         buffer: &[u8],
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-    ) -> Texture {
-        let bytes = image::load_from_memory(buffer).expect("Couldn't load brick texture");
+    ) -> Result<Texture> {
+        let bytes = image::load_from_memory(buffer)?;
         //let bytes = image::open("src/render_kit/stud_texture.png").expect("Can't find brick texture");
         let rgba = bytes.to_rgba8().into_raw();
 
         let dimensions = bytes.dimensions();
 
+        // Square layers stacked top to bottom, one per StudType value
+        if dimensions.0 == 0 || dimensions.1 != dimensions.0 * STUD_LAYERS {
+            bail!(
+                "Stud texture is {}x{}, expected {} square layers stacked vertically",
+                dimensions.0,
+                dimensions.1,
+                STUD_LAYERS
+            );
+        }
+
         let size = wgpu::Extent3d {
             width: dimensions.0,
             height: dimensions.0,
-            depth_or_array_layers: 3,
+            depth_or_array_layers: STUD_LAYERS,
         };
 
         let texture = device.create_texture(&wgpu::TextureDescriptor {
@@ -120,11 +133,11 @@ impl Texture {
             ..Default::default()
         });
 
-        return Texture {
+        Ok(Texture {
             texture: texture,
             view: view,
             sampler: sampler,
-        };
+        })
     }
 
     pub fn from_bytes(
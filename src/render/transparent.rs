@@ -0,0 +1,150 @@
+/*
+    Parts whose Color has an alpha below 255.
+
+    They can't sit in the brick buffer or a MeshBatch, those are drawn in any order with depth writes on.
+    Instances are kept in insertion order here (a part's BufferIndex is its slot, removal swaps the last
+    one in like MeshBatch) and every frame they're sorted back to front into their own instance buffer.
+    Sorted instances are drawn in runs sharing a mesh, with the blending pipeline after every opaque part.
+*/
+use bevy_ecs::prelude::*;
+use glam::{Mat4, Vec3};
+use std::{mem::size_of, ops::Range};
+
+use crate::{
+    ecs::parts::{MeshRef, Part},
+    render::bricks::BrickUniform,
+};
+
+const INITIAL_CAPACITY: usize = 64;
+
+/// Whether a color needs the blending pass
+pub fn is_transparent(color: [u8; 4]) -> bool {
+    color[3] < 255
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Vertices a transparent instance is drawn with
+pub enum MeshKey {
+    Brick,
+    /// One of SceneTree's primitives
+    Primitive(Part),
+    /// Mesh asset name
+    Asset(String),
+}
+
+impl MeshKey {
+    pub fn new(part: &Part, mesh: Option<&MeshRef>, primitive: bool) -> Self {
+        match mesh {
+            Some(mesh) => MeshKey::Asset(mesh.name.clone()),
+            None if primitive => MeshKey::Primitive(*part),
+            None => MeshKey::Brick,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct TransparentSet {
+    pub instances: Vec<BrickUniform>,
+    /// Part owning each instance
+    pub entities: Vec<Entity>,
+    pub keys: Vec<MeshKey>,
+    /// Slots from back to front, rebuilt by sort
+    pub order: Vec<u32>,
+    /// Sorted instances sharing a mesh, kept with the order so parts removed
+    ///     before drawing can't break it
+    runs: Vec<(MeshKey, Range<u32>)>,
+    instance_buffer: Option<wgpu::Buffer>,
+    capacity: usize,
+}
+
+impl TransparentSet {
+    /// Add an instance, returns its index
+    pub fn push(&mut self, entity: Entity, key: MeshKey, uniform: BrickUniform) -> u32 {
+        self.instances.push(uniform);
+        self.entities.push(entity);
+        self.keys.push(key);
+        self.instances.len() as u32 - 1
+    }
+
+    pub fn set(&mut self, index: u32, uniform: BrickUniform) {
+        if let Some(instance) = self.instances.get_mut(index as usize) {
+            *instance = uniform;
+        }
+    }
+
+    /// Remove an instance, returns the part that was moved into its slot
+    pub fn remove(&mut self, index: u32) -> Option<Entity> {
+        let index = index as usize;
+        if index >= self.instances.len() {
+            return None;
+        }
+        self.instances.swap_remove(index);
+        self.entities.swap_remove(index);
+        self.keys.swap_remove(index);
+        self.entities.get(index).copied()
+    }
+
+    /// Whether the part's BufferIndex points into this set
+    pub fn owns(&self, entity: Entity, index: u32) -> bool {
+        self.entities.get(index as usize) == Some(&entity)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    /// Order instances by their distance to the camera, furthest first
+    pub fn sort(&mut self, view: Mat4) {
+        let eye = view.inverse().w_axis.truncate();
+        let distance = |slot: u32| {
+            let translation = self.instances[slot as usize].model[3];
+            Vec3::from_array(translation).distance_squared(eye)
+        };
+
+        let mut order: Vec<u32> = (0..self.instances.len() as u32).collect();
+        order.sort_by(|&a, &b| distance(b).total_cmp(&distance(a)));
+
+        self.runs.clear();
+        for (i, &slot) in order.iter().enumerate() {
+            let key = &self.keys[slot as usize];
+            match self.runs.last_mut() {
+                Some((last, range)) if last == key => range.end = i as u32 + 1,
+                _ => self.runs.push((key.clone(), i as u32..i as u32 + 1)),
+            }
+        }
+        self.order = order;
+    }
+
+    /// Ranges of sorted instances sharing a mesh, in drawing order
+    pub fn runs(&self) -> &[(MeshKey, Range<u32>)] {
+        &self.runs
+    }
+
+    /// Write the sorted instances, growing the buffer when it's full
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.order.is_empty() {
+            return;
+        }
+        if self.instance_buffer.is_none() || self.order.len() > self.capacity {
+            self.capacity = self.order.len().next_power_of_two().max(INITIAL_CAPACITY);
+            self.instance_buffer = Some(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Transparent Instance Buffer"),
+                size: (size_of::<BrickUniform>() * self.capacity) as u64,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+        let sorted: Vec<BrickUniform> = self
+            .order
+            .iter()
+            .map(|&slot| self.instances[slot as usize])
+            .collect();
+        if let Some(buffer) = &self.instance_buffer {
+            queue.write_buffer(buffer, 0, bytemuck::cast_slice(&sorted));
+        }
+    }
+
+    pub fn instance_buffer(&self) -> Option<&wgpu::Buffer> {
+        self.instance_buffer.as_ref()
+    }
+}
@@ -0,0 +1,481 @@
+/*
+    Compact binary scene format, meant for scenes in the 2^16-2^17 brick range.
+
+    Everything is little endian.
+
+    Header
+        magic       [u8; 4]     "FBSB"
+        version     u16
+        reserved    u16
+        count       u32         number of part records
+        palette     u16         number of palette entries, followed by [u8; 4] RGBA for each
+
+    Part record
+        kind        u8          Part
+        flags       u8          FLAG_* in the low nibble, Material in the high nibble
+        studs       u8          top in the low nibble, bottom in the high nibble
+        sides       [u8; 2]     front | right, back | left (version 3 and up)
+        rotation    u8          index into AXIS_ROTATIONS, or ROTATION_PACKED followed by a u32
+        position    [i32; 3]    in 1/GRID_DIVISIONS studs
+        size        [u16; 3]    in 1/GRID_DIVISIONS studs
+        color       u16         palette index
+        mesh                    only with FLAG_MESH (version 2 and up), a string for the mesh asset
+                                and a u8 MeshCollider
+        labels                  only with FLAG_LABELS (version 4 and up), a string for the name
+                                (empty if unnamed), a u16 tag count and a string for every tag
+
+    Strings are a u16 byte length followed by UTF-8
+*/
+use anyhow::{Context, Result, anyhow, bail};
+use bevy_ecs::prelude::*;
+use bevy_platform::collections::HashMap;
+use glam::{Quat, Vec3, Vec4};
+use std::io::{Read, Write};
+
+use crate::{
+    ecs::{
+        material::Material,
+        parts::{MeshCollider, MeshRef, Part, StudInfo, StudType},
+        physics::Anchor,
+    },
+    scene::{PartDesc, Scene, spawn_part},
+    utils::rotation::{AXIS_ROTATIONS, axis_rotation_index},
+};
+
+const MAGIC: [u8; 4] = *b"FBSB";
+pub const BINARY_VERSION: u16 = 4;
+
+/// Positions and sizes snap to 1/12th of a stud, enough for half studs and plate thirds
+pub const GRID_DIVISIONS: f32 = 12.0;
+/// How many parts are read before handing them to World::spawn_batch
+pub const SPAWN_BATCH_SIZE: usize = 4096;
+
+const FLAG_ANCHOR: u8 = 0x01;
+const FLAG_PHYSICAL: u8 = 0x02;
+/// MeshRef follows the record
+const FLAG_MESH: u8 = 0x04;
+/// Name or tags follow the record
+const FLAG_LABELS: u8 = 0x08;
+
+const ROTATION_PACKED: u8 = 0xFF;
+
+/// Write a scene as a binary container
+pub fn write_binary<W: Write>(scene: &Scene, writer: &mut W) -> Result<()> {
+    // Palette has to be known up front so the reader can stream records
+    let mut palette: Vec<[u8; 4]> = Vec::new();
+    let mut palette_index: HashMap<[u8; 4], u16> = HashMap::new();
+    for desc in &scene.parts {
+        if palette_index.contains_key(&desc.color) {
+            continue;
+        }
+        let index = u16::try_from(palette.len()).context("Scene has too many colors")?;
+        palette_index.insert(desc.color, index);
+        palette.push(desc.color);
+    }
+
+    let count = u32::try_from(scene.parts.len()).context("Scene has too many parts")?;
+    let palette_len = u16::try_from(palette.len()).context("Scene has too many colors")?;
+
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&BINARY_VERSION.to_le_bytes())?;
+    writer.write_all(&0u16.to_le_bytes())?;
+    writer.write_all(&count.to_le_bytes())?;
+    writer.write_all(&palette_len.to_le_bytes())?;
+    for color in &palette {
+        writer.write_all(color)?;
+    }
+
+    for desc in &scene.parts {
+        let mut flags = (desc.material as u8) << 4;
+        if desc.anchor {
+            flags |= FLAG_ANCHOR;
+        }
+        if desc.physical {
+            flags |= FLAG_PHYSICAL;
+        }
+        if desc.mesh.is_some() {
+            flags |= FLAG_MESH;
+        }
+        let labeled = desc.name.is_some() || !desc.tags.is_empty();
+        if labeled {
+            flags |= FLAG_LABELS;
+        }
+        let studs = &desc.studs;
+        writer.write_all(&[
+            desc.part as u8,
+            flags,
+            nibbles(studs.top, studs.bottom),
+            nibbles(studs.front, studs.right),
+            nibbles(studs.back, studs.left),
+        ])?;
+
+        match axis_rotation_index(desc.rotation) {
+            Some(index) => writer.write_all(&[index as u8])?,
+            None => {
+                writer.write_all(&[ROTATION_PACKED])?;
+                writer.write_all(&pack_rotation(desc.rotation).to_le_bytes())?;
+            }
+        }
+
+        for value in desc.position.to_array() {
+            let value = i32::try_from(grid_units(value)?)
+                .with_context(|| format!("Position {} is too far out", desc.position))?;
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        for value in desc.size.to_array() {
+            let value = u16::try_from(grid_units(value)?)
+                .with_context(|| format!("Size {} is out of range", desc.size))?;
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.write_all(&palette_index[&desc.color].to_le_bytes())?;
+
+        if let Some(mesh) = &desc.mesh {
+            write_string(writer, &mesh.name)?;
+            writer.write_all(&[mesh.collider as u8])?;
+        }
+        if labeled {
+            write_string(writer, desc.name.as_deref().unwrap_or_default())?;
+            let count = u16::try_from(desc.tags.len()).context("Part has too many tags")?;
+            writer.write_all(&count.to_le_bytes())?;
+            for tag in &desc.tags {
+                write_string(writer, tag)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Streams part records out of a binary container
+pub struct BinarySceneReader<R: Read> {
+    reader: R,
+    version: u16,
+    count: u32,
+    remaining: u32,
+    palette: Vec<[u8; 4]>,
+}
+
+impl<R: Read> BinarySceneReader<R> {
+    /// Reads the header, leaving the reader at the first part record
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut magic = [0; 4];
+        reader
+            .read_exact(&mut magic)
+            .context("Couldn't read scene header")?;
+        if magic != MAGIC {
+            bail!("Not a binary scene");
+        }
+
+        let version = read_u16(&mut reader)?;
+        if version == 0 || version > BINARY_VERSION {
+            bail!(
+                "Unsupported binary scene version {}, expected at most {}",
+                version,
+                BINARY_VERSION
+            );
+        }
+        let _reserved = read_u16(&mut reader)?;
+        let count = read_u32(&mut reader)?;
+
+        let palette_len = read_u16(&mut reader)?;
+        let mut palette = Vec::with_capacity(palette_len as usize);
+        for _ in 0..palette_len {
+            let mut color = [0; 4];
+            reader.read_exact(&mut color)?;
+            palette.push(color);
+        }
+
+        Ok(Self {
+            reader,
+            version,
+            count,
+            remaining: count,
+            palette,
+        })
+    }
+
+    /// Number of parts stored in the header
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Read the next part record, None once every record was read
+    pub fn next_part(&mut self) -> Result<Option<PartDesc>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+
+        let reader = &mut self.reader;
+        let mut record = [0; 3];
+        reader.read_exact(&mut record)?;
+        let [kind, flags, studs] = record;
+        // Sides were flat before version 3
+        let sides = if self.version >= 3 {
+            let mut sides = [0; 2];
+            reader.read_exact(&mut sides)?;
+            sides
+        } else {
+            [0; 2]
+        };
+        let rotation = read_u8(reader)?;
+
+        let rotation = if rotation == ROTATION_PACKED {
+            unpack_rotation(read_u32(reader)?)
+        } else {
+            *AXIS_ROTATIONS
+                .get(rotation as usize)
+                .ok_or(anyhow!("Invalid rotation index {}", rotation))?
+        };
+
+        let mut position = [0.0; 3];
+        for value in &mut position {
+            *value = read_i32(reader)? as f32 / GRID_DIVISIONS;
+        }
+        let mut size = [0.0; 3];
+        for value in &mut size {
+            *value = read_u16(reader)? as f32 / GRID_DIVISIONS;
+        }
+
+        let color_index = read_u16(reader)?;
+        let color = *self
+            .palette
+            .get(color_index as usize)
+            .ok_or(anyhow!("Invalid palette index {}", color_index))?;
+
+        // Meshes came in version 2
+        let mesh = if self.version >= 2 && flags & FLAG_MESH != 0 {
+            let name = read_string(reader)?;
+            let mut collider = [0; 1];
+            reader.read_exact(&mut collider)?;
+            Some(MeshRef {
+                name,
+                collider: mesh_collider_from_u8(collider[0])?,
+            })
+        } else {
+            None
+        };
+        // Labels came in version 4
+        let (name, tags) = if self.version >= 4 && flags & FLAG_LABELS != 0 {
+            let name = read_string(reader)?;
+            let count = read_u16(reader)?;
+            let tags = (0..count)
+                .map(|_| read_string(reader))
+                .collect::<Result<Vec<_>>>()?;
+            (Some(name).filter(|name| !name.is_empty()), tags)
+        } else {
+            (None, Vec::new())
+        };
+
+        Ok(Some(PartDesc {
+            part: part_from_u8(kind)?,
+            position: Vec3::from_array(position),
+            rotation,
+            size: Vec3::from_array(size),
+            color,
+            studs: StudInfo {
+                top: stud_from_u8(studs & 0x0F)?,
+                bottom: stud_from_u8(studs >> 4)?,
+                front: stud_from_u8(sides[0] & 0x0F)?,
+                right: stud_from_u8(sides[0] >> 4)?,
+                back: stud_from_u8(sides[1] & 0x0F)?,
+                left: stud_from_u8(sides[1] >> 4)?,
+            },
+            material: material_from_u8(flags >> 4)?,
+            mesh,
+            name,
+            tags,
+            anchor: flags & FLAG_ANCHOR != 0,
+            physical: flags & FLAG_PHYSICAL != 0,
+        }))
+    }
+
+    /// Read up to `max` parts
+    pub fn next_batch(&mut self, max: usize) -> Result<Vec<PartDesc>> {
+        let mut batch = Vec::with_capacity(max.min(self.remaining as usize));
+        while batch.len() < max {
+            match self.next_part()? {
+                Some(desc) => batch.push(desc),
+                None => break,
+            }
+        }
+        Ok(batch)
+    }
+
+    /// Spawn every remaining part SPAWN_BATCH_SIZE at a time.
+    /// Doesn't build models or physics, see scene::build_scene.
+    /// Entities are returned in file order.
+    pub fn spawn(mut self, world: &mut World) -> Result<Vec<Entity>> {
+        let mut entities = Vec::with_capacity(self.remaining as usize);
+
+        loop {
+            let batch = self.next_batch(SPAWN_BATCH_SIZE)?;
+            if batch.is_empty() {
+                break;
+            }
+
+            // spawn_batch wants one bundle type, so split by marker
+            let mut spawned = vec![Entity::PLACEHOLDER; batch.len()];
+            let mut plain = Vec::new();
+            let mut anchored = Vec::new();
+            for (i, desc) in batch.iter().enumerate() {
+                if desc.mesh.is_some() || desc.name.is_some() || !desc.tags.is_empty() {
+                    spawned[i] = spawn_part(world, desc);
+                } else if desc.anchor {
+                    anchored.push(i);
+                } else if desc.physical {
+                    plain.push(i);
+                } else {
+                    spawned[i] = spawn_part(world, desc);
+                }
+            }
+
+            let ids = world.spawn_batch(plain.iter().map(|&i| batch[i].bundle()));
+            for (i, id) in plain.iter().zip(ids) {
+                spawned[*i] = id;
+            }
+
+            let ids = world.spawn_batch(anchored.iter().map(|&i| (batch[i].bundle(), Anchor)));
+            for (i, id) in anchored.iter().zip(ids) {
+                spawned[*i] = id;
+            }
+
+            entities.extend(spawned);
+        }
+
+        Ok(entities)
+    }
+
+    /// Read the whole container into a scene
+    pub fn into_scene(mut self) -> Result<Scene> {
+        let parts = self.next_batch(self.remaining as usize)?;
+        Ok(Scene {
+            parts,
+            ..Default::default()
+        })
+    }
+}
+
+/*
+    Helper functions
+*/
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> Result<u16> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_i32<R: Read>(reader: &mut R) -> Result<i32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(i32::from_le_bytes(bytes))
+}
+
+fn read_string<R: Read>(reader: &mut R) -> Result<String> {
+    let mut bytes = vec![0; read_u16(reader)? as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).context("Invalid string")
+}
+
+fn write_string<W: Write>(writer: &mut W, value: &str) -> Result<()> {
+    let length = u16::try_from(value.len()).context("String is too long")?;
+    writer.write_all(&length.to_le_bytes())?;
+    writer.write_all(value.as_bytes())?;
+    Ok(())
+}
+
+/// Length rounded to the nearest 1/GRID_DIVISIONS stud
+fn grid_units(value: f32) -> Result<i64> {
+    let units = (value * GRID_DIVISIONS).round();
+    if !units.is_finite() {
+        bail!("Can't store {} in a binary scene", value);
+    }
+    Ok(units as i64)
+}
+
+fn part_from_u8(value: u8) -> Result<Part> {
+    match value {
+        0 => Ok(Part::Brick),
+        1 => Ok(Part::Wedge),
+        2 => Ok(Part::Ball),
+        3 => Ok(Part::Mesh),
+        4 => Ok(Part::Cylinder),
+        _ => Err(anyhow!("Invalid part kind {}", value)),
+    }
+}
+
+fn mesh_collider_from_u8(value: u8) -> Result<MeshCollider> {
+    match value {
+        0 => Ok(MeshCollider::ConvexHull),
+        1 => Ok(MeshCollider::TriMesh),
+        _ => Err(anyhow!("Invalid mesh collider {}", value)),
+    }
+}
+
+fn nibbles(low: StudType, high: StudType) -> u8 {
+    (low as u8) | ((high as u8) << 4)
+}
+
+fn stud_from_u8(value: u8) -> Result<StudType> {
+    StudType::from_u8(value).ok_or(anyhow!("Invalid stud type {}", value))
+}
+
+fn material_from_u8(value: u8) -> Result<Material> {
+    Material::from_u8(value).ok_or(anyhow!("Invalid material {}", value))
+}
+
+/// Largest component is dropped (its index goes in the top two bits), the other three
+///     are stored with 10 bits each since they're within +-1/sqrt(2)
+fn pack_rotation(rotation: Quat) -> u32 {
+    let q = rotation.normalize().to_array();
+    let largest = (0..4)
+        .max_by(|&a, &b| q[a].abs().total_cmp(&q[b].abs()))
+        .unwrap();
+    let sign = if q[largest] < 0.0 { -1.0 } else { 1.0 };
+
+    let mut packed = (largest as u32) << 30;
+    let mut shift = 20;
+    for (i, value) in q.iter().enumerate() {
+        if i == largest {
+            continue;
+        }
+        let normalized = (value * sign * std::f32::consts::SQRT_2 + 1.0) / 2.0;
+        let quantized = (normalized.clamp(0.0, 1.0) * 1023.0).round() as u32;
+        packed |= quantized << shift;
+        shift -= 10;
+    }
+    packed
+}
+
+fn unpack_rotation(packed: u32) -> Quat {
+    let largest = (packed >> 30) as usize;
+
+    let mut q = [0.0; 4];
+    let mut shift = 20;
+    let mut sum = 0.0;
+    for (i, value) in q.iter_mut().enumerate() {
+        if i == largest {
+            continue;
+        }
+        let quantized = (packed >> shift) & 0x3FF;
+        *value = (quantized as f32 / 1023.0 * 2.0 - 1.0) / std::f32::consts::SQRT_2;
+        sum += *value * *value;
+        shift -= 10;
+    }
+    q[largest] = f32::sqrt(f32::max(1.0 - sum, 0.0));
+
+    Quat::from_vec4(Vec4::from_array(q)).normalize()
+}
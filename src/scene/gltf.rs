@@ -0,0 +1,359 @@
+/*
+    glTF 2.0 (.glb) export of the parts in a world.
+
+    Every shape's mesh is written into the buffer once, mesh parts use their asset from the
+    AssetCache. Every distinct Color gets a material, every shape and color pair a mesh pointing
+    at the shared accessors, parts are nodes using those meshes and Models are parent nodes of
+    their parts. Doesn't touch RenderState so it runs headless.
+*/
+use anyhow::Result;
+use bevy_ecs::prelude::*;
+use bevy_platform::collections::HashMap;
+use serde_json::{Value, json};
+use std::io::Write;
+use tracing::warn;
+
+use crate::{
+    common::{
+        asset_cache::AssetCache,
+        mesh::{FALLBACK_MESH, Mesh},
+    },
+    ecs::{
+        common::*,
+        material::Material,
+        model::Model,
+        parts::{MeshRef, Part, StudInfo},
+    },
+    render::{
+        bricks::{
+            BrickUniform, BrickVertex, INDICES, VERTICES, ball_mesh, cylinder_mesh, wedge_vertices,
+        },
+        mesh_batch::mesh_vertices,
+    },
+};
+
+pub(crate) const GLB_MAGIC: &[u8; 4] = b"glTF";
+pub(crate) const GLB_VERSION: u32 = 2;
+pub(crate) const CHUNK_JSON: &[u8; 4] = b"JSON";
+pub(crate) const CHUNK_BIN: &[u8; 4] = b"BIN\0";
+
+// Accessor component types and buffer view targets from the spec
+pub(crate) const FLOAT: u32 = 5126;
+pub(crate) const UNSIGNED_BYTE: u32 = 5121;
+pub(crate) const UNSIGNED_SHORT: u32 = 5123;
+pub(crate) const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+/// Write every part in the world as a binary glTF
+pub fn export_glb<W: Write>(world: &mut World, writer: &mut W) -> Result<()> {
+    let (json, bin) = build_gltf(world)?;
+
+    let mut json = serde_json::to_vec(&json)?;
+    pad(&mut json, b' ');
+    let mut bin = bin;
+    pad(&mut bin, 0);
+
+    let length = 12 + 8 + json.len() + 8 + bin.len();
+
+    writer.write_all(GLB_MAGIC)?;
+    writer.write_all(&GLB_VERSION.to_le_bytes())?;
+    writer.write_all(&(length as u32).to_le_bytes())?;
+
+    writer.write_all(&(json.len() as u32).to_le_bytes())?;
+    writer.write_all(CHUNK_JSON)?;
+    writer.write_all(&json)?;
+
+    writer.write_all(&(bin.len() as u32).to_le_bytes())?;
+    writer.write_all(CHUNK_BIN)?;
+    writer.write_all(&bin)?;
+    Ok(())
+}
+
+/// glTF document and its binary buffer
+pub fn build_gltf(world: &mut World) -> Result<(Value, Vec<u8>)> {
+    let mut buffers = Buffers::default();
+    // First accessor of every shape's mesh, mesh parts are keyed by their asset
+    let mut shapes: HashMap<(Part, Option<String>), usize> = HashMap::new();
+
+    /*
+       Materials and part nodes
+    */
+    let mut materials = Vec::new();
+    let mut meshes = Vec::new();
+    let mut mesh_by_look: HashMap<(usize, [u8; 4]), usize> = HashMap::new();
+    let mut material_by_color: HashMap<[u8; 4], usize> = HashMap::new();
+
+    let mut nodes = Vec::new();
+    let mut node_by_part: HashMap<Entity, usize> = HashMap::new();
+    let mut roots = Vec::new();
+
+    let mut parts = world.query::<(
+        Entity,
+        &Part,
+        Option<&MeshRef>,
+        &Position,
+        &Rotation,
+        &Size,
+        &Color,
+        Has<ChildOf>,
+    )>();
+    let mut parts: Vec<_> = parts.iter(world).collect();
+    parts.sort_by_key(|(entity, ..)| *entity);
+    let assets = world.get_resource::<AssetCache>();
+
+    for (entity, part, mesh_ref, position, rotation, size, color, child) in parts {
+        // Every shape's mesh is only stored once
+        let key = (*part, mesh_ref.map(|mesh| mesh.name.clone()));
+        let accessors = match shapes.get(&key) {
+            Some(&accessors) => accessors,
+            None => {
+                let (vertices, indices) = shape_mesh(*part, mesh_ref, assets);
+                let accessors = buffers.push_mesh(&vertices, &indices)?;
+                shapes.insert(key, accessors);
+                accessors
+            }
+        };
+        let material = *material_by_color.entry(color.0).or_insert_with(|| {
+            materials.push(material(color.0));
+            materials.len() - 1
+        });
+        let mesh = *mesh_by_look.entry((accessors, color.0)).or_insert_with(|| {
+            meshes.push(json!({
+                "primitives": [{
+                    "attributes": {
+                        "POSITION": accessors,
+                        "NORMAL": accessors + 1,
+                        "TEXCOORD_0": accessors + 2,
+                    },
+                    "indices": accessors + 3,
+                    "material": material,
+                }],
+            }));
+            meshes.len() - 1
+        });
+
+        let uniform = part.to_uniform(
+            position,
+            rotation,
+            size,
+            color,
+            &StudInfo::default(),
+            &Material::default(),
+        );
+        nodes.push(json!({
+            "name": format!("Part {}", entity),
+            "mesh": mesh,
+            "matrix": node_matrix(&uniform),
+        }));
+        node_by_part.insert(entity, nodes.len() - 1);
+        if !child {
+            roots.push(nodes.len() - 1);
+        }
+    }
+
+    /*
+       Model hierarchy, parts already carry world transforms so models are identity
+    */
+    let mut models = world.query::<(Entity, &Model, &Children)>();
+    let mut models: Vec<_> = models.iter(world).collect();
+    models.sort_by_key(|(entity, ..)| *entity);
+
+    for (entity, _, children) in models {
+        let children: Vec<usize> = children
+            .iter()
+            .filter_map(|child| node_by_part.get(&child).copied())
+            .collect();
+        nodes.push(json!({
+            "name": format!("Model {}", entity),
+            "children": children,
+        }));
+        roots.push(nodes.len() - 1);
+    }
+
+    let mut document = json!({
+        "asset": { "version": "2.0", "generator": "freebricks" },
+        "scene": 0,
+        "scenes": [{ "nodes": roots }],
+        "nodes": nodes,
+        "meshes": meshes,
+        "materials": materials,
+        "accessors": buffers.accessors,
+        "bufferViews": buffers.views,
+        "buffers": [{ "byteLength": buffers.bin.len() }],
+    });
+
+    // Empty arrays aren't valid glTF
+    if let Some(object) = document.as_object_mut() {
+        object.retain(|_, v| !matches!(v, Value::Array(a) if a.is_empty()));
+    }
+
+    Ok((document, buffers.bin))
+}
+
+/*
+    Helper functions
+*/
+
+/// Binary buffer with the views and accessors pointing into it
+#[derive(Default)]
+struct Buffers {
+    bin: Vec<u8>,
+    views: Vec<Value>,
+    accessors: Vec<Value>,
+}
+
+impl Buffers {
+    fn push_view(&mut self, bytes: &[u8], target: u32) -> usize {
+        pad(&mut self.bin, 0);
+        self.views.push(json!({
+            "buffer": 0,
+            "byteOffset": self.bin.len(),
+            "byteLength": bytes.len(),
+            "target": target,
+        }));
+        self.bin.extend_from_slice(bytes);
+        self.views.len() - 1
+    }
+
+    /// Store a mesh, returns the first of its position, normal, uv and index accessors
+    fn push_mesh(&mut self, vertices: &[BrickVertex], indices: &[u32]) -> Result<usize> {
+        let (mut min, mut max) = ([f32::MAX; 3], [f32::MIN; 3]);
+        for vertex in vertices {
+            for i in 0..3 {
+                min[i] = min[i].min(vertex.position[i]);
+                max[i] = max[i].max(vertex.position[i]);
+            }
+        }
+
+        let positions: Vec<[f32; 3]> = vertices.iter().map(|v| v.position).collect();
+        let normals: Vec<[f32; 3]> = vertices.iter().map(|v| v.normals).collect();
+        let tex_coords: Vec<[f32; 2]> = vertices.iter().map(|v| v.tex_coords).collect();
+        // Our pipeline uses clockwise front faces, glTF wants counter-clockwise
+        let indices: Vec<u32> = indices
+            .chunks(3)
+            .flat_map(|tri| [tri[0], tri[2], tri[1]])
+            .collect();
+        let (index_bytes, index_type) = match indices
+            .iter()
+            .map(|&i| u16::try_from(i))
+            .collect::<Result<Vec<u16>, _>>()
+        {
+            Ok(short) => (bytemuck::cast_slice(&short).to_vec(), UNSIGNED_SHORT),
+            Err(_) => (bytemuck::cast_slice(&indices).to_vec(), UNSIGNED_INT),
+        };
+
+        let position_view = self.push_view(bytemuck::cast_slice(&positions), ARRAY_BUFFER);
+        let normal_view = self.push_view(bytemuck::cast_slice(&normals), ARRAY_BUFFER);
+        let tex_view = self.push_view(bytemuck::cast_slice(&tex_coords), ARRAY_BUFFER);
+        let index_view = self.push_view(&index_bytes, ELEMENT_ARRAY_BUFFER);
+
+        let first = self.accessors.len();
+        self.accessors.extend([
+            json!({
+                "bufferView": position_view,
+                "componentType": FLOAT,
+                "count": positions.len(),
+                "type": "VEC3",
+                "min": min,
+                "max": max,
+            }),
+            json!({
+                "bufferView": normal_view,
+                "componentType": FLOAT,
+                "count": normals.len(),
+                "type": "VEC3",
+            }),
+            json!({
+                "bufferView": tex_view,
+                "componentType": FLOAT,
+                "count": tex_coords.len(),
+                "type": "VEC2",
+            }),
+            json!({
+                "bufferView": index_view,
+                "componentType": index_type,
+                "count": indices.len(),
+                "type": "SCALAR",
+            }),
+        ]);
+        Ok(first)
+    }
+}
+
+/// Vertices and clockwise indices a part is drawn with.
+/// Mesh parts fall back to the brick cube like the renderer does when their asset isn't there
+pub(crate) fn shape_mesh(
+    part: Part,
+    mesh_ref: Option<&MeshRef>,
+    assets: Option<&AssetCache>,
+) -> (Vec<BrickVertex>, Vec<u32>) {
+    match (part, mesh_ref) {
+        (Part::Mesh, Some(mesh_ref)) => {
+            let mesh = match assets {
+                Some(assets) => assets.get(&assets.load_or_fallback::<Mesh>(&mesh_ref.name)),
+                None => {
+                    warn!(
+                        "No assets to export mesh {}, using the fallback",
+                        mesh_ref.name
+                    );
+                    &FALLBACK_MESH
+                }
+            };
+            mesh_vertices(mesh)
+        }
+        (Part::Ball, _) => ball_mesh(),
+        (Part::Cylinder, _) => cylinder_mesh(),
+        (Part::Wedge, _) => (
+            wedge_vertices(),
+            INDICES.iter().map(|&i| i as u32).collect(),
+        ),
+        _ => (
+            VERTICES.to_vec(),
+            INDICES.iter().map(|&i| i as u32).collect(),
+        ),
+    }
+}
+
+/// 3x4 affine from the instance data into a column major 4x4
+fn node_matrix(uniform: &BrickUniform) -> [f32; 16] {
+    let mut matrix = [0.0; 16];
+    for (column, values) in uniform.model.iter().enumerate() {
+        matrix[column * 4..column * 4 + 3].copy_from_slice(values);
+    }
+    matrix[15] = 1.0;
+    matrix
+}
+
+fn material(color: [u8; 4]) -> Value {
+    let [r, g, b, a] = color;
+    let mut material = json!({
+        "name": format!("Color #{:02X}{:02X}{:02X}{:02X}", r, g, b, a),
+        "pbrMetallicRoughness": {
+            "baseColorFactor": [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a as f32 / 255.0],
+            "metallicFactor": 0.0,
+            "roughnessFactor": 0.5,
+        },
+    });
+    if a < 255 {
+        material["alphaMode"] = json!("BLEND");
+    }
+    material
+}
+
+/// baseColorFactor is linear, our colors are sRGB
+fn srgb_to_linear(channel: u8) -> f32 {
+    let c = channel as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// GLB chunks are 4 byte aligned
+fn pad(bytes: &mut Vec<u8>, with: u8) {
+    while !bytes.len().is_multiple_of(4) {
+        bytes.push(with);
+    }
+}
@@ -0,0 +1,412 @@
+/*
+    LDraw (.ldr / .mpd) importer.
+
+    Only type-1 lines (sub-file references) matter to us, a reference is either
+        - a part we know the dimensions of, which becomes a Part::Brick
+        - a submodel (0 FILE in an .mpd, or a sibling .ldr on disk), which is recursed into
+        - anything else, which gets counted in the report
+
+    LDraw units: 1 stud = 20 LDU, a brick is 24 LDU tall and a plate 8 LDU. -Y is up.
+*/
+use anyhow::{Context, Result, anyhow};
+use bevy_ecs::prelude::*;
+use glam::{Mat3, Vec3};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+use tracing::warn;
+
+use crate::{
+    ecs::{
+        material::Material,
+        parts::{Part, StudInfo, StudType},
+    },
+    scene::{PartDesc, Scene},
+    utils::rotation::bake_upright_rotation,
+};
+
+pub const LDU_PER_STUD: f32 = 20.0;
+pub const LDU_PER_BRICK: f32 = 24.0;
+const LDU_PER_PLATE: f32 = 8.0;
+
+/// Submodels referencing each other in a loop would never end otherwise
+const MAX_DEPTH: usize = 32;
+
+/// LDraw colour 16, "use the colour of whatever references me"
+const MAIN_COLOUR: u32 = 16;
+
+pub struct LDrawImport {
+    pub scene: Scene,
+    /// Part file name and how many times it was referenced
+    pub unknown_parts: BTreeMap<String, usize>,
+}
+
+impl LDrawImport {
+    /// Spawn the imported bricks and build models out of the ones that snap
+    pub fn load(&self, world: &mut World) -> Vec<Entity> {
+        self.scene.load(world)
+    }
+}
+
+/// Import an .ldr or .mpd file.
+/// Submodels that aren't inside an .mpd are looked up next to the file.
+pub fn import_ldraw(path: impl AsRef<Path>) -> Result<LDrawImport> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path)
+        .with_context(|| format!("Couldn't read LDraw file {}", path.display()))?;
+
+    let mut files = split_files(&source);
+    let main = files
+        .first()
+        .map(|(name, _)| name.clone())
+        .unwrap_or_default();
+
+    // Pull in sibling .ldr submodels the file refers to
+    if let Some(dir) = path.parent() {
+        let mut pending: Vec<String> = files
+            .iter()
+            .flat_map(|(_, lines)| referenced_files(lines))
+            .collect();
+        while let Some(name) = pending.pop() {
+            if !name.ends_with(".ldr") || files.iter().any(|(n, _)| *n == name) {
+                continue;
+            }
+            let Ok(source) = fs::read_to_string(dir.join(&name)) else {
+                continue;
+            };
+            let lines: Vec<String> = source.lines().map(str::to_string).collect();
+            pending.extend(referenced_files(&lines));
+            files.push((name, lines));
+        }
+    }
+
+    import_files(&main, files.into_iter().collect())
+}
+
+/// Import LDraw source, either a single model or an .mpd with several 0 FILE sections
+pub fn parse_ldraw(source: &str) -> Result<LDrawImport> {
+    let files = split_files(source);
+    let main = files
+        .first()
+        .map(|(name, _)| name.clone())
+        .unwrap_or_default();
+    import_files(&main, files.into_iter().collect())
+}
+
+fn import_files(main: &str, files: HashMap<String, Vec<String>>) -> Result<LDrawImport> {
+    let mut import = LDrawImport {
+        scene: Scene::default(),
+        unknown_parts: BTreeMap::new(),
+    };
+
+    let transform = LDrawTransform {
+        position: Vec3::ZERO,
+        matrix: Mat3::IDENTITY,
+    };
+    walk(&files, main, transform, MAIN_COLOUR, 0, &mut import)?;
+
+    for (name, count) in &import.unknown_parts {
+        warn!(
+            "LDraw import: unsupported part {} (used {} times)",
+            name, count
+        );
+    }
+    Ok(import)
+}
+
+#[derive(Clone, Copy)]
+struct LDrawTransform {
+    position: Vec3,
+    matrix: Mat3,
+}
+
+fn walk(
+    files: &HashMap<String, Vec<String>>,
+    name: &str,
+    transform: LDrawTransform,
+    colour: u32,
+    depth: usize,
+    import: &mut LDrawImport,
+) -> Result<()> {
+    if depth > MAX_DEPTH {
+        return Err(anyhow!("LDraw submodels nested too deep at {}", name));
+    }
+    let lines = files
+        .get(name)
+        .ok_or(anyhow!("Missing LDraw submodel {}", name))?;
+
+    for (line_number, line) in lines.iter().enumerate() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.first() != Some(&"1") {
+            continue;
+        }
+        if tokens.len() < 15 {
+            return Err(anyhow!(
+                "Malformed LDraw line {} in {}: {}",
+                line_number + 1,
+                name,
+                line
+            ));
+        }
+
+        let malformed = || format!("Malformed LDraw line {} in {}", line_number + 1, name);
+
+        let line_colour = match parse_colour(tokens[1]).with_context(malformed)? {
+            MAIN_COLOUR => colour,
+            c => c,
+        };
+        let numbers = tokens[2..14]
+            .iter()
+            .map(|t| t.parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>()
+            .with_context(malformed)?;
+
+        let position = Vec3::new(numbers[0], numbers[1], numbers[2]);
+        // a b c / d e f / g h i is row major
+        let matrix = Mat3::from_cols_array(&[
+            numbers[3],
+            numbers[6],
+            numbers[9],
+            numbers[4],
+            numbers[7],
+            numbers[10],
+            numbers[5],
+            numbers[8],
+            numbers[11],
+        ]);
+
+        let child = LDrawTransform {
+            position: transform.position + transform.matrix * position,
+            matrix: transform.matrix * matrix,
+        };
+
+        let file = normalize_name(&tokens[14..].join(" "));
+        if files.contains_key(&file) {
+            walk(files, &file, child, line_colour, depth + 1, import)?;
+        } else if let Some(brick) = lookup_part(&file) {
+            import
+                .scene
+                .parts
+                .push(brick_desc(&brick, child, line_colour));
+        } else {
+            *import.unknown_parts.entry(file).or_insert(0) += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Split an .mpd into its files, a plain .ldr becomes a single unnamed file
+fn split_files(source: &str) -> Vec<(String, Vec<String>)> {
+    let mut files: Vec<(String, Vec<String>)> = Vec::new();
+
+    for line in source.lines() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.len() >= 3 && tokens[0] == "0" && tokens[1] == "FILE" {
+            files.push((normalize_name(&tokens[2..].join(" ")), Vec::new()));
+            continue;
+        }
+        if tokens.len() >= 2 && tokens[0] == "0" && tokens[1] == "NOFILE" {
+            continue;
+        }
+
+        match files.last_mut() {
+            Some((_, lines)) => lines.push(line.to_string()),
+            None => files.push((String::new(), vec![line.to_string()])),
+        }
+    }
+    files
+}
+
+fn referenced_files(lines: &[String]) -> Vec<String> {
+    lines
+        .iter()
+        .filter_map(|line| {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if tokens.len() >= 15 && tokens[0] == "1" {
+                Some(normalize_name(&tokens[14..].join(" ")))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Colours are either a table index or a direct 0x2RRGGBB colour
+fn parse_colour(token: &str) -> Result<u32> {
+    match token.strip_prefix("0x").or(token.strip_prefix("0X")) {
+        Some(hex) => Ok(u32::from_str_radix(hex, 16)?),
+        None => Ok(token.parse()?),
+    }
+}
+
+/// LDraw names are case insensitive and may use either slash
+fn normalize_name(name: &str) -> String {
+    name.trim().to_lowercase().replace('\\', "/")
+}
+
+/*
+    Part lookup
+*/
+
+struct BrickDims {
+    /// Studs along LDraw X and Z
+    x: f32,
+    z: f32,
+    /// Height in LDU
+    height: f32,
+    top: StudType,
+}
+
+/// Basic bricks, plates and tiles. Long side is along X like in the LDraw library.
+fn lookup_part(file: &str) -> Option<BrickDims> {
+    let name = file.rsplit('/').next()?.strip_suffix(".dat")?;
+
+    let (x, z, height, top) = match name {
+        // Bricks
+        "3005" => (1, 1, LDU_PER_BRICK, StudType::Outlet),
+        "3004" => (2, 1, LDU_PER_BRICK, StudType::Outlet),
+        "3622" => (3, 1, LDU_PER_BRICK, StudType::Outlet),
+        "3010" => (4, 1, LDU_PER_BRICK, StudType::Outlet),
+        "3009" => (6, 1, LDU_PER_BRICK, StudType::Outlet),
+        "3008" => (8, 1, LDU_PER_BRICK, StudType::Outlet),
+        "6111" => (10, 1, LDU_PER_BRICK, StudType::Outlet),
+        "6112" => (12, 1, LDU_PER_BRICK, StudType::Outlet),
+        "3003" => (2, 2, LDU_PER_BRICK, StudType::Outlet),
+        "3002" => (3, 2, LDU_PER_BRICK, StudType::Outlet),
+        "3001" => (4, 2, LDU_PER_BRICK, StudType::Outlet),
+        "2456" => (6, 2, LDU_PER_BRICK, StudType::Outlet),
+        "3007" => (8, 2, LDU_PER_BRICK, StudType::Outlet),
+        "3006" => (10, 2, LDU_PER_BRICK, StudType::Outlet),
+        // Plates
+        "3024" => (1, 1, LDU_PER_PLATE, StudType::Outlet),
+        "3023" => (2, 1, LDU_PER_PLATE, StudType::Outlet),
+        "3623" => (3, 1, LDU_PER_PLATE, StudType::Outlet),
+        "3710" => (4, 1, LDU_PER_PLATE, StudType::Outlet),
+        "3666" => (6, 1, LDU_PER_PLATE, StudType::Outlet),
+        "3460" => (8, 1, LDU_PER_PLATE, StudType::Outlet),
+        "3022" => (2, 2, LDU_PER_PLATE, StudType::Outlet),
+        "3021" => (3, 2, LDU_PER_PLATE, StudType::Outlet),
+        "3020" => (4, 2, LDU_PER_PLATE, StudType::Outlet),
+        "3795" => (6, 2, LDU_PER_PLATE, StudType::Outlet),
+        "3034" => (8, 2, LDU_PER_PLATE, StudType::Outlet),
+        "3832" => (10, 2, LDU_PER_PLATE, StudType::Outlet),
+        "3031" => (4, 4, LDU_PER_PLATE, StudType::Outlet),
+        "3032" => (6, 4, LDU_PER_PLATE, StudType::Outlet),
+        "3035" => (8, 4, LDU_PER_PLATE, StudType::Outlet),
+        "3030" => (10, 4, LDU_PER_PLATE, StudType::Outlet),
+        "3958" => (6, 6, LDU_PER_PLATE, StudType::Outlet),
+        "3036" => (8, 6, LDU_PER_PLATE, StudType::Outlet),
+        "3033" => (10, 6, LDU_PER_PLATE, StudType::Outlet),
+        "41539" => (8, 8, LDU_PER_PLATE, StudType::Outlet),
+        // Tiles
+        "3070b" => (1, 1, LDU_PER_PLATE, StudType::Flat),
+        "3069b" => (2, 1, LDU_PER_PLATE, StudType::Flat),
+        "63864" => (3, 1, LDU_PER_PLATE, StudType::Flat),
+        "2431" => (4, 1, LDU_PER_PLATE, StudType::Flat),
+        "3068b" => (2, 2, LDU_PER_PLATE, StudType::Flat),
+        _ => return None,
+    };
+
+    Some(BrickDims {
+        x: x as f32,
+        z: z as f32,
+        height,
+        top,
+    })
+}
+
+/// Convert a placed LDraw part into engine units
+fn brick_desc(brick: &BrickDims, transform: LDrawTransform, colour: u32) -> PartDesc {
+    // LDraw part origins sit on the top face, center is half the height further down (+Y)
+    let center = transform.position + transform.matrix * Vec3::new(0.0, brick.height / 2.0, 0.0);
+    let position = Vec3::new(
+        center.x / LDU_PER_STUD,
+        -center.y / LDU_PER_BRICK,
+        center.z / LDU_PER_STUD,
+    );
+
+    // Flip Y so the rotation works in engine space
+    let flip = Mat3::from_diagonal(Vec3::new(1.0, -1.0, 1.0));
+    let matrix = flip * transform.matrix * flip;
+
+    let size = Vec3::new(brick.x, brick.height / LDU_PER_BRICK, brick.z);
+    let (rotation, size) = bake_upright_rotation(matrix, size);
+
+    PartDesc {
+        part: Part::Brick,
+        position,
+        rotation,
+        size,
+        color: ldraw_colour(colour),
+        studs: StudInfo {
+            top: brick.top,
+            bottom: StudType::Inlet,
+            ..StudInfo::FLAT
+        },
+        material: Material::Plastic,
+        name: None,
+        tags: Vec::new(),
+        mesh: None,
+        anchor: false,
+        physical: true,
+    }
+}
+
+/// Main colours of the LDraw colour table, plus direct 0x2RRGGBB colours
+pub fn ldraw_colour(code: u32) -> [u8; 4] {
+    if code & 0xFF00_0000 == 0x0200_0000 {
+        let [_, r, g, b] = code.to_be_bytes();
+        return [r, g, b, 255];
+    }
+
+    match code {
+        0 => [0x1B, 0x2A, 0x34, 255],
+        1 => [0x1E, 0x5A, 0xA8, 255],
+        2 => [0x00, 0x85, 0x2B, 255],
+        3 => [0x06, 0x9D, 0x9F, 255],
+        4 => [0xB4, 0x00, 0x00, 255],
+        5 => [0xD3, 0x35, 0x9D, 255],
+        6 => [0x54, 0x33, 0x24, 255],
+        7 => [0x8A, 0x92, 0x8D, 255],
+        8 => [0x54, 0x59, 0x55, 255],
+        9 => [0x97, 0xCB, 0xD9, 255],
+        10 => [0x58, 0xAB, 0x41, 255],
+        11 => [0x00, 0xAA, 0xA4, 255],
+        12 => [0xF0, 0x6D, 0x61, 255],
+        13 => [0xF6, 0xA9, 0xBB, 255],
+        14 => [0xFA, 0xC8, 0x0A, 255],
+        15 => [0xF4, 0xF4, 0xF4, 255],
+        17 => [0xAD, 0xD9, 0xA8, 255],
+        18 => [0xFF, 0xD6, 0x7F, 255],
+        19 => [0xD7, 0xBA, 0x8C, 255],
+        22 => [0x90, 0x1F, 0x76, 255],
+        25 => [0xD6, 0x79, 0x23, 255],
+        26 => [0xA8, 0x3D, 0x15, 255],
+        27 => [0xA5, 0xCA, 0x18, 255],
+        28 => [0x89, 0x7D, 0x62, 255],
+        70 => [0x5F, 0x31, 0x09, 255],
+        71 => [0x96, 0x96, 0x96, 255],
+        72 => [0x64, 0x64, 0x64, 255],
+        73 => [0x73, 0x96, 0xC8, 255],
+        84 => [0xAA, 0x7D, 0x55, 255],
+        85 => [0x46, 0x9B, 0xC3, 255],
+        272 => [0x19, 0x32, 0x5A, 255],
+        288 => [0x00, 0x45, 0x1A, 255],
+        308 => [0x35, 0x21, 0x00, 255],
+        320 => [0x72, 0x0E, 0x0F, 255],
+        326 => [0xFF, 0xF0, 0x3A, 255],
+        378 => [0x70, 0x8E, 0x7C, 255],
+        // Transparent
+        33 => [0x00, 0x20, 0xA0, 128],
+        34 => [0x06, 0x64, 0x32, 128],
+        36 => [0xC9, 0x1A, 0x09, 128],
+        40 => [0x63, 0x5F, 0x52, 128],
+        41 => [0xAE, 0xEF, 0xEC, 128],
+        43 => [0xC1, 0xDF, 0xF0, 128],
+        46 => [0xF5, 0xCD, 0x2F, 128],
+        47 => [0xFC, 0xFC, 0xFC, 128],
+        _ => [0x8A, 0x92, 0x8D, 255],
+    }
+}
@@ -0,0 +1,276 @@
+/*
+    Scene description shared by every on-disk format.
+
+    Formats only translate to and from a Scene, spawning and building models lives here.
+*/
+use anyhow::{Context, Result, bail};
+use bevy_ecs::prelude::*;
+use glam::{Quat, Vec3};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    common::model_graph::build_models,
+    ecs::{common::*, material::Material, parts::*, physics::*},
+    physics::PhysicsState,
+};
+
+pub mod binary;
+pub mod gltf;
+pub mod ldraw;
+pub mod prefab;
+pub mod print;
+pub mod rbxlx;
+pub mod terrain;
+pub mod text;
+pub mod vox;
+pub use binary::*;
+
+/// Scene files with this extension use the binary container, anything else is read as text.
+pub const BINARY_EXTENSION: &str = "fbs";
+
+/// Bump whenever PartDesc changes in a way older readers can't handle.
+pub const SCENE_VERSION: u32 = 3;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+    pub version: u32,
+    pub parts: Vec<PartDesc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+/// Everything needed to respawn a single part.
+/// Fields left out of a scene file fall back to the component defaults.
+pub struct PartDesc {
+    pub part: Part,
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub size: Vec3,
+    pub color: [u8; 4],
+    pub studs: StudInfo,
+    pub material: Material,
+    /// Name component, left out for unnamed parts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// MeshRef of mesh parts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mesh: Option<MeshRef>,
+    pub anchor: bool,
+    pub physical: bool,
+}
+
+impl Default for PartDesc {
+    fn default() -> Self {
+        PartDesc {
+            part: Part::default(),
+            position: Position::default().0,
+            rotation: Rotation::default().0,
+            size: Size::default().0,
+            color: Color::default().0,
+            studs: StudInfo::default(),
+            material: Material::default(),
+            name: None,
+            tags: Vec::new(),
+            mesh: None,
+            anchor: false,
+            physical: true,
+        }
+    }
+}
+
+impl PartDesc {
+    /// Components shared by every part, markers are handled by the caller.
+    pub fn bundle(&self) -> (Part, Position, Rotation, Size, Color, StudInfo, Material) {
+        (
+            self.part,
+            Position(self.position),
+            Rotation(self.rotation),
+            Size(self.size),
+            Color(self.color),
+            self.studs,
+            self.material,
+        )
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Scene {
+            version: SCENE_VERSION,
+            parts: Vec::new(),
+        }
+    }
+}
+
+impl Scene {
+    /// Collect every part in the world.
+    /// Parts under a model are plain entities with ChildOf, so they are picked up as well
+    ///     and models get rebuilt from stud connections on load.
+    pub fn from_world(world: &mut World) -> Self {
+        let mut query = world.query::<(
+            Entity,
+            &Part,
+            &Position,
+            &Rotation,
+            &Size,
+            &Color,
+            &StudInfo,
+            &Material,
+            Option<&Name>,
+            Option<&Tags>,
+            Option<&MeshRef>,
+            Has<Anchor>,
+            Has<Physical>,
+        )>();
+
+        let mut parts: Vec<_> = query.iter(world).collect();
+        // Keep output stable between saves
+        parts.sort_by_key(|(entity, ..)| *entity);
+
+        let parts = parts
+            .into_iter()
+            .map(
+                |(
+                    _,
+                    part,
+                    position,
+                    rotation,
+                    size,
+                    color,
+                    studs,
+                    material,
+                    name,
+                    tags,
+                    mesh,
+                    anchor,
+                    physical,
+                )| PartDesc {
+                    part: *part,
+                    position: position.0,
+                    rotation: rotation.0,
+                    size: size.0,
+                    color: color.0,
+                    studs: *studs,
+                    material: *material,
+                    name: name.map(|name| name.to_string()),
+                    tags: tags
+                        .map(|tags| tags.iter().cloned().collect())
+                        .unwrap_or_default(),
+                    mesh: mesh.cloned(),
+                    anchor,
+                    physical,
+                },
+            )
+            .collect();
+
+        Scene {
+            version: SCENE_VERSION,
+            parts,
+        }
+    }
+
+    /// Make sure we are able to read this scene
+    pub fn check_version(&self) -> Result<()> {
+        if self.version == 0 || self.version > SCENE_VERSION {
+            bail!(
+                "Unsupported scene version {}, expected at most {}",
+                self.version,
+                SCENE_VERSION
+            );
+        }
+        Ok(())
+    }
+
+    /// Spawn parts without building models or physics.
+    /// Use this if an init schedule will run build_models afterwards (e.g. Game::new)
+    pub fn spawn(&self, world: &mut World) -> Vec<Entity> {
+        self.parts
+            .iter()
+            .map(|desc| spawn_part(world, desc))
+            .collect()
+    }
+
+    /// Spawn parts and build models and physics for them.
+    /// Expects a world set up with PhysicsState::consume and no other parts in it.
+    pub fn load(&self, world: &mut World) -> Vec<Entity> {
+        let entities = self.spawn(world);
+        build_scene(world);
+        entities
+    }
+}
+
+/// Spawn a single described part with its markers.
+pub fn spawn_part(world: &mut World, desc: &PartDesc) -> Entity {
+    let mut entity = world.spawn(desc.bundle());
+    if desc.anchor {
+        entity.insert(Anchor);
+    }
+    if let Some(name) = &desc.name {
+        entity.insert(Name::new(name.clone()));
+    }
+    if !desc.tags.is_empty() {
+        entity.insert(Tags::new(desc.tags.iter().cloned()));
+    }
+    if let Some(mesh) = &desc.mesh {
+        entity.insert(mesh.clone());
+    }
+    // Physical is required by Part, so take it off again
+    if !desc.physical && !desc.anchor {
+        entity.remove::<Physical>();
+    }
+    entity.id()
+}
+
+/// Run the same model and physics setup as the init schedule on freshly spawned parts.
+pub fn build_scene(world: &mut World) {
+    let mut schedule = Schedule::default();
+    schedule.add_systems((build_models, PhysicsState::setup_system()).chain());
+    schedule.run(world);
+}
+
+/// Read a scene file, spawn its parts and build models and physics.
+/// Binary scenes are streamed straight into the world.
+pub fn load_scene(world: &mut World, path: impl AsRef<Path>) -> Result<Vec<Entity>> {
+    let path = path.as_ref();
+
+    let entities = if is_binary(path) {
+        let file =
+            File::open(path).with_context(|| format!("Couldn't open scene {}", path.display()))?;
+        BinarySceneReader::new(BufReader::new(file))?.spawn(world)?
+    } else {
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read scene {}", path.display()))?;
+        Scene::from_ron(&source)?.spawn(world)
+    };
+
+    build_scene(world);
+    Ok(entities)
+}
+
+/// Save every part in the world into a scene file.
+pub fn save_scene(world: &mut World, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    let scene = Scene::from_world(world);
+
+    let file =
+        File::create(path).with_context(|| format!("Couldn't write scene {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    if is_binary(path) {
+        write_binary(&scene, &mut writer)?;
+    } else {
+        writer.write_all(scene.to_ron()?.as_bytes())?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn is_binary(path: &Path) -> bool {
+    path.extension().and_then(|s| s.to_str()) == Some(BINARY_EXTENSION)
+}
@@ -0,0 +1,228 @@
+/*
+    Prefabs, a built Model saved so copies can be stamped out.
+
+    Parts are stored relative to the pivot (bottom center of the model) along with the stud
+    connections between them, so spawning a copy doesn't need build_models at all.
+    Stored as RON like text scenes, AssetCache picks up *.prefab files.
+
+    (
+        version: 1,
+        parts: [
+            (position: (0.0, 0.5, 0.0)),
+            (position: (0.0, 1.5, 0.0), color: (255, 0, 0, 255)),
+        ],
+        connections: [(0, 1)],
+    )
+*/
+use anyhow::{Context, Result, anyhow, bail};
+use bevy_ecs::prelude::*;
+use bevy_platform::collections::{HashMap, HashSet};
+use glam::{Quat, Vec3};
+use petgraph::prelude::UnGraphMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    common::asset_cache::AssetCache,
+    ecs::{
+        common::*,
+        material::Material,
+        model::Model,
+        parts::*,
+        physics::{Physical, QPhysics},
+    },
+    physics::{PhysicsState, model_body},
+    scene::{PartDesc, SCENE_VERSION, spawn_part},
+};
+
+pub const PREFAB_EXTENSION: &str = "prefab";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Prefab {
+    pub version: u32,
+    /// Positions and rotations are relative to the pivot
+    pub parts: Vec<PartDesc>,
+    /// Stud connections, as indices into parts
+    pub connections: Vec<(u32, u32)>,
+}
+
+impl Prefab {
+    /// Capture a model's parts and graph.
+    /// The model's rotation is taken off so the prefab is stored upright.
+    pub fn from_model(world: &mut World, model: Entity) -> Result<Self> {
+        let (children, graph) = {
+            let mut query = world.query::<(&Model, &Children)>();
+            let (item, children) = query
+                .get(world, model)
+                .map_err(|_| anyhow!("{} isn't a model", model))?;
+            let children: Vec<Entity> = children.iter().collect();
+            let edges: Vec<(Entity, Entity)> =
+                item.graph.all_edges().map(|(a, b, _)| (a, b)).collect();
+            (children, edges)
+        };
+
+        let mut query = world.query::<(
+            &Part,
+            &Position,
+            &Rotation,
+            &Size,
+            &Color,
+            &StudInfo,
+            &Material,
+            Option<&Name>,
+            Option<&Tags>,
+            Option<&MeshRef>,
+            Has<Physical>,
+        )>();
+        let parts = children
+            .iter()
+            .map(|&child| query.get(world, child))
+            .collect::<Result<Vec<_>, _>>()
+            .context("Model child isn't a part")?;
+        let Some((_, _, frame, ..)) = parts.first() else {
+            bail!("{} has no parts", model);
+        };
+
+        // Parts of a model move together, any of them gives the model's rotation
+        let inverse = frame.0.inverse();
+        let mut min = Vec3::MAX;
+        let mut max = Vec3::MIN;
+        for (_, position, rotation, size, ..) in &parts {
+            let local = inverse * position.0;
+            // Parts can be turned right angles within the model
+            let half = ((inverse * rotation.0) * size.0).abs() / 2.0;
+            min = min.min(local - half);
+            max = max.max(local + half);
+        }
+        let pivot = Vec3::new((min.x + max.x) / 2.0, min.y, (min.z + max.z) / 2.0);
+
+        let descs = parts
+            .iter()
+            .map(
+                |(
+                    part,
+                    position,
+                    rotation,
+                    size,
+                    color,
+                    studs,
+                    material,
+                    name,
+                    tags,
+                    mesh,
+                    physical,
+                )| {
+                    PartDesc {
+                        part: **part,
+                        position: inverse * position.0 - pivot,
+                        rotation: (inverse * rotation.0).normalize(),
+                        size: size.0,
+                        color: color.0,
+                        studs: **studs,
+                        material: **material,
+                        name: name.map(|name| name.to_string()),
+                        tags: tags
+                            .map(|tags| tags.iter().cloned().collect())
+                            .unwrap_or_default(),
+                        mesh: mesh.cloned(),
+                        anchor: false,
+                        physical: *physical,
+                    }
+                },
+            )
+            .collect();
+
+        let index: HashMap<Entity, u32> = children
+            .iter()
+            .enumerate()
+            .map(|(i, &child)| (child, i as u32))
+            .collect();
+        let connections = graph.iter().map(|(a, b)| (index[a], index[b])).collect();
+
+        Ok(Prefab {
+            version: SCENE_VERSION,
+            parts: descs,
+            connections,
+        })
+    }
+
+    /// Spawn a copy with its pivot at `position`, as a Model with its graph and rigid body
+    ///     already in place.
+    pub fn spawn(&self, world: &mut World, position: Vec3, rotation: Quat) -> Result<Entity> {
+        if self.parts.is_empty() {
+            bail!("Prefab has no parts");
+        }
+        if !world.contains_resource::<PhysicsState>() {
+            bail!("Prefabs need a world with PhysicsState");
+        }
+
+        let entities: Vec<Entity> = self
+            .parts
+            .iter()
+            .map(|desc| {
+                let placed = PartDesc {
+                    position: position + rotation * desc.position,
+                    rotation: (rotation * desc.rotation).normalize(),
+                    anchor: false,
+                    ..desc.clone()
+                };
+                spawn_part(world, &placed)
+            })
+            .collect();
+
+        let mut graph: UnGraphMap<Entity, ()> = UnGraphMap::new();
+        for &entity in &entities {
+            graph.add_node(entity);
+        }
+        for &(a, b) in &self.connections {
+            let (Some(&a), Some(&b)) = (entities.get(a as usize), entities.get(b as usize)) else {
+                bail!("Prefab connection ({}, {}) is out of range", a, b);
+            };
+            graph.add_edge(a, b, ());
+        }
+
+        let model = world
+            .spawn(Model {
+                graph,
+                anchors: HashSet::new(),
+                dirty: false,
+            })
+            .add_children(&entities)
+            .id();
+
+        // Same body setup_models would give it
+        let (body, shapes) = world.resource_scope(|world, mut state: Mut<PhysicsState>| {
+            let mut query = world.query::<QPhysics>();
+            let parts = entities
+                .iter()
+                .map(|&entity| query.get(world, entity))
+                .collect::<Result<Vec<_>, _>>()?;
+            let assets = world.get_resource::<AssetCache>();
+            Ok::<_, anyhow::Error>(model_body(&mut state, assets, model, &parts, false))
+        })?;
+        for (&entity, shape) in entities.iter().zip(shapes) {
+            world.entity_mut(entity).insert(shape);
+        }
+        world.entity_mut(model).insert(body);
+
+        Ok(model)
+    }
+
+    /// Parse a prefab from RON source
+    pub fn from_ron(source: &str) -> Result<Self> {
+        let prefab: Prefab = ron::from_str(source).context("Couldn't parse prefab")?;
+        if prefab.version == 0 || prefab.version > SCENE_VERSION {
+            bail!(
+                "Unsupported prefab version {}, expected at most {}",
+                prefab.version,
+                SCENE_VERSION
+            );
+        }
+        Ok(prefab)
+    }
+
+    /// Write prefab into RON source
+    pub fn to_ron(&self) -> Result<String> {
+        let config = ron::ser::PrettyConfig::new().depth_limit(3);
+        Ok(ron::ser::to_string_pretty(self, config)?)
+    }
+}
@@ -0,0 +1,413 @@
+/*
+    STL / OBJ export of a single Model for 3D printing.
+
+    Parts of a model are only ever turned right angles from each other, so in the first part's
+    frame they are all axis-aligned boxes. Any other turn can't be exported.
+    Every box bound (and every stud line of a studded top) goes into a grid, cells covered by a
+    part are filled, and only faces between a filled and an empty cell are written. That drops the
+    faces between snapped bricks and keeps the surface closed, since neighbouring faces always
+    share grid corners.
+
+    Exposed studded cells get a real stud, stitched into the top face so it stays one surface.
+    Parts that aren't boxes (wedges, balls, cylinders, meshes) are added as their own closed
+    shells overlapping the rest, slicers merge overlapping shells when printing.
+    Output is in millimetres with the lowest point on Y = 0.
+*/
+use anyhow::{Result, anyhow};
+use bevy_ecs::prelude::*;
+use bevy_platform::collections::HashMap;
+use glam::{Affine3A, Quat, Vec3};
+use std::{f32::consts::TAU, io::Write};
+
+use crate::{
+    common::{asset_cache::AssetCache, model_graph::right_angle_axes},
+    ecs::{
+        common::*,
+        material::Material,
+        model::Model,
+        parts::{MeshRef, Part, StudInfo, StudType},
+    },
+    render::bricks::BrickVertex,
+    scene::gltf::shape_mesh,
+};
+
+pub const STUD_PITCH_MM: f32 = 8.0;
+pub const BRICK_HEIGHT_MM: f32 = 9.6;
+
+/// Stud radius and height in engine units (4.8mm wide, 1.7mm tall)
+const STUD_RADIUS: f32 = 0.3;
+const STUD_HEIGHT: f32 = 1.7 / BRICK_HEIGHT_MM;
+/// Has to be a multiple of 8 so the top face corners and edge middles line up with it
+const STUD_SEGMENTS: usize = 16;
+
+const EPSILON: f32 = 1e-4;
+
+#[derive(Debug, Default)]
+pub struct TriMesh {
+    pub vertices: Vec<Vec3>,
+    /// Counter-clockwise seen from outside
+    pub triangles: Vec<[u32; 3]>,
+    lookup: HashMap<[i64; 3], u32>,
+}
+
+impl TriMesh {
+    /// Add a vertex, reusing one that's already at the same spot
+    fn vertex(&mut self, position: Vec3) -> u32 {
+        let key = (position / EPSILON).round().as_i64vec3().to_array();
+        if let Some(&index) = self.lookup.get(&key) {
+            return index;
+        }
+        self.vertices.push(position);
+        let index = self.vertices.len() as u32 - 1;
+        self.lookup.insert(key, index);
+        index
+    }
+
+    fn triangle(&mut self, a: Vec3, b: Vec3, c: Vec3) {
+        let tri = [self.vertex(a), self.vertex(b), self.vertex(c)];
+        self.triangles.push(tri);
+    }
+
+    fn quad(&mut self, a: Vec3, b: Vec3, c: Vec3, d: Vec3) {
+        self.triangle(a, b, c);
+        self.triangle(a, c, d);
+    }
+
+    /// Add another mesh's triangles without sharing any vertices with this one
+    fn append(&mut self, other: TriMesh) {
+        let offset = self.vertices.len() as u32;
+        self.vertices.extend(other.vertices);
+        self.triangles.extend(
+            other
+                .triangles
+                .iter()
+                .map(|triangle| triangle.map(|i| i + offset)),
+        );
+    }
+
+    pub fn normal(&self, triangle: &[u32; 3]) -> Vec3 {
+        let [a, b, c] = triangle.map(|i| self.vertices[i as usize]);
+        (b - a).cross(c - a).normalize_or_zero()
+    }
+}
+
+/// Write a model as binary STL
+pub fn export_model_stl<W: Write>(world: &mut World, model: Entity, writer: &mut W) -> Result<()> {
+    let mesh = build_model_mesh(world, model)?;
+
+    let mut header = [0u8; 80];
+    let name = b"freebricks model";
+    header[..name.len()].copy_from_slice(name);
+    writer.write_all(&header)?;
+    writer.write_all(&(mesh.triangles.len() as u32).to_le_bytes())?;
+
+    for triangle in &mesh.triangles {
+        let normal = mesh.normal(triangle);
+        let points = triangle.map(|i| mesh.vertices[i as usize]);
+        for v in std::iter::once(normal).chain(points) {
+            for value in v.to_array() {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        writer.write_all(&0u16.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Write a model as Wavefront OBJ
+pub fn export_model_obj<W: Write>(world: &mut World, model: Entity, writer: &mut W) -> Result<()> {
+    let mesh = build_model_mesh(world, model)?;
+
+    writeln!(writer, "# freebricks model, millimetres")?;
+    writeln!(writer, "o model")?;
+    for v in &mesh.vertices {
+        writeln!(writer, "v {} {} {}", v.x, v.y, v.z)?;
+    }
+    for [a, b, c] in &mesh.triangles {
+        writeln!(writer, "f {} {} {}", a + 1, b + 1, c + 1)?;
+    }
+    Ok(())
+}
+
+/// Build the closed surface of a model's parts
+pub fn build_model_mesh(world: &mut World, model: Entity) -> Result<TriMesh> {
+    let children: Vec<Entity> = world
+        .query_filtered::<&Children, With<Model>>()
+        .get(world, model)
+        .map_err(|_| anyhow!("{} isn't a model", model))?
+        .iter()
+        .collect();
+
+    let mut query = world.query::<(
+        &Part,
+        Option<&MeshRef>,
+        &Position,
+        &Rotation,
+        &Size,
+        &StudInfo,
+    )>();
+    let parts: Vec<_> = children
+        .iter()
+        .filter_map(|&child| query.get(world, child).ok())
+        .collect();
+    let Some((_, _, _, first_rotation, _, _)) = parts.first() else {
+        return Err(anyhow!("{} has no parts", model));
+    };
+
+    // Undo the first part's rotation so every part is an axis-aligned box
+    let inverse: Quat = first_rotation.0.inverse();
+    let boxes = parts
+        .iter()
+        .filter(|(part, ..)| is_box(part))
+        .map(|(_, _, position, rotation, size, studs)| {
+            let turn = inverse * rotation.0;
+            let axes = right_angle_axes(turn)
+                .ok_or_else(|| anyhow!("{} has a part that isn't turned a right angle", model))?;
+            let center = inverse * position.0;
+            let half = (turn * size.0).abs() / 2.0;
+            Ok(PartBox {
+                min: center - half,
+                max: center + half,
+                // Studs only get cut into a top that still faces up
+                studded: studs.top == StudType::Outlet && axes[1] == (1, true),
+            })
+        })
+        .collect::<Result<Vec<PartBox>>>()?;
+
+    let mut mesh = TriMesh::default();
+    if !boxes.is_empty() {
+        surface(&boxes, &mut mesh);
+    }
+
+    let assets = world.get_resource::<AssetCache>();
+    for (part, mesh_ref, position, rotation, size, _) in &parts {
+        if is_box(part) {
+            continue;
+        }
+        let uniform = part.to_uniform(
+            &Position(inverse * position.0),
+            &Rotation(inverse * rotation.0),
+            size,
+            &Color::default(),
+            &StudInfo::default(),
+            &Material::default(),
+        );
+        let (vertices, indices) = shape_mesh(**part, *mesh_ref, assets);
+        let transform = Affine3A::from_cols_array_2d(&uniform.model);
+        mesh.append(shell(&vertices, &indices, transform));
+    }
+
+    // Millimetres, resting on Y = 0
+    let scale = Vec3::new(STUD_PITCH_MM, BRICK_HEIGHT_MM, STUD_PITCH_MM);
+    let min = mesh
+        .vertices
+        .iter()
+        .fold(Vec3::splat(f32::MAX), |acc, v| acc.min(*v));
+    for v in &mut mesh.vertices {
+        *v = (*v - min) * scale;
+    }
+    Ok(mesh)
+}
+
+/*
+    Helper functions
+*/
+
+/// Parts the surface grid can build
+fn is_box(part: &Part) -> bool {
+    !matches!(part, Part::Wedge | Part::Ball | Part::Cylinder | Part::Mesh)
+}
+
+/// Closed surface of a part's own mesh, placed with its transform
+fn shell(vertices: &[BrickVertex], indices: &[u32], transform: Affine3A) -> TriMesh {
+    let mut shell = TriMesh::default();
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| {
+            let vertex = Vec3::from(vertices[triangle[i] as usize].position);
+            shell.vertex(transform.transform_point3(vertex))
+        });
+        // Collapsed corners of the mesh (ball poles, the wedge's back) leave nothing to print
+        if a == b || b == c || c == a {
+            continue;
+        }
+        // Meshes are wound clockwise seen from outside
+        shell.triangles.push([a, c, b]);
+    }
+    shell
+}
+
+struct PartBox {
+    min: Vec3,
+    max: Vec3,
+    studded: bool,
+}
+
+/// Sorted grid lines along one axis
+fn grid_lines(mut values: Vec<f32>) -> Vec<f32> {
+    values.sort_by(f32::total_cmp);
+    values.dedup_by(|a, b| (*a - *b).abs() < EPSILON);
+    values
+}
+
+fn range(lines: &[f32], min: f32, max: f32) -> std::ops::Range<usize> {
+    let start = lines.partition_point(|&v| v < min - EPSILON);
+    let end = lines.partition_point(|&v| v < max - EPSILON);
+    start..end
+}
+
+fn surface(boxes: &[PartBox], mesh: &mut TriMesh) {
+    let mut xs = Vec::new();
+    let mut ys = Vec::new();
+    let mut zs = Vec::new();
+    for b in boxes {
+        ys.extend([b.min.y, b.max.y]);
+        if b.studded {
+            // One cell per stud so studs can be cut into the top face
+            let (w, d) = ((b.max.x - b.min.x).round(), (b.max.z - b.min.z).round());
+            xs.extend((0..=w as i32).map(|i| b.min.x + i as f32));
+            zs.extend((0..=d as i32).map(|i| b.min.z + i as f32));
+        }
+        xs.extend([b.min.x, b.max.x]);
+        zs.extend([b.min.z, b.max.z]);
+    }
+    let (xs, ys, zs) = (grid_lines(xs), grid_lines(ys), grid_lines(zs));
+    let (nx, ny, nz) = (xs.len() - 1, ys.len() - 1, zs.len() - 1);
+
+    // Which part fills a cell, if any
+    let mut cells: Vec<Option<usize>> = vec![None; nx * ny * nz];
+    let index = |x: usize, y: usize, z: usize| (x * ny + y) * nz + z;
+    for (part, b) in boxes.iter().enumerate() {
+        for x in range(&xs, b.min.x, b.max.x) {
+            for y in range(&ys, b.min.y, b.max.y) {
+                for z in range(&zs, b.min.z, b.max.z) {
+                    cells[index(x, y, z)] = Some(part);
+                }
+            }
+        }
+    }
+    let filled = |x: isize, y: isize, z: isize| -> bool {
+        if x < 0 || y < 0 || z < 0 || x >= nx as isize || y >= ny as isize || z >= nz as isize {
+            return false;
+        }
+        cells[index(x as usize, y as usize, z as usize)].is_some()
+    };
+
+    for x in 0..nx {
+        for y in 0..ny {
+            for z in 0..nz {
+                let Some(part) = cells[index(x, y, z)] else {
+                    continue;
+                };
+                let (xi, yi, zi) = (x as isize, y as isize, z as isize);
+                let min = Vec3::new(xs[x], ys[y], zs[z]);
+                let max = Vec3::new(xs[x + 1], ys[y + 1], zs[z + 1]);
+
+                let c = |px: f32, py: f32, pz: f32| Vec3::new(px, py, pz);
+                if !filled(xi + 1, yi, zi) {
+                    mesh.quad(
+                        c(max.x, min.y, min.z),
+                        c(max.x, max.y, min.z),
+                        c(max.x, max.y, max.z),
+                        c(max.x, min.y, max.z),
+                    );
+                }
+                if !filled(xi - 1, yi, zi) {
+                    mesh.quad(
+                        c(min.x, min.y, min.z),
+                        c(min.x, min.y, max.z),
+                        c(min.x, max.y, max.z),
+                        c(min.x, max.y, min.z),
+                    );
+                }
+                if !filled(xi, yi - 1, zi) {
+                    mesh.quad(
+                        c(min.x, min.y, min.z),
+                        c(max.x, min.y, min.z),
+                        c(max.x, min.y, max.z),
+                        c(min.x, min.y, max.z),
+                    );
+                }
+                if !filled(xi, yi, zi + 1) {
+                    mesh.quad(
+                        c(min.x, min.y, max.z),
+                        c(max.x, min.y, max.z),
+                        c(max.x, max.y, max.z),
+                        c(min.x, max.y, max.z),
+                    );
+                }
+                if !filled(xi, yi, zi - 1) {
+                    mesh.quad(
+                        c(min.x, min.y, min.z),
+                        c(min.x, max.y, min.z),
+                        c(max.x, max.y, min.z),
+                        c(max.x, min.y, min.z),
+                    );
+                }
+                if !filled(xi, yi + 1, zi) {
+                    let b = &boxes[part];
+                    let stud_cell = b.studded
+                        && (max.y - b.max.y).abs() < EPSILON
+                        && (max.x - min.x - 1.0).abs() < EPSILON
+                        && (max.z - min.z - 1.0).abs() < EPSILON
+                        && ((min.x - b.min.x) - (min.x - b.min.x).round()).abs() < EPSILON
+                        && ((min.z - b.min.z) - (min.z - b.min.z).round()).abs() < EPSILON;
+
+                    if stud_cell {
+                        stud(mesh, min, max);
+                    } else {
+                        mesh.quad(
+                            c(min.x, max.y, min.z),
+                            c(min.x, max.y, max.z),
+                            c(max.x, max.y, max.z),
+                            c(max.x, max.y, min.z),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Top face of a 1x1 cell with a stud standing on it.
+/// The face only uses its four corners on the cell edges, so it meets its neighbours exactly.
+fn stud(mesh: &mut TriMesh, min: Vec3, max: Vec3) {
+    let y = max.y;
+    let center = Vec3::new((min.x + max.x) / 2.0, y, (min.z + max.z) / 2.0);
+    let top = center + Vec3::Y * STUD_HEIGHT;
+
+    // Angle 0 points along +X, going towards +Z
+    let ring: Vec<Vec3> = (0..STUD_SEGMENTS)
+        .map(|i| {
+            let angle = i as f32 / STUD_SEGMENTS as f32 * TAU;
+            center + Vec3::new(angle.cos(), 0.0, angle.sin()) * STUD_RADIUS
+        })
+        .collect();
+    let corners = [
+        Vec3::new(max.x, y, max.z),
+        Vec3::new(min.x, y, max.z),
+        Vec3::new(min.x, y, min.z),
+        Vec3::new(max.x, y, min.z),
+    ];
+
+    let quarter = STUD_SEGMENTS / 4;
+    for q in 0..4 {
+        // Edge between the previous corner and this one faces the ring point at q * 90 degrees
+        let edge_mid = ring[q * quarter];
+        mesh.triangle(corners[(q + 3) % 4], edge_mid, corners[q]);
+
+        // Ring points of this quadrant fan out from its corner
+        for i in q * quarter..(q + 1) * quarter {
+            let next = ring[(i + 1) % STUD_SEGMENTS];
+            mesh.triangle(corners[q], ring[i], next);
+        }
+    }
+
+    // Stud wall and cap
+    for i in 0..STUD_SEGMENTS {
+        let a = ring[i];
+        let b = ring[(i + 1) % STUD_SEGMENTS];
+        let up = Vec3::Y * STUD_HEIGHT;
+        mesh.quad(a, a + up, b + up, b);
+        mesh.triangle(top, b + up, a + up);
+    }
+}
@@ -0,0 +1,353 @@
+/*
+    Classic XML place (.rbxlx / .rbxmx) importer.
+
+    Only the Part and WedgePart classes map onto our parts. Workspace, Model and Folder are walked through,
+    every other class is counted so we know what the engine still lacks.
+
+    Studs are 1 unit in both, but a brick is 1.2 studs tall there and 1 unit here.
+*/
+use anyhow::{Context, Result, anyhow};
+use bevy_ecs::prelude::*;
+use glam::{Mat3, Quat, Vec3};
+use roxmltree::{Document, Node};
+use std::{collections::BTreeMap, fs, path::Path};
+use tracing::warn;
+
+use crate::{
+    ecs::{
+        material::Material,
+        parts::{Part, StudInfo, StudType},
+    },
+    scene::{PartDesc, Scene},
+    utils::rotation::bake_upright_rotation,
+};
+
+pub const STUDS_PER_BRICK: f32 = 1.2;
+
+const CONTAINER_CLASSES: &[&str] = &["Workspace", "Model", "Folder"];
+
+pub struct RbxlxImport {
+    pub scene: Scene,
+    /// Class name (or Part shape) and how many instances were skipped
+    pub unsupported_classes: BTreeMap<String, usize>,
+}
+
+impl RbxlxImport {
+    /// Spawn the imported parts and build models out of the ones that snap
+    pub fn load(&self, world: &mut World) -> Vec<Entity> {
+        self.scene.load(world)
+    }
+}
+
+pub fn import_rbxlx(path: impl AsRef<Path>) -> Result<RbxlxImport> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path)
+        .with_context(|| format!("Couldn't read place {}", path.display()))?;
+    parse_rbxlx(&source)
+}
+
+pub fn parse_rbxlx(source: &str) -> Result<RbxlxImport> {
+    let document = Document::parse(source).context("Couldn't parse place XML")?;
+    let root = document.root_element();
+    if root.tag_name().name() != "roblox" {
+        return Err(anyhow!(
+            "Not a place file, root is <{}>",
+            root.tag_name().name()
+        ));
+    }
+
+    let mut import = RbxlxImport {
+        scene: Scene::default(),
+        unsupported_classes: BTreeMap::new(),
+    };
+
+    // Places have services at the top, only Workspace holds parts. Model files don't.
+    let items: Vec<Node> = items(root).collect();
+    let workspace = items.iter().find(|item| class(item) == "Workspace");
+    match workspace {
+        Some(workspace) => walk(*workspace, &mut import)?,
+        None => {
+            for item in items {
+                walk(item, &mut import)?;
+            }
+        }
+    }
+
+    for (name, count) in &import.unsupported_classes {
+        warn!(
+            "Place import: unsupported class {} ({} instances)",
+            name, count
+        );
+    }
+    Ok(import)
+}
+
+fn walk(item: Node, import: &mut RbxlxImport) -> Result<()> {
+    let class = class(&item);
+
+    if CONTAINER_CLASSES.contains(&class) {
+        for child in items(item) {
+            walk(child, import)?;
+        }
+    } else if class == "Part" || class == "WedgePart" {
+        match read_part(item, class)? {
+            Ok(desc) => import.scene.parts.push(desc),
+            Err(unsupported) => *import.unsupported_classes.entry(unsupported).or_insert(0) += 1,
+        }
+        // Anything attached to a part (decals, scripts, welds) is something we don't do yet
+        for child in items(item) {
+            walk(child, import)?;
+        }
+    } else {
+        *import
+            .unsupported_classes
+            .entry(class.to_string())
+            .or_insert(0) += 1;
+    }
+    Ok(())
+}
+
+/// Inner Err is a part we can't represent yet, named for the report
+fn read_part(item: Node, class: &str) -> Result<Result<PartDesc, String>> {
+    let properties = item
+        .children()
+        .find(|n| n.has_tag_name("Properties"))
+        .ok_or(anyhow!("Part without Properties"))?;
+    let property = |name: &str| {
+        properties
+            .children()
+            .find(|n| n.is_element() && n.attribute("name") == Some(name))
+    };
+
+    // PartType: Ball = 0, Block = 1, Cylinder = 2
+    let part = match property("shape").map(text).transpose()?.unwrap_or("1") {
+        _ if class == "WedgePart" => Part::Wedge,
+        "1" => Part::Brick,
+        "0" => Part::Ball,
+        "2" => Part::Cylinder,
+        other => return Ok(Err(format!("Part (shape {})", other))),
+    };
+
+    let (position, matrix) = match property("CFrame").or(property("CoordinateFrame")) {
+        Some(cframe) => read_cframe(cframe)?,
+        None => (Vec3::ZERO, Mat3::IDENTITY),
+    };
+    let size = match property("size").or(property("Size")) {
+        Some(size) => read_vector3(size)?,
+        None => Vec3::new(4.0, STUDS_PER_BRICK, 2.0),
+    };
+
+    let position = position / Vec3::new(1.0, STUDS_PER_BRICK, 1.0);
+    let size = size / Vec3::new(1.0, STUDS_PER_BRICK, 1.0);
+    // Their axis is always X, ours is the longest side. Short wide ones (wheels) can't be told apart
+    if part == Part::Cylinder && (size.x <= size.y || size.x <= size.z) {
+        return Ok(Err("Part (Cylinder)".to_string()));
+    }
+    // Turning a wedge changes which way its slope faces, so it keeps its rotation
+    let (rotation, size) = match part {
+        Part::Wedge => (Quat::from_mat3(&matrix).normalize(), size),
+        _ => bake_upright_rotation(matrix, size),
+    };
+
+    let [r, g, b] = match property("Color3uint8").or(property("Color")) {
+        Some(color) => read_color3(color)?,
+        None => match property("BrickColor") {
+            Some(color) => brick_color(text(color)?.parse()?),
+            None => brick_color(194),
+        },
+    };
+    let transparency: f32 = match property("Transparency") {
+        Some(t) => text(t)?.parse()?,
+        None => 0.0,
+    };
+    let alpha = ((1.0 - transparency.clamp(0.0, 1.0)) * 255.0).round() as u8;
+
+    let name = property("Name")
+        .map(text)
+        .transpose()?
+        .filter(|name| !name.is_empty())
+        .map(str::to_string);
+
+    let material = match property("Material") {
+        Some(material) => material_type(text(material)?.parse()?),
+        None => Material::Plastic,
+    };
+
+    let anchor = match property("Anchored") {
+        Some(anchored) => text(anchored)? == "true",
+        None => false,
+    };
+
+    let surface = |name: &str, default: StudType| -> Result<StudType> {
+        match property(name) {
+            Some(surface) => Ok(surface_type(text(surface)?.parse()?)),
+            None => Ok(default),
+        }
+    };
+    // Their front looks down -Z, ours is the Z+ face
+    let studs = StudInfo {
+        top: surface("TopSurface", StudType::Outlet)?,
+        bottom: surface("BottomSurface", StudType::Inlet)?,
+        front: surface("BackSurface", StudType::Flat)?,
+        back: surface("FrontSurface", StudType::Flat)?,
+        left: surface("LeftSurface", StudType::Flat)?,
+        right: surface("RightSurface", StudType::Flat)?,
+    };
+    // A turn folded into the size has to turn the faces with it
+    let studs = if rotation == Quat::IDENTITY {
+        studs.rotated(Quat::from_mat3(&matrix).normalize())
+    } else {
+        studs
+    };
+
+    Ok(Ok(PartDesc {
+        part,
+        position,
+        rotation,
+        size,
+        color: [r, g, b, alpha],
+        studs,
+        material,
+        name,
+        tags: Vec::new(),
+        mesh: None,
+        anchor,
+        physical: true,
+    }))
+}
+
+/*
+    Helper functions
+*/
+
+fn items<'a, 'input>(node: Node<'a, 'input>) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(|n| n.has_tag_name("Item"))
+}
+
+fn class<'a>(item: &Node<'a, '_>) -> &'a str {
+    item.attribute("class").unwrap_or_default()
+}
+
+fn text<'a>(node: Node<'a, '_>) -> Result<&'a str> {
+    Ok(node.text().unwrap_or_default().trim())
+}
+
+fn child_f32(node: Node, name: &str) -> Result<f32> {
+    let child = node
+        .children()
+        .find(|n| n.has_tag_name(name))
+        .ok_or(anyhow!("Missing <{}>", name))?;
+    text(child)?
+        .parse()
+        .with_context(|| format!("Invalid <{}>", name))
+}
+
+fn read_vector3(node: Node) -> Result<Vec3> {
+    Ok(Vec3::new(
+        child_f32(node, "X")?,
+        child_f32(node, "Y")?,
+        child_f32(node, "Z")?,
+    ))
+}
+
+/// R00 R01 R02 is the first row of the rotation matrix
+fn read_cframe(node: Node) -> Result<(Vec3, Mat3)> {
+    let mut r = [0.0; 9];
+    for (i, value) in r.iter_mut().enumerate() {
+        *value = child_f32(node, &format!("R{}{}", i / 3, i % 3))?;
+    }
+    let matrix = Mat3::from_cols_array(&r).transpose();
+    Ok((read_vector3(node)?, matrix))
+}
+
+/// Either packed 0xAARRGGBB (Color3uint8) or <R> <G> <B> floats (older Color3)
+fn read_color3(node: Node) -> Result<[u8; 3]> {
+    if node.children().any(|n| n.has_tag_name("R")) {
+        let channel = |name| -> Result<u8> {
+            Ok((child_f32(node, name)?.clamp(0.0, 1.0) * 255.0).round() as u8)
+        };
+        return Ok([channel("R")?, channel("G")?, channel("B")?]);
+    }
+    let packed: u32 = text(node)?.parse().context("Invalid Color3")?;
+    let [_, r, g, b] = packed.to_be_bytes();
+    Ok([r, g, b])
+}
+
+/// SurfaceType: Smooth = 0, Glue = 1, Weld = 2, Studs = 3, Inlet = 4, Universal = 5,
+///     Hinge = 6, Motor = 7, SteppingMotor = 8, SmoothNoOutlines = 10
+fn surface_type(value: u32) -> StudType {
+    match value {
+        1 => StudType::Glue,
+        2 => StudType::Weld,
+        3 => StudType::Outlet,
+        4 => StudType::Inlet,
+        5 => StudType::Universal,
+        6 => StudType::Hinge,
+        7 => StudType::Motor,
+        8 => StudType::SteppingMotor,
+        10 => StudType::Smooth,
+        _ => StudType::Flat,
+    }
+}
+
+/// Material enum, the closest of ours for each of theirs
+fn material_type(value: u32) -> Material {
+    match value {
+        // Wood, WoodPlanks
+        512 | 528 => Material::Wood,
+        // CorrodedMetal, DiamondPlate, Foil, Metal
+        1040 | 1056 | 1072 | 1088 => Material::Metal,
+        // Ice, Glacier
+        1536 | 1552 => Material::Ice,
+        1568 => Material::Glass,
+        288 => Material::Neon,
+        _ => Material::Plastic,
+    }
+}
+
+/// Common BrickColor numbers
+pub fn brick_color(number: u32) -> [u8; 3] {
+    match number {
+        1 => [0xF2, 0xF3, 0xF3],
+        5 => [0xD7, 0xC5, 0x9A],
+        9 => [0xE8, 0xBA, 0xC8],
+        11 => [0x80, 0xBB, 0xDB],
+        18 => [0xCC, 0x8E, 0x69],
+        21 => [0xC4, 0x28, 0x1C],
+        23 => [0x0D, 0x69, 0xAC],
+        24 => [0xF5, 0xCD, 0x30],
+        26 => [0x1B, 0x2A, 0x35],
+        28 => [0x28, 0x7F, 0x47],
+        37 => [0x4B, 0x97, 0x4B],
+        38 => [0xA0, 0x5F, 0x35],
+        45 => [0xB4, 0xD2, 0xE4],
+        101 => [0xDA, 0x86, 0x7A],
+        102 => [0x6E, 0x99, 0xCA],
+        104 => [0x6B, 0x32, 0x7C],
+        106 => [0xDA, 0x85, 0x41],
+        119 => [0xA4, 0xBD, 0x47],
+        135 => [0x74, 0x86, 0x9D],
+        141 => [0x27, 0x46, 0x2D],
+        151 => [0x78, 0x90, 0x82],
+        153 => [0x95, 0x79, 0x77],
+        192 => [0x69, 0x40, 0x28],
+        194 => [0xA3, 0xA2, 0xA5],
+        199 => [0x63, 0x5F, 0x62],
+        208 => [0xE5, 0xE4, 0xDF],
+        217 => [0x7C, 0x5C, 0x46],
+        226 => [0xFD, 0xEA, 0x8D],
+        1001 => [0xF8, 0xF8, 0xF8],
+        1002 => [0xCD, 0xCD, 0xCD],
+        1003 => [0x11, 0x11, 0x11],
+        1004 => [0xFF, 0x00, 0x00],
+        1007 => [0xA3, 0x4B, 0x4B],
+        1009 => [0xFF, 0xFF, 0x00],
+        1010 => [0x00, 0x00, 0xFF],
+        1018 => [0x12, 0xEE, 0xD4],
+        1019 => [0x00, 0xFF, 0xFF],
+        1020 => [0x00, 0xFF, 0x00],
+        1030 => [0xFF, 0xCC, 0x99],
+        1032 => [0xFF, 0x00, 0xBF],
+        _ => [0xA3, 0xA2, 0xA5],
+    }
+}
@@ -0,0 +1,142 @@
+/*
+    Terrain out of a greyscale heightmap.
+
+    The footprint is split into square columns, each sampling the heightmap at its center.
+    White is max_height bricks, black still gets one brick so the ground has no holes.
+    Every column is anchored and stacked out of as few parts as the color bands allow,
+    a band change is the only place a column gets split.
+*/
+use anyhow::{Context, Result, anyhow, bail};
+use glam::{Vec2, Vec3};
+
+use crate::{
+    common::asset_cache::AssetCache,
+    scene::{PartDesc, Scene},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerrainBand {
+    /// First brick layer that isn't part of this band
+    pub below: u32,
+    pub color: [u8; 4],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TerrainOptions {
+    /// Name of the heightmap in the AssetCache
+    pub heightmap: String,
+    /// World-space size along X and Z, in studs
+    pub footprint: Vec2,
+    /// Width of a column in studs
+    pub column_width: f32,
+    /// Height of a white pixel, in bricks
+    pub max_height: u32,
+    /// Corner the terrain grows from along +X and +Z, its Y is the bottom of every column
+    pub origin: Vec3,
+    /// Ordered bottom up, layers above the last band use its color
+    pub bands: Vec<TerrainBand>,
+}
+
+impl Default for TerrainOptions {
+    fn default() -> Self {
+        TerrainOptions {
+            heightmap: "textures/heightmap.png".to_string(),
+            footprint: Vec2::new(128.0, 128.0),
+            column_width: 2.0,
+            max_height: 16,
+            origin: Vec3::ZERO,
+            bands: vec![
+                // Grass, dirt, stone
+                TerrainBand {
+                    below: 6,
+                    color: [0x4B, 0x97, 0x4B, 0xFF],
+                },
+                TerrainBand {
+                    below: 11,
+                    color: [0x7C, 0x5C, 0x46, 0xFF],
+                },
+                TerrainBand {
+                    below: u32::MAX,
+                    color: [0xA3, 0xA2, 0xA5, 0xFF],
+                },
+            ],
+        }
+    }
+}
+
+/// Build a scene of anchored columns following the heightmap
+pub fn generate_terrain(assets: &AssetCache, options: &TerrainOptions) -> Result<Scene> {
+    let bytes = assets.get_image(&options.heightmap).ok_or(anyhow!(
+        "No heightmap {} in the asset cache",
+        options.heightmap
+    ))?;
+    let heightmap = image::load_from_memory(bytes)
+        .with_context(|| format!("Couldn't decode heightmap {}", options.heightmap))?
+        .to_luma8();
+    if options.column_width <= 0.0 {
+        bail!("Terrain columns need a positive width");
+    }
+
+    let columns = (options.footprint / options.column_width)
+        .round()
+        .as_uvec2();
+    let (width, height) = heightmap.dimensions();
+
+    let mut scene = Scene::default();
+    for cx in 0..columns.x {
+        for cz in 0..columns.y {
+            // Nearest pixel to the column center
+            let u = (cx as f32 + 0.5) / columns.x as f32;
+            let v = (cz as f32 + 0.5) / columns.y as f32;
+            let px = ((u * width as f32) as u32).min(width - 1);
+            let py = ((v * height as f32) as u32).min(height - 1);
+            let luma = heightmap.get_pixel(px, py).0[0] as f32 / 255.0;
+
+            let top = ((luma * options.max_height as f32).round() as u32).max(1);
+            let corner =
+                options.origin + Vec3::new(cx as f32, 0.0, cz as f32) * options.column_width;
+
+            for (bottom, top, color) in column_segments(top, &options.bands) {
+                let size = Vec3::new(
+                    options.column_width,
+                    (top - bottom) as f32,
+                    options.column_width,
+                );
+                scene.parts.push(PartDesc {
+                    position: corner + Vec3::Y * bottom as f32 + size / 2.0,
+                    size,
+                    color,
+                    anchor: true,
+                    ..Default::default()
+                });
+            }
+        }
+    }
+    Ok(scene)
+}
+
+/*
+    Helper functions
+*/
+
+/// Split a column of `height` bricks where the bands change, as (bottom, top, color)
+fn column_segments(height: u32, bands: &[TerrainBand]) -> Vec<(u32, u32, [u8; 4])> {
+    let mut segments = Vec::new();
+    let mut bottom = 0;
+    for (i, band) in bands.iter().enumerate() {
+        let last = i == bands.len() - 1;
+        let top = if last { height } else { band.below.min(height) };
+        if top > bottom {
+            segments.push((bottom, top, band.color));
+            bottom = top;
+        }
+        if bottom >= height {
+            break;
+        }
+    }
+    // No bands at all, keep the default color
+    if segments.is_empty() {
+        segments.push((0, height, PartDesc::default().color));
+    }
+    segments
+}
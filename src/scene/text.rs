@@ -0,0 +1,40 @@
+/*
+    Human readable scene format (RON).
+
+    (
+        version: 3,
+        parts: [
+            (position: (0.0, -7.0, 0.0), size: (20.0, 1.0, 20.0), anchor: true),
+            (
+                position: (0.0, -6.0, 0.0),
+                color: (255, 0, 0, 255),
+                material: Wood,
+                name: Some("door"),
+                tags: ["house", "red"],
+            ),
+            (
+                part: Mesh,
+                position: (4.0, -6.0, 0.0),
+                mesh: Some((name: "meshes/teapot.obj", collider: ConvexHull)),
+            ),
+        ],
+    )
+*/
+use anyhow::{Context, Result};
+
+use crate::scene::Scene;
+
+impl Scene {
+    /// Parse a scene from RON source
+    pub fn from_ron(source: &str) -> Result<Self> {
+        let scene: Scene = ron::from_str(source).context("Couldn't parse scene")?;
+        scene.check_version()?;
+        Ok(scene)
+    }
+
+    /// Write scene into RON source
+    pub fn to_ron(&self) -> Result<String> {
+        let config = ron::ser::PrettyConfig::new().depth_limit(3);
+        Ok(ron::ser::to_string_pretty(self, config)?)
+    }
+}
@@ -0,0 +1,275 @@
+/*
+    MagicaVoxel (.vox) importer.
+
+    A voxel is a 1x1 brick. Each layer is packed on its own: same coloured voxels are merged
+    greedily into the largest brick footprint that fits, so layers snap onto each other through
+    their studs like any other stack. Odd layers prefer footprints running along Z so the seams
+    of neighbouring layers don't line up and the model holds together.
+
+    .vox is Z up, voxel X stays X, voxel Z becomes Y and voxel Y becomes -Z.
+*/
+use anyhow::{Context, Result, anyhow, bail};
+use bevy_ecs::prelude::*;
+use glam::{IVec3, Vec3};
+use std::{collections::BTreeMap, fs, path::Path};
+use tracing::warn;
+
+use crate::scene::{PartDesc, Scene};
+
+const MAGIC: &[u8; 4] = b"VOX ";
+
+/// Brick footprints in studs, checked in this order. 1x1 always fits.
+const BRICK_FOOTPRINTS: &[(u32, u32)] = &[
+    (4, 12),
+    (4, 10),
+    (4, 6),
+    (2, 10),
+    (2, 8),
+    (2, 6),
+    (2, 4),
+    (1, 8),
+    (2, 3),
+    (1, 6),
+    (2, 2),
+    (1, 4),
+    (1, 3),
+    (1, 2),
+    (1, 1),
+];
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VoxOptions {
+    /// Anchor the bricks of the lowest layer
+    pub anchor_bottom: bool,
+}
+
+pub struct VoxImport {
+    pub scene: Scene,
+    /// Voxels read, to compare against the number of bricks
+    pub voxels: usize,
+    /// Extra models in the file, only the first one is imported
+    pub skipped_models: usize,
+}
+
+impl VoxImport {
+    /// Spawn the imported bricks and build models out of the ones that snap
+    pub fn load(&self, world: &mut World) -> Vec<Entity> {
+        self.scene.load(world)
+    }
+}
+
+pub fn import_vox(path: impl AsRef<Path>, options: VoxOptions) -> Result<VoxImport> {
+    let path = path.as_ref();
+    let bytes =
+        fs::read(path).with_context(|| format!("Couldn't read voxel file {}", path.display()))?;
+    parse_vox(&bytes, options)
+}
+
+pub fn parse_vox(bytes: &[u8], options: VoxOptions) -> Result<VoxImport> {
+    if bytes.len() < 8 || &bytes[0..4] != MAGIC {
+        bail!("Not a .vox file");
+    }
+    let (id, _, children) = chunk(&bytes[8..]).context("Missing MAIN chunk")?;
+    if id != *b"MAIN" {
+        bail!(
+            "Expected MAIN chunk, found {}",
+            String::from_utf8_lossy(&id)
+        );
+    }
+
+    let mut models: Vec<Vec<[u8; 4]>> = Vec::new();
+    let mut palette = None;
+
+    let mut rest = children;
+    while !rest.is_empty() {
+        let (id, content, nested) = chunk(rest)?;
+        match &id {
+            b"XYZI" => {
+                let count = read_u32(content, 0)? as usize;
+                let voxels = content
+                    .get(4..4 + count * 4)
+                    .ok_or(anyhow!("XYZI chunk is shorter than its voxel count"))?;
+                models.push(
+                    voxels
+                        .chunks_exact(4)
+                        .map(|v| [v[0], v[1], v[2], v[3]])
+                        .collect(),
+                );
+            }
+            b"RGBA" => {
+                if content.len() < 256 * 4 {
+                    bail!("RGBA chunk is too short");
+                }
+                // Entry i is color index i + 1
+                let mut colors = [[0; 4]; 256];
+                for (i, color) in content.chunks_exact(4).take(255).enumerate() {
+                    colors[i + 1] = [color[0], color[1], color[2], color[3]];
+                }
+                palette = Some(colors);
+            }
+            // SIZE, PACK and the scene graph chunks aren't needed
+            _ => {}
+        }
+        rest = &rest[12 + content.len() + nested.len()..];
+    }
+
+    let palette = palette.unwrap_or_else(default_palette);
+    let voxels = models
+        .first()
+        .ok_or(anyhow!("File doesn't contain any voxels"))?;
+    let skipped_models = models.len() - 1;
+    if skipped_models > 0 {
+        warn!(
+            "Voxel import: only the first model is imported, skipped {}",
+            skipped_models
+        );
+    }
+
+    let mut import = VoxImport {
+        scene: Scene::default(),
+        voxels: voxels.len(),
+        skipped_models,
+    };
+
+    // Voxels by layer, in our axes
+    let mut layers: BTreeMap<i32, BTreeMap<(i32, i32), u8>> = BTreeMap::new();
+    for &[x, y, z, color] in voxels {
+        // Index 0 means empty
+        if color == 0 {
+            continue;
+        }
+        let cell = IVec3::new(x as i32, z as i32, -(y as i32) - 1);
+        layers
+            .entry(cell.y)
+            .or_default()
+            .insert((cell.x, cell.z), color);
+    }
+
+    let bottom = layers.keys().next().copied();
+    for (&y, cells) in &layers {
+        let anchor = options.anchor_bottom && Some(y) == bottom;
+        for LayerBrick {
+            min,
+            footprint,
+            color,
+        } in pack_layer(cells, y % 2 != 0)
+        {
+            let size = Vec3::new(footprint.0 as f32, 1.0, footprint.1 as f32);
+            import.scene.parts.push(PartDesc {
+                position: Vec3::new(min.0 as f32, y as f32, min.1 as f32) + size / 2.0,
+                size,
+                color: palette[color as usize],
+                anchor,
+                ..Default::default()
+            });
+        }
+    }
+
+    Ok(import)
+}
+
+/*
+    Helper functions
+*/
+
+/// Id, content and children of the chunk at the start of bytes
+fn chunk(bytes: &[u8]) -> Result<([u8; 4], &[u8], &[u8])> {
+    let id: [u8; 4] = bytes
+        .get(0..4)
+        .ok_or(anyhow!("Truncated chunk"))?
+        .try_into()?;
+    let content_len = read_u32(bytes, 4)? as usize;
+    let children_len = read_u32(bytes, 8)? as usize;
+    let content = bytes
+        .get(12..12 + content_len)
+        .ok_or(anyhow!("Truncated {} chunk", String::from_utf8_lossy(&id)))?;
+    let children = bytes
+        .get(12 + content_len..12 + content_len + children_len)
+        .ok_or(anyhow!("Truncated {} chunk", String::from_utf8_lossy(&id)))?;
+    Ok((id, content, children))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Result<u32> {
+    let value = bytes.get(at..at + 4).ok_or(anyhow!("Truncated chunk"))?;
+    Ok(u32::from_le_bytes(value.try_into()?))
+}
+
+struct LayerBrick {
+    min: (i32, i32),
+    /// Studs along X and Z
+    footprint: (u32, u32),
+    color: u8,
+}
+
+/// Greedy packing of one layer, cells are (X, Z) to color index
+fn pack_layer(cells: &BTreeMap<(i32, i32), u8>, along_z: bool) -> Vec<LayerBrick> {
+    let mut free = cells.clone();
+    let mut bricks = Vec::new();
+
+    // BTreeMap order gives the lowest X, then lowest Z, uncovered cell
+    while let Some((&min, &color)) = free.iter().next() {
+        let fits = |(w, d): (u32, u32)| {
+            (0..w as i32).all(|dx| {
+                (0..d as i32).all(|dz| free.get(&(min.0 + dx, min.1 + dz)) == Some(&color))
+            })
+        };
+
+        let footprint = BRICK_FOOTPRINTS
+            .iter()
+            .flat_map(|&(short, long)| {
+                if along_z {
+                    [(short, long), (long, short)]
+                } else {
+                    [(long, short), (short, long)]
+                }
+            })
+            .find(|&footprint| fits(footprint))
+            .unwrap_or((1, 1));
+
+        for dx in 0..footprint.0 as i32 {
+            for dz in 0..footprint.1 as i32 {
+                free.remove(&(min.0 + dx, min.1 + dz));
+            }
+        }
+        bricks.push(LayerBrick {
+            min,
+            footprint,
+            color,
+        });
+    }
+    bricks
+}
+
+/// Palette MagicaVoxel uses when a file has no RGBA chunk.
+/// A 6x6x6 color cube without black, then ramps of blue, green, red and grey.
+fn default_palette() -> [[u8; 4]; 256] {
+    let mut palette = [[0; 4]; 256];
+    let cube = [0xFF, 0xCC, 0x99, 0x66, 0x33, 0x00];
+    let ramp = [0xEE, 0xDD, 0xBB, 0xAA, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+
+    let mut index = 1;
+    for r in cube {
+        for g in cube {
+            for b in cube {
+                if index == 216 {
+                    break;
+                }
+                palette[index] = [r, g, b, 0xFF];
+                index += 1;
+            }
+        }
+    }
+    for channel in [2, 1, 0] {
+        for value in ramp {
+            let mut color = [0, 0, 0, 0xFF];
+            color[channel] = value;
+            palette[index] = color;
+            index += 1;
+        }
+    }
+    for value in ramp {
+        palette[index] = [value, value, value, 0xFF];
+        index += 1;
+    }
+    palette
+}
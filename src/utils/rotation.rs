@@ -0,0 +1,48 @@
+use glam::{Mat3, Quat, Vec3};
+use std::{f32::consts::FRAC_PI_2, sync::LazyLock};
+
+/// Every rotation that maps the axes onto the axes (6 up directions * 4 turns around them).
+/// Index 0 is identity.
+pub static AXIS_ROTATIONS: LazyLock<[Quat; 24]> = LazyLock::new(|| {
+    let ups = [
+        Quat::IDENTITY,
+        Quat::from_rotation_x(FRAC_PI_2),
+        Quat::from_rotation_x(-FRAC_PI_2),
+        Quat::from_rotation_x(2.0 * FRAC_PI_2),
+        Quat::from_rotation_z(FRAC_PI_2),
+        Quat::from_rotation_z(-FRAC_PI_2),
+    ];
+
+    let mut rotations = [Quat::IDENTITY; 24];
+    for (i, up) in ups.iter().enumerate() {
+        for turn in 0..4 {
+            rotations[i * 4 + turn] = *up * Quat::from_rotation_y(turn as f32 * FRAC_PI_2);
+        }
+    }
+    rotations
+});
+
+/// Find which axis-aligned rotation this is, if any.
+pub fn axis_rotation_index(rotation: Quat) -> Option<usize> {
+    AXIS_ROTATIONS
+        .iter()
+        .position(|axis| axis.dot(rotation).abs() > 1.0 - 1e-5)
+}
+
+/// Upright bricks turned in steps of 90 degrees are still axis-aligned boxes,
+///     so the turn is folded into the size instead. Anything else keeps its rotation.
+pub fn bake_upright_rotation(matrix: Mat3, size: Vec3) -> (Quat, Vec3) {
+    let rotation = Quat::from_mat3(&matrix).normalize();
+
+    if axis_rotation_index(rotation).is_some() && (rotation * Vec3::Y).abs_diff_eq(Vec3::Y, 1e-4) {
+        // Entries are +-1 or 0 up to float noise
+        let swizzle = Mat3::from_cols(
+            matrix.x_axis.abs().round(),
+            matrix.y_axis.abs().round(),
+            matrix.z_axis.abs().round(),
+        );
+        (Quat::IDENTITY, swizzle * size)
+    } else {
+        (rotation, size)
+    }
+}
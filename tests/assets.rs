@@ -0,0 +1,160 @@
+use freebricks::{
+    common::asset_cache::{AssetCache, AssetErrorCause, AssetKind, Handle, Image, Shader},
+    render::texture::STUD_LAYERS,
+};
+use std::{
+    fs::{self, File},
+    time::{Duration, SystemTime},
+};
+
+/// Rewrite a file and push its modification time forward so coarse timestamps still differ
+fn touch(path: &str, contents: &str) {
+    fs::write(path, contents).unwrap();
+    let later = SystemTime::now() + Duration::from_secs(5);
+    File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(later)
+        .unwrap();
+}
+
+#[test]
+pub fn asset_hot_reload() {
+    let message = "Testing asset polling";
+    let dir = "target/reload-assets";
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(format!("{}/shaders", dir)).unwrap();
+    fs::write(format!("{}/shaders/a.wgsl", dir), "// first").unwrap();
+    fs::write(format!("{}/shaders/b.wgsl", dir), "// untouched").unwrap();
+
+    let mut assets = AssetCache::init(dir).expect("Couldn't load assets");
+    assert!(
+        assets.changed.is_empty(),
+        "{} - Nothing changed yet",
+        message
+    );
+    assert_eq!(
+        assets
+            .get_shader("reload-assets/shaders/a.wgsl")
+            .map(String::as_str),
+        Some("// first"),
+        "{}",
+        message
+    );
+
+    touch(&format!("{}/shaders/a.wgsl", dir), "// second");
+    fs::write(format!("{}/shaders/c.wgsl", dir), "// new").unwrap();
+    assets.poll().expect("Couldn't poll");
+
+    assert!(
+        assets.was_reloaded("reload-assets/shaders/a.wgsl"),
+        "{} - Edited file wasn't marked",
+        message
+    );
+    assert!(
+        assets.was_reloaded("reload-assets/shaders/c.wgsl"),
+        "{} - New file wasn't marked",
+        message
+    );
+    assert!(
+        !assets.was_reloaded("reload-assets/shaders/b.wgsl"),
+        "{} - Untouched file was marked",
+        message
+    );
+    assert_eq!(
+        assets
+            .get_shader("reload-assets/shaders/a.wgsl")
+            .map(String::as_str),
+        Some("// second"),
+        "{} - Contents weren't reloaded",
+        message
+    );
+
+    // AssetCache::watch clears the marks before polling, nothing changed since
+    assets.changed.clear();
+    assets.poll().expect("Couldn't poll");
+    assert!(assets.changed.is_empty(), "{}", message);
+}
+
+#[test]
+pub fn asset_fallbacks() {
+    let message = "Testing asset load errors";
+    let dir = "target/broken-assets";
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(format!("{}/textures", dir)).unwrap();
+    fs::write(format!("{}/textures/studs.png", dir), "not a png").unwrap();
+    fs::write(format!("{}/textures/bricks.wgsl", dir), "// shader").unwrap();
+
+    // Broken files are skipped, not fatal
+    let mut assets = AssetCache::init(dir).expect("Couldn't load assets");
+
+    let e = assets
+        .load::<Image>("broken-assets/textures/studs.png")
+        .expect_err("Corrupt image loaded");
+    assert!(
+        matches!(e.cause, AssetErrorCause::Invalid(_)),
+        "{} - {}",
+        message,
+        e
+    );
+    assert!(
+        e.to_string().contains("studs.png"),
+        "{} - Error should name the file, got {}",
+        message,
+        e
+    );
+
+    let e = assets
+        .load::<Image>("broken-assets/textures/bricks.wgsl")
+        .expect_err("Shader loaded as an image");
+    assert!(
+        matches!(e.cause, AssetErrorCause::WrongKind { expected: "image" }),
+        "{} - {}",
+        message,
+        e
+    );
+    let e = assets
+        .load::<Shader>("broken-assets/missing.wgsl")
+        .expect_err("Missing shader loaded");
+    assert!(
+        matches!(e.cause, AssetErrorCause::Missing),
+        "{} - {}",
+        message,
+        e
+    );
+
+    // Handles to broken entries resolve to the built-in assets
+    let studs = assets.load_or_fallback::<Image>("broken-assets/textures/studs.png");
+    assert_eq!(assets.get(&studs), Image::fallback(), "{}", message);
+    let fallback = image::load_from_memory(Image::fallback()).expect("Fallback doesn't decode");
+    assert_eq!(
+        fallback.width() * STUD_LAYERS,
+        fallback.height(),
+        "{} - Fallback should have a square layer per stud type",
+        message
+    );
+    let missing = Handle::<Shader>::new("broken-assets/missing.wgsl");
+    assert_eq!(assets.get(&missing), Shader::fallback(), "{}", message);
+
+    // Fixing the file on disk makes the same handle resolve to it
+    let mut png = Vec::new();
+    image::RgbaImage::new(4, 12)
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+    fs::write(format!("{}/textures/studs.png", dir), &png).unwrap();
+    File::options()
+        .write(true)
+        .open(format!("{}/textures/studs.png", dir))
+        .unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(5))
+        .unwrap();
+    assets.poll().expect("Couldn't poll");
+
+    assert!(
+        assets.load::<Image>(studs.name()).is_ok(),
+        "{} - Fixed file still reports an error",
+        message
+    );
+    assert_eq!(assets.get(&studs), png.as_slice(), "{}", message);
+}
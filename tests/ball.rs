@@ -0,0 +1,173 @@
+use bevy_ecs::prelude::*;
+use freebricks::{
+    common::mesh::Mesh,
+    ecs::{
+        common::{Color, Position, Rotation, Size},
+        material::Material,
+        parts::{Part, StudInfo},
+        physics::{BodyHandle, Physical, ShapeHandle},
+    },
+    physics::PhysicsState,
+    render::bricks::ball_mesh,
+    scene::gltf::export_glb,
+};
+use glam::Vec3;
+use rapier3d::prelude::*;
+mod test_utils;
+use crate::test_utils::*;
+
+fn spawn_ball(world: &mut World, position: Vec3, size: Vec3) -> Entity {
+    world
+        .spawn((Part::Ball, Physical, Position(position), Size(size)))
+        .id()
+}
+
+#[test]
+pub fn ball_mesh_shape() {
+    let message = "Testing the ball mesh";
+    let (vertices, indices) = ball_mesh();
+
+    for vertex in &vertices {
+        let position = Vec3::from_array(vertex.position);
+        assert!(
+            (position.length() - 0.5).abs() < 1e-5,
+            "{} - Vertex {} is off the sphere",
+            message,
+            position
+        );
+        assert!(
+            (position * 2.0).abs_diff_eq(Vec3::from_array(vertex.normals), 1e-5),
+            "{}",
+            message
+        );
+    }
+
+    // Clockwise from outside, like the brick
+    for triangle in indices.chunks(3) {
+        let [a, b, c] =
+            [0, 1, 2].map(|i| Vec3::from_array(vertices[triangle[i] as usize].position));
+        let cross = (b - a).cross(c - a);
+        assert!(
+            cross.length() > 1e-6,
+            "{} - Degenerate triangle {:?}",
+            message,
+            triangle
+        );
+        assert!(
+            cross.dot(a + b + c) < 0.0,
+            "{} - Triangle {:?} faces inwards",
+            message,
+            triangle
+        );
+    }
+}
+
+#[test]
+pub fn ball_collider() {
+    let message = "Testing ball colliders";
+    let (mut world, mut sched_start, _) = util_setup();
+    let ball = spawn_ball(
+        &mut world,
+        Vec3::new(0.0, 5.0, 0.0),
+        Vec3::new(4.0, 2.0, 3.0),
+    );
+    sched_start.run(&mut world);
+
+    let handle = world.get::<ShapeHandle>(ball).expect("No collider").0;
+    let collider = &world.resource::<PhysicsState>().colliders[handle];
+    let radius = collider.shape().as_ball().map(|b| b.radius);
+    assert_eq!(
+        radius,
+        Some(1.0),
+        "{} - Radius comes from the smallest side",
+        message
+    );
+
+    // Drawn at the size it's simulated at
+    let uniform = Part::Ball.to_uniform(
+        &Position(Vec3::ZERO),
+        &Rotation::default(),
+        &Size(Vec3::new(4.0, 2.0, 3.0)),
+        &Color::default(),
+        &StudInfo::default(),
+        &Material::default(),
+    );
+    assert_eq!(uniform.size, [2.0; 3], "{}", message);
+}
+
+#[test]
+pub fn ball_rolls() {
+    let message = "Testing rolling balls";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+    spawn_ps(
+        &mut world,
+        true,
+        Vec3::new(0.0, -0.5, 0.0),
+        Vec3::new(64.0, 1.0, 64.0),
+    );
+    let ball = spawn_ball(&mut world, Vec3::new(0.0, 1.0, 0.0), Vec3::splat(2.0));
+    sched_start.run(&mut world);
+
+    let body = world.get::<BodyHandle>(ball).unwrap().0;
+    world.resource_mut::<PhysicsState>().rigid_bodies[body]
+        .set_linvel(vector![5.0, 0.0, 0.0], true);
+    for _ in 0..30 {
+        sched_update.run(&mut world);
+    }
+
+    let rigid_body = &world.resource::<PhysicsState>().rigid_bodies[body];
+    assert!(
+        rigid_body.angvel().norm() > 1.0,
+        "{} - Ball slid instead of rolling, angular velocity {}",
+        message,
+        rigid_body.angvel()
+    );
+}
+
+#[test]
+pub fn ball_no_studs() {
+    let message = "Testing balls don't snap";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+
+    // Brick, ball, brick stacked exactly touching
+    spawn_p(&mut world, false, Vec3::ZERO);
+    let ball = spawn_ball(
+        &mut world,
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(4.0, 1.0, 2.0),
+    );
+    spawn_p(&mut world, false, Vec3::new(0.0, 2.0, 0.0));
+
+    sched_start.run(&mut world);
+    sched_update.run(&mut world);
+
+    assert_eq!(get_models(&mut world).len(), 0, "{}", message);
+    guarantee(
+        &mut world, message, ball, false, false, false, false, true, true,
+    );
+}
+
+#[test]
+pub fn ball_export() {
+    let message = "Testing ball glTF export";
+    let mut world = World::new();
+    world.spawn((
+        Part::Ball,
+        Position(Vec3::new(0.0, 3.0, 0.0)),
+        Size(Vec3::splat(2.0)),
+    ));
+
+    let mut glb = Vec::new();
+    export_glb(&mut world, &mut glb).expect("Couldn't export glb");
+    let mesh = Mesh::from_glb(&glb).expect("Couldn't parse exported glb");
+    assert_eq!(mesh.indices.len(), ball_mesh().1.len(), "{}", message);
+    // The loaded mesh is fitted to a unit box, a sphere stays round
+    for position in &mesh.positions {
+        assert!(
+            (position.length() - 0.5).abs() < 1e-4,
+            "{} - Vertex {} off the sphere",
+            message,
+            position
+        );
+    }
+}
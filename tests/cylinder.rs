@@ -0,0 +1,204 @@
+use bevy_ecs::prelude::*;
+use freebricks::{
+    common::mesh::Mesh,
+    ecs::{
+        common::{Color, Position, Rotation, Size},
+        material::Material,
+        parts::{Part, StudInfo},
+        physics::{Physical, ShapeHandle},
+    },
+    physics::PhysicsState,
+    render::bricks::cylinder_mesh,
+    scene::{BinarySceneReader, PartDesc, Scene, gltf::export_glb, write_binary},
+};
+use glam::Vec3;
+use rapier3d::prelude::ShapeType;
+mod test_utils;
+use crate::test_utils::*;
+
+fn spawn_cylinder(world: &mut World, position: Vec3, size: Vec3) -> Entity {
+    world
+        .spawn((Part::Cylinder, Physical, Position(position), Size(size)))
+        .id()
+}
+
+#[test]
+pub fn cylinder_mesh_shape() {
+    let message = "Testing the cylinder mesh";
+    let (vertices, indices) = cylinder_mesh();
+
+    for vertex in &vertices {
+        let [x, y, z] = vertex.position;
+        assert!(
+            y.abs() == 0.5 && Vec3::new(x, 0.0, z).length() <= 0.5 + 1e-5,
+            "{} - Vertex {:?} is outside the cylinder",
+            message,
+            vertex.position
+        );
+    }
+
+    // Clockwise from outside, like the brick
+    for triangle in indices.chunks(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| vertices[triangle[i] as usize]);
+        let [pa, pb, pc] = [a, b, c].map(|v| Vec3::from_array(v.position));
+        let cross = (pb - pa).cross(pc - pa);
+        let normal = Vec3::from_array(a.normals) + Vec3::from_array(b.normals);
+        assert!(
+            cross.length() > 1e-6 && cross.dot(normal) < 0.0,
+            "{} - Triangle {:?} faces inwards",
+            message,
+            triangle
+        );
+    }
+}
+
+#[test]
+pub fn cylinder_axis() {
+    let message = "Testing cylinder axes";
+    let uniform = |size: Vec3| {
+        Part::Cylinder.to_uniform(
+            &Position(Vec3::ZERO),
+            &Rotation::default(),
+            &Size(size),
+            &Color::default(),
+            &StudInfo::default(),
+            &Material::default(),
+        )
+    };
+
+    // The mesh's Y ends up on the longest side, the other two stay equal
+    for (size, axis) in [
+        (Vec3::new(1.0, 4.0, 2.0), Vec3::Y * 4.0),
+        (Vec3::new(6.0, 2.0, 3.0), Vec3::X * 6.0),
+        (Vec3::new(1.0, 2.0, 5.0), Vec3::Z * 5.0),
+    ] {
+        let uniform = uniform(size);
+        let model_y = Vec3::from_array(uniform.model[1]);
+        assert!(
+            model_y.abs().abs_diff_eq(axis, 1e-5),
+            "{} - {} ran along {}",
+            message,
+            size,
+            model_y
+        );
+        let radial = Vec3::from_array(uniform.model[0]).length();
+        let other = Vec3::from_array(uniform.model[2]).length();
+        assert!(
+            (radial - other).abs() < 1e-5 && radial <= size.min_element() + 1e-5,
+            "{} - {} isn't round",
+            message,
+            size
+        );
+    }
+}
+
+#[test]
+pub fn cylinder_collider() {
+    let message = "Testing cylinder colliders";
+    let (mut world, mut sched_start, _) = util_setup();
+    let upright = spawn_cylinder(&mut world, Vec3::ZERO, Vec3::new(2.0, 4.0, 2.0));
+    let lying = spawn_cylinder(
+        &mut world,
+        Vec3::new(10.0, 0.0, 0.0),
+        Vec3::new(6.0, 2.0, 3.0),
+    );
+    sched_start.run(&mut world);
+
+    for (entity, shape_type, extents) in [
+        (upright, ShapeType::Cylinder, Vec3::new(2.0, 4.0, 2.0)),
+        (lying, ShapeType::Compound, Vec3::new(6.0, 2.0, 2.0)),
+    ] {
+        let handle = world.get::<ShapeHandle>(entity).expect("No collider").0;
+        let collider = &world.resource::<PhysicsState>().colliders[handle];
+        assert_eq!(collider.shape().shape_type(), shape_type, "{}", message);
+        let aabb = collider.compute_aabb().extents();
+        assert!(
+            Vec3::new(aabb.x, aabb.y, aabb.z).abs_diff_eq(extents, 1e-3),
+            "{} - Extents were {}, expected {}",
+            message,
+            aabb,
+            extents
+        );
+    }
+}
+
+#[test]
+pub fn cylinder_snapping() {
+    let message = "Testing cylinder snapping";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+
+    // Upright, the caps snap like a brick's top and bottom
+    spawn_p(&mut world, false, Vec3::ZERO);
+    spawn_cylinder(
+        &mut world,
+        Vec3::new(0.0, 1.5, 0.0),
+        Vec3::new(2.0, 2.0, 2.0),
+    );
+
+    // Lying down it's round on top and bottom
+    spawn_p(&mut world, false, Vec3::new(20.0, 0.0, 0.0));
+    let lying = spawn_cylinder(
+        &mut world,
+        Vec3::new(20.0, 1.5, 0.0),
+        Vec3::new(4.0, 2.0, 2.0),
+    );
+
+    sched_start.run(&mut world);
+    sched_update.run(&mut world);
+
+    let models = get_models(&mut world);
+    assert_eq!(models.len(), 1, "{} - Only one model", message);
+    guarantee_model(&mut world, message, models[0], 2, 0, 1);
+    guarantee(
+        &mut world, message, lying, false, false, false, false, true, true,
+    );
+}
+
+#[test]
+pub fn cylinder_scenes() {
+    let message = "Testing cylinders in scenes";
+    let scene = Scene {
+        parts: vec![PartDesc {
+            part: Part::Cylinder,
+            size: Vec3::new(1.0, 1.0, 6.0),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    let loaded = Scene::from_ron(&scene.to_ron().unwrap()).expect("Couldn't read scene");
+    assert_eq!(loaded.parts[0].part, Part::Cylinder, "{} - Text", message);
+
+    let mut buffer = Vec::new();
+    write_binary(&scene, &mut buffer).unwrap();
+    let loaded = BinarySceneReader::new(buffer.as_slice())
+        .unwrap()
+        .into_scene()
+        .unwrap();
+    assert_eq!(loaded.parts[0].part, Part::Cylinder, "{} - Binary", message);
+}
+
+#[test]
+pub fn cylinder_export() {
+    let message = "Testing cylinder glTF export";
+    let mut world = World::new();
+    world.spawn((Part::Cylinder, Size(Vec3::new(4.0, 1.0, 1.0))));
+
+    let mut glb = Vec::new();
+    export_glb(&mut world, &mut glb).expect("Couldn't export glb");
+    let mesh = Mesh::from_glb(&glb).expect("Couldn't parse exported glb");
+    assert_eq!(mesh.indices.len(), cylinder_mesh().1.len(), "{}", message);
+
+    // The loaded mesh is fitted to a unit box, lying along X it's round across Y and Z
+    let radius = |a: f32, b: f32| (a * a + b * b).sqrt();
+    assert!(
+        mesh.positions.iter().all(|p| radius(p.y, p.z) < 0.5 + 1e-4),
+        "{} - Not round around X",
+        message
+    );
+    assert!(
+        mesh.positions.iter().any(|p| radius(p.x, p.y) > 0.6),
+        "{} - Round around the wrong axis",
+        message
+    );
+}
@@ -0,0 +1,335 @@
+use bevy_ecs::hierarchy::ChildOf;
+use bevy_platform::collections::HashMap;
+use freebricks::{
+    ecs::{
+        common::{Color, Position, Rotation, Size},
+        parts::Part,
+        physics::Physical,
+    },
+    render::bricks::INDICES,
+    scene::{
+        gltf::export_glb,
+        print::{BRICK_HEIGHT_MM, TriMesh, build_model_mesh, export_model_obj, export_model_stl},
+    },
+};
+use glam::{Quat, Vec3};
+use serde_json::Value;
+use std::f32::consts::FRAC_PI_2;
+mod test_utils;
+use crate::test_utils::*;
+
+/// Split a .glb back into its JSON and BIN chunks
+fn read_glb(glb: &[u8]) -> (Value, Vec<u8>) {
+    assert_eq!(&glb[0..4], b"glTF", "Bad magic");
+    assert_eq!(u32::from_le_bytes(glb[4..8].try_into().unwrap()), 2);
+    assert_eq!(
+        u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize,
+        glb.len()
+    );
+
+    let json_len = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+    assert_eq!(&glb[16..20], b"JSON");
+    let json = serde_json::from_slice(&glb[20..20 + json_len]).expect("Bad JSON chunk");
+
+    let bin_start = 20 + json_len;
+    let bin_len = u32::from_le_bytes(glb[bin_start..bin_start + 4].try_into().unwrap()) as usize;
+    assert_eq!(&glb[bin_start + 4..bin_start + 8], b"BIN\0");
+    let bin = glb[bin_start + 8..bin_start + 8 + bin_len].to_vec();
+    (json, bin)
+}
+
+#[test]
+pub fn gltf_export() {
+    let message = "Testing glTF export";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+
+    let positions = vec![
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(8.0, 0.0, 0.0),
+    ];
+    let entities: Vec<_> = positions
+        .iter()
+        .map(|position| spawn_p(&mut world, false, *position))
+        .collect();
+    world
+        .entity_mut(entities[2])
+        .insert(Color([255, 0, 0, 128]));
+
+    sched_start.run(&mut world);
+    sched_update.run(&mut world);
+
+    let mut glb = Vec::new();
+    export_glb(&mut world, &mut glb).expect("Couldn't export");
+    let (json, bin) = read_glb(&glb);
+
+    let nodes = json["nodes"].as_array().unwrap();
+    // Three parts and one model
+    assert_eq!(nodes.len(), 4, "{} - Node count", message);
+    assert_eq!(
+        json["materials"].as_array().unwrap().len(),
+        2,
+        "{} - One material per color",
+        message
+    );
+    assert_eq!(json["materials"][1]["alphaMode"], "BLEND", "{}", message);
+
+    // Model node holds the two stacked parts, roots are the model and the lone part
+    let model = nodes.iter().find(|n| n.get("children").is_some()).unwrap();
+    assert_eq!(
+        model["children"].as_array().unwrap().len(),
+        2,
+        "{}",
+        message
+    );
+    assert_eq!(
+        json["scenes"][0]["nodes"].as_array().unwrap().len(),
+        2,
+        "{} - Root nodes",
+        message
+    );
+
+    // Mesh is only stored once
+    let indices = &json["accessors"][3];
+    assert_eq!(indices["count"], INDICES.len(), "{}", message);
+    assert_eq!(json["buffers"][0]["byteLength"], bin.len(), "{}", message);
+
+    // Lone part at x = 8 with default size
+    let lone = nodes
+        .iter()
+        .find(|n| n["matrix"][12].as_f64() == Some(8.0))
+        .expect("Couldn't find lone part");
+    assert_eq!(lone["matrix"][0].as_f64(), Some(4.0), "{} - Scale", message);
+}
+
+/// Every edge has to be used once in each direction for a closed, consistently wound surface
+fn check_watertight(mesh: &TriMesh, message: &str) {
+    let mut edges: HashMap<(u32, u32), usize> = HashMap::new();
+    for [a, b, c] in &mesh.triangles {
+        for edge in [(*a, *b), (*b, *c), (*c, *a)] {
+            *edges.entry(edge).or_insert(0) += 1;
+        }
+    }
+    for (&(a, b), &count) in &edges {
+        assert_eq!(count, 1, "{} - Edge {}-{} used twice", message, a, b);
+        assert_eq!(
+            edges.get(&(b, a)),
+            Some(&1),
+            "{} - Edge {}-{} is open",
+            message,
+            a,
+            b
+        );
+    }
+}
+
+/// Studs are the only thing sticking out above a brick top
+fn count_studs(mesh: &TriMesh, top: f32) -> usize {
+    // Ring plus center on every cap
+    mesh.vertices.iter().filter(|v| v.y > top + 0.01).count() / 17
+}
+
+#[test]
+pub fn print_export() {
+    let message = "Testing model export for printing";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+
+    // Two stacked bricks, and two more where the top one hangs two studs over
+    let stacked = spawn_p(&mut world, false, Vec3::new(0.0, 0.0, 0.0));
+    spawn_p(&mut world, false, Vec3::new(0.0, 1.0, 0.0));
+    let offset = spawn_p(&mut world, false, Vec3::new(20.0, 0.0, 0.0));
+    spawn_p(&mut world, false, Vec3::new(22.0, 1.0, 0.0));
+
+    sched_start.run(&mut world);
+    sched_update.run(&mut world);
+
+    assert_eq!(get_models(&mut world).len(), 2, "{} - Model count", message);
+    let models = [stacked, offset].map(|part| world.get::<ChildOf>(part).unwrap().parent());
+
+    let stacked = build_model_mesh(&mut world, models[0]).expect("Couldn't build mesh");
+    check_watertight(&stacked, message);
+    assert_eq!(
+        count_studs(&stacked, 2.0 * BRICK_HEIGHT_MM),
+        8,
+        "{} - Covered studs are dropped",
+        message
+    );
+    // Nothing lies flat between the two bricks
+    let between = stacked.triangles.iter().any(|tri| {
+        tri.iter()
+            .all(|&i| (stacked.vertices[i as usize].y - BRICK_HEIGHT_MM).abs() < 0.01)
+    });
+    assert!(!between, "{} - Internal face left in", message);
+
+    let offset = build_model_mesh(&mut world, models[1]).expect("Couldn't build mesh");
+    check_watertight(&offset, message);
+    assert_eq!(
+        count_studs(&offset, 2.0 * BRICK_HEIGHT_MM),
+        8,
+        "{} - Studs of the upper brick",
+        message
+    );
+    let studs = offset
+        .vertices
+        .iter()
+        .filter(|v| v.y > BRICK_HEIGHT_MM + 0.01 && v.y < 2.0 * BRICK_HEIGHT_MM - 0.01)
+        .count();
+    assert_eq!(
+        studs,
+        4 * 17,
+        "{} - Exposed studs of the lower brick",
+        message
+    );
+
+    /*
+        File formats
+    */
+    let mut stl = Vec::new();
+    export_model_stl(&mut world, models[0], &mut stl).expect("Couldn't export STL");
+    let count = u32::from_le_bytes(stl[80..84].try_into().unwrap()) as usize;
+    assert_eq!(count, stacked.triangles.len(), "{}", message);
+    assert_eq!(stl.len(), 84 + count * 50, "{} - STL size", message);
+
+    let mut obj = Vec::new();
+    export_model_obj(&mut world, models[0], &mut obj).expect("Couldn't export OBJ");
+    let obj = String::from_utf8(obj).unwrap();
+    assert_eq!(
+        obj.lines().filter(|l| l.starts_with("v ")).count(),
+        stacked.vertices.len(),
+        "{}",
+        message
+    );
+    assert_eq!(
+        obj.lines().filter(|l| l.starts_with("f ")).count(),
+        stacked.triangles.len(),
+        "{}",
+        message
+    );
+
+    let lone = spawn_p(&mut world, false, Vec3::new(50.0, 0.0, 0.0));
+    assert!(
+        build_model_mesh(&mut world, lone).is_err(),
+        "{} - Only models export",
+        message
+    );
+}
+
+#[test]
+pub fn print_export_turned() {
+    let message = "Testing model export with turned parts";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+
+    // A brick crossing another one underneath
+    let bottom = spawn_p(&mut world, false, Vec3::new(0.0, 0.0, 0.0));
+    let top = spawn_p(&mut world, false, Vec3::new(0.0, 1.0, 0.0));
+    world.get_mut::<Rotation>(top).unwrap().0 = Quat::from_rotation_y(FRAC_PI_2);
+
+    sched_start.run(&mut world);
+    sched_update.run(&mut world);
+    let model = world.get::<ChildOf>(bottom).unwrap().parent();
+    assert_eq!(
+        world.get::<ChildOf>(top).unwrap().parent(),
+        model,
+        "{} - Bricks didn't snap",
+        message
+    );
+
+    let mesh = build_model_mesh(&mut world, model).expect("Couldn't build mesh");
+    check_watertight(&mesh, message);
+    let max = mesh
+        .vertices
+        .iter()
+        .fold(Vec3::splat(f32::MIN), |acc, v| acc.max(*v));
+    assert!(
+        (max.x - 4.0 * 8.0).abs() < 0.01 && (max.z - 4.0 * 8.0).abs() < 0.01,
+        "{} - Turned brick keeps its footprint, got {}",
+        message,
+        max
+    );
+    assert_eq!(
+        count_studs(&mesh, 2.0 * BRICK_HEIGHT_MM),
+        8,
+        "{} - Studs of the turned brick",
+        message
+    );
+    let lower = mesh
+        .vertices
+        .iter()
+        .filter(|v| v.y > BRICK_HEIGHT_MM + 0.01 && v.y < 2.0 * BRICK_HEIGHT_MM - 0.01)
+        .count();
+    assert_eq!(lower, 4 * 17, "{} - Exposed studs below", message);
+
+    // Any other turn can't be laid out on the grid
+    world.get_mut::<Rotation>(top).unwrap().0 = Quat::from_rotation_y(0.3);
+    assert!(
+        build_model_mesh(&mut world, model).is_err(),
+        "{} - Odd angle exported",
+        message
+    );
+}
+
+#[test]
+pub fn print_export_mixed() {
+    let message = "Testing model export with parts that aren't boxes";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+
+    // A wedge and an upright cylinder standing on a brick
+    let brick = spawn_p(&mut world, false, Vec3::new(0.0, 0.0, 0.0));
+    let shapes = [
+        (
+            Part::Wedge,
+            Vec3::new(-1.0, 1.0, 0.0),
+            Vec3::new(2.0, 1.0, 2.0),
+        ),
+        (
+            Part::Cylinder,
+            Vec3::new(1.0, 2.0, 0.0),
+            Vec3::new(2.0, 3.0, 2.0),
+        ),
+    ]
+    .map(|(part, position, size)| {
+        world
+            .spawn((part, Physical, Position(position), Size(size)))
+            .id()
+    });
+
+    sched_start.run(&mut world);
+    sched_update.run(&mut world);
+    let model = world.get::<ChildOf>(brick).unwrap().parent();
+    for shape in shapes {
+        assert_eq!(
+            world.get::<ChildOf>(shape).map(|c| c.parent()),
+            Some(model),
+            "{} - Part didn't snap",
+            message
+        );
+    }
+
+    let mesh = build_model_mesh(&mut world, model).expect("Couldn't build mesh");
+    // Every shell is closed on its own
+    check_watertight(&mesh, message);
+    let max = mesh
+        .vertices
+        .iter()
+        .fold(Vec3::splat(f32::MIN), |acc, v| acc.max(*v));
+    assert!(
+        (max.y - 4.0 * BRICK_HEIGHT_MM).abs() < 0.01,
+        "{} - Cylinder is in the print, top at {}",
+        message,
+        max.y
+    );
+
+    // The wedge slopes down to the back, only its front reaches the full height
+    let top = |v: &&Vec3| v.x < 2.0 * 8.0 - 0.01 && (v.y - 2.0 * BRICK_HEIGHT_MM).abs() < 0.01;
+    let front = mesh
+        .vertices
+        .iter()
+        .filter(top)
+        .any(|v| v.z > 2.0 * 8.0 - 0.01);
+    let back = mesh.vertices.iter().filter(top).any(|v| v.z < 0.01);
+    assert!(
+        front && !back,
+        "{} - Wedge is in the print with its slope",
+        message
+    );
+}
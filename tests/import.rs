@@ -0,0 +1,308 @@
+use freebricks::{
+    ecs::parts::{Part, StudType, cylinder_axis},
+    scene::{
+        ldraw::{ldraw_colour, parse_ldraw},
+        rbxlx::{brick_color, parse_rbxlx},
+        vox::{VoxOptions, parse_vox},
+    },
+};
+use glam::{Quat, Vec3};
+mod test_utils;
+use crate::test_utils::*;
+
+const LDRAW_MPD: &str = "\
+0 FILE main.ldr
+0 Test model
+1 4 0 0 0 1 0 0 0 1 0 0 0 1 3001.dat
+1 1 0 -24 0 1 0 0 0 1 0 0 0 1 3001.DAT
+1 2 100 0 0 1 0 0 0 1 0 0 0 1 tower.ldr
+1 14 200 0 0 0 0 1 0 1 0 -1 0 0 3001.dat
+1 15 0 0 200 1 0 0 0 1 0 0 0 1 3626bpx1.dat
+0 NOFILE
+0 FILE tower.ldr
+1 16 0 0 0 1 0 0 0 1 0 0 0 1 3003.dat
+1 0x2FF0000 0 -24 0 1 0 0 0 1 0 0 0 1 parts\\3003.dat
+0 NOFILE
+";
+
+#[test]
+pub fn ldraw_import() {
+    let message = "Testing LDraw import";
+    let import = parse_ldraw(LDRAW_MPD).expect("Couldn't parse LDraw");
+
+    assert_eq!(import.scene.parts.len(), 5, "{} - Brick count", message);
+    assert_eq!(
+        import.unknown_parts.get("3626bpx1.dat"),
+        Some(&1),
+        "{} - Unknown part wasn't reported",
+        message
+    );
+
+    let parts = &import.scene.parts;
+    // Origin is the top of the brick, -Y is up
+    assert_eq!(parts[0].position, Vec3::new(0.0, -0.5, 0.0), "{}", message);
+    assert_eq!(parts[1].position, Vec3::new(0.0, 0.5, 0.0), "{}", message);
+    assert_eq!(parts[0].size, Vec3::new(4.0, 1.0, 2.0), "{}", message);
+    assert_eq!(parts[0].color, ldraw_colour(4), "{}", message);
+
+    // Submodel inherits the colour it was referenced with
+    assert_eq!(parts[2].position, Vec3::new(5.0, -0.5, 0.0), "{}", message);
+    assert_eq!(parts[2].size, Vec3::new(2.0, 1.0, 2.0), "{}", message);
+    assert_eq!(parts[2].color, ldraw_colour(2), "{}", message);
+    assert_eq!(parts[3].color, [255, 0, 0, 255], "{}", message);
+
+    // Turned a quarter around Y, baked into the size
+    assert_eq!(parts[4].position, Vec3::new(10.0, -0.5, 0.0), "{}", message);
+    assert_eq!(parts[4].size, Vec3::new(2.0, 1.0, 4.0), "{}", message);
+    assert_eq!(parts[4].rotation, Quat::IDENTITY, "{}", message);
+
+    let (mut world, _, _) = util_setup();
+    import.load(&mut world);
+
+    let models = get_models(&mut world);
+    assert_eq!(models.len(), 2, "{} - Stacks didn't become models", message);
+    for model_id in models {
+        guarantee_model(&mut world, message, model_id, 2, 0, 1);
+    }
+}
+
+#[test]
+pub fn ldraw_malformed() {
+    assert!(parse_ldraw("1 4 0 0 0 1 0 0 3001.dat").is_err());
+    assert!(parse_ldraw("1 4 0 0 zero 1 0 0 0 1 0 0 0 1 3001.dat").is_err());
+    assert!(parse_ldraw("1 4 0 0 0 1 0 0 0 1 0 0 0 1 loop.ldr\n0 FILE loop.ldr\n1 4 0 0 0 1 0 0 0 1 0 0 0 1 loop.ldr").is_err());
+}
+
+const PLACE: &str = r#"<roblox version="4">
+    <Item class="Lighting" referent="RBX9"><Properties/></Item>
+    <Item class="Workspace" referent="RBX0">
+        <Properties/>
+        <Item class="Part" referent="RBX1">
+            <Properties>
+                <bool name="Anchored">true</bool>
+                <int name="BrickColor">28</int>
+                <CoordinateFrame name="CFrame">
+                    <X>0</X><Y>-0.6</Y><Z>0</Z>
+                    <R00>1</R00><R01>0</R01><R02>0</R02>
+                    <R10>0</R10><R11>1</R11><R12>0</R12>
+                    <R20>0</R20><R21>0</R21><R22>1</R22>
+                </CoordinateFrame>
+                <Vector3 name="size"><X>16</X><Y>1.2</Y><Z>16</Z></Vector3>
+                <token name="TopSurface">3</token>
+                <token name="BottomSurface">0</token>
+            </Properties>
+        </Item>
+        <Item class="Model" referent="RBX2">
+            <Properties/>
+            <Item class="Part" referent="RBX3">
+                <Properties>
+                    <bool name="Anchored">false</bool>
+                    <Color3uint8 name="Color3uint8">4294901760</Color3uint8>
+                    <float name="Transparency">0.5</float>
+                    <CoordinateFrame name="CFrame">
+                        <X>0</X><Y>0.6</Y><Z>0</Z>
+                        <R00>-4.37113883e-08</R00><R01>0</R01><R02>1</R02>
+                        <R10>0</R10><R11>1</R11><R12>0</R12>
+                        <R20>-1</R20><R21>0</R21><R22>-4.37113883e-08</R22>
+                    </CoordinateFrame>
+                    <Vector3 name="size"><X>4</X><Y>1.2</Y><Z>2</Z></Vector3>
+                    <token name="TopSurface">0</token>
+                    <token name="BottomSurface">4</token>
+                </Properties>
+                <Item class="Decal" referent="RBX4"><Properties/></Item>
+            </Item>
+            <Item class="Part" referent="RBX5">
+                <Properties>
+                    <token name="shape">2</token>
+                </Properties>
+            </Item>
+            <Item class="Part" referent="RBX7">
+                <Properties>
+                    <token name="shape">2</token>
+                    <CoordinateFrame name="CFrame">
+                        <X>10</X><Y>3.6</Y><Z>0</Z>
+                        <R00>0</R00><R01>-1</R01><R02>0</R02>
+                        <R10>1</R10><R11>0</R11><R12>0</R12>
+                        <R20>0</R20><R21>0</R21><R22>1</R22>
+                    </CoordinateFrame>
+                    <Vector3 name="size"><X>6</X><Y>2.4</Y><Z>2</Z></Vector3>
+                </Properties>
+            </Item>
+            <Item class="Part" referent="RBX8">
+                <Properties>
+                    <token name="shape">2</token>
+                    <Vector3 name="size"><X>1</X><Y>4.8</Y><Z>4</Z></Vector3>
+                </Properties>
+            </Item>
+            <Item class="Script" referent="RBX6"><Properties/></Item>
+        </Item>
+    </Item>
+</roblox>"#;
+
+#[test]
+pub fn rbxlx_import() {
+    let message = "Testing place import";
+    let import = parse_rbxlx(PLACE).expect("Couldn't parse place");
+
+    assert_eq!(import.scene.parts.len(), 4, "{} - Part count", message);
+    let report: Vec<_> = import
+        .unsupported_classes
+        .iter()
+        .map(|(k, v)| (k.as_str(), *v))
+        .collect();
+    assert_eq!(
+        report,
+        vec![("Decal", 1), ("Part (Cylinder)", 1), ("Script", 1)],
+        "{} - Unsupported report",
+        message
+    );
+
+    let base = &import.scene.parts[0];
+    assert_eq!(base.part, Part::Brick, "{}", message);
+    assert!(base.anchor, "{} - Anchored wasn't read", message);
+    assert_eq!(base.position, Vec3::new(0.0, -0.5, 0.0), "{}", message);
+    assert_eq!(base.size, Vec3::new(16.0, 1.0, 16.0), "{}", message);
+    let [r, g, b] = brick_color(28);
+    assert_eq!(base.color, [r, g, b, 255], "{}", message);
+    assert_eq!(base.studs.top, StudType::Outlet, "{}", message);
+    assert_eq!(base.studs.bottom, StudType::Flat, "{}", message);
+
+    let brick = &import.scene.parts[1];
+    assert!(!brick.anchor, "{}", message);
+    assert_eq!(brick.color, [255, 0, 0, 128], "{}", message);
+    assert_eq!(brick.rotation, Quat::IDENTITY, "{}", message);
+    assert_eq!(brick.size, Vec3::new(2.0, 1.0, 4.0), "{}", message);
+    assert_eq!(brick.studs.top, StudType::Flat, "{}", message);
+    assert_eq!(brick.studs.bottom, StudType::Inlet, "{}", message);
+
+    assert_eq!(import.scene.parts[2].part, Part::Cylinder, "{}", message);
+
+    // Their X axis stood upright stays our longest side, the wheel is reported instead
+    let standing = &import.scene.parts[3];
+    assert_eq!(standing.part, Part::Cylinder, "{}", message);
+    let mut axis = Vec3::ZERO;
+    axis[cylinder_axis(standing.size)] = 1.0;
+    assert!(
+        (standing.rotation * axis).abs().abs_diff_eq(Vec3::Y, 1e-5),
+        "{} - Cylinder should stand along Y",
+        message
+    );
+
+    let (mut world, _, _) = util_setup();
+    let entities = import.load(&mut world);
+    guarantee(
+        &mut world,
+        message,
+        entities[1],
+        true,
+        false,
+        false,
+        false,
+        true,
+        true,
+    );
+}
+
+#[test]
+pub fn rbxlx_malformed() {
+    assert!(parse_rbxlx("<notaplace/>").is_err());
+    assert!(parse_rbxlx("<roblox><Item class=\"Part\"></roblox>").is_err());
+}
+
+/// .vox with one model and optionally a palette, chunks are id, content size, children size
+fn vox_file(voxels: &[[u8; 4]], palette: Option<&[[u8; 4]; 256]>) -> Vec<u8> {
+    let chunk = |id: &[u8; 4], content: &[u8]| {
+        let mut bytes = id.to_vec();
+        bytes.extend((content.len() as u32).to_le_bytes());
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend(content);
+        bytes
+    };
+
+    let mut children = chunk(b"SIZE", &[8, 0, 0, 0, 8, 0, 0, 0, 8, 0, 0, 0]);
+    let mut xyzi = (voxels.len() as u32).to_le_bytes().to_vec();
+    xyzi.extend(voxels.iter().flatten());
+    children.extend(chunk(b"XYZI", &xyzi));
+    if let Some(palette) = palette {
+        children.extend(chunk(b"RGBA", palette.as_flattened()));
+    }
+
+    let mut bytes = b"VOX ".to_vec();
+    bytes.extend(150u32.to_le_bytes());
+    bytes.extend(b"MAIN");
+    bytes.extend(0u32.to_le_bytes());
+    bytes.extend((children.len() as u32).to_le_bytes());
+    bytes.extend(children);
+    bytes
+}
+
+#[test]
+pub fn vox_import() {
+    let message = "Testing voxel import";
+
+    // 5x2 bottom layer, two 4x2 layers and a single red voxel on top
+    let mut voxels = Vec::new();
+    for (z, width) in [(0, 5), (1, 4), (2, 4)] {
+        for x in 0..width {
+            for y in 0..2 {
+                voxels.push([x, y, z, 1]);
+            }
+        }
+    }
+    voxels.push([0, 0, 3, 2]);
+
+    let mut palette = [[0; 4]; 256];
+    palette[0] = [0, 0, 255, 255];
+    palette[1] = [255, 0, 0, 255];
+
+    let options = VoxOptions {
+        anchor_bottom: true,
+    };
+    let import = parse_vox(&vox_file(&voxels, Some(&palette)), options).expect("Couldn't parse");
+    assert_eq!(import.voxels, 27, "{}", message);
+
+    let parts = &import.scene.parts;
+    assert_eq!(parts.len(), 5, "{} - Voxels weren't merged", message);
+    assert_eq!(parts[0].size, Vec3::new(4.0, 1.0, 2.0), "{}", message);
+    assert_eq!(parts[0].position, Vec3::new(2.0, 0.5, -1.0), "{}", message);
+    assert_eq!(parts[1].size, Vec3::new(1.0, 1.0, 2.0), "{}", message);
+    assert!(
+        parts[0].anchor && parts[1].anchor,
+        "{} - Bottom anchor",
+        message
+    );
+    assert!(!parts[2].anchor, "{}", message);
+    // RGBA entry 0 is color index 1
+    assert_eq!(parts[0].color, [0, 0, 255, 255], "{}", message);
+    assert_eq!(parts[4].color, [255, 0, 0, 255], "{}", message);
+    assert_eq!(parts[4].size, Vec3::new(1.0, 1.0, 1.0), "{}", message);
+
+    let (mut world, _, _) = util_setup();
+    import.load(&mut world);
+    let models = get_models(&mut world);
+    assert_eq!(models.len(), 1, "{} - Layers didn't snap", message);
+    // The anchored 4x2 holds the model instead of being part of it,
+    // the 1x2 next to it only meets the layer above on an edge
+    guarantee_model(&mut world, message, models[0], 3, 1, 2);
+
+    // Without a palette chunk the default one is used, index 1 is white
+    let import =
+        parse_vox(&vox_file(&[[0, 0, 0, 1]], None), VoxOptions::default()).expect("Couldn't parse");
+    assert_eq!(
+        import.scene.parts[0].color,
+        [255, 255, 255, 255],
+        "{}",
+        message
+    );
+    assert!(!import.scene.parts[0].anchor, "{}", message);
+}
+
+#[test]
+pub fn vox_malformed() {
+    assert!(parse_vox(b"VOX \x96\0\0\0", VoxOptions::default()).is_err());
+    assert!(parse_vox(b"NOPE\x96\0\0\0MAIN", VoxOptions::default()).is_err());
+
+    let mut truncated = vox_file(&[[0, 0, 0, 1]], None);
+    truncated.truncate(truncated.len() - 2);
+    assert!(parse_vox(&truncated, VoxOptions::default()).is_err());
+}
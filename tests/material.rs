@@ -0,0 +1,214 @@
+use bevy_ecs::prelude::*;
+use freebricks::{
+    ecs::{
+        common::{Color, Position, Rotation, Size},
+        material::Material,
+        parts::{Part, StudInfo},
+        physics::{BodyHandle, Physical, ShapeHandle},
+    },
+    physics::PhysicsState,
+    scene::{BinarySceneReader, PartDesc, Scene, rbxlx::parse_rbxlx, write_binary},
+};
+use glam::Vec3;
+use rapier3d::prelude::*;
+mod test_utils;
+use crate::test_utils::*;
+
+#[test]
+pub fn material_colliders() {
+    let message = "Testing material colliders";
+    let (mut world, mut sched_start, _) = util_setup();
+    let entities: Vec<Entity> = Material::ALL
+        .iter()
+        .enumerate()
+        .map(|(i, &material)| {
+            world
+                .spawn((
+                    Part::Brick,
+                    Physical,
+                    Position(Vec3::new(i as f32 * 10.0, 0.0, 0.0)),
+                    material,
+                ))
+                .id()
+        })
+        .collect();
+    sched_start.run(&mut world);
+
+    for (entity, material) in entities.into_iter().zip(Material::ALL) {
+        let properties = material.properties();
+        let handle = world.get::<ShapeHandle>(entity).expect("No collider").0;
+        let collider = &world.resource::<PhysicsState>().colliders[handle];
+        assert_eq!(
+            collider.friction(),
+            properties.friction,
+            "{} - {:?}",
+            message,
+            material
+        );
+        assert_eq!(
+            collider.restitution(),
+            properties.restitution,
+            "{} - {:?}",
+            message,
+            material
+        );
+        assert_eq!(
+            collider.density(),
+            properties.density,
+            "{} - {:?}",
+            message,
+            material
+        );
+    }
+
+    // Parts without a Material are plastic
+    assert_eq!(
+        world.spawn(Part::Brick).get::<Material>(),
+        Some(&Material::Plastic),
+        "{}",
+        message
+    );
+}
+
+#[test]
+pub fn material_shading() {
+    let message = "Testing material shading";
+    for material in Material::ALL {
+        let uniform = Part::Brick.to_uniform(
+            &Position(Vec3::ZERO),
+            &Rotation::default(),
+            &Size::default(),
+            &Color::default(),
+            &StudInfo::default(),
+            &material,
+        );
+        let properties = material.properties();
+        assert_eq!(
+            uniform.material,
+            [
+                properties.roughness,
+                properties.specular,
+                properties.emissive
+            ],
+            "{} - {:?}",
+            message,
+            material
+        );
+    }
+
+    // Plastic keeps the old look, a shininess of 32 at full strength
+    let plastic = Material::Plastic.properties();
+    assert_eq!(
+        2.0 / (plastic.roughness * plastic.roughness),
+        32.0,
+        "{}",
+        message
+    );
+    assert_eq!(plastic.specular, 1.0, "{}", message);
+    assert_eq!(
+        Material::Neon.properties().emissive,
+        1.0,
+        "{} - Neon glows",
+        message
+    );
+}
+
+#[test]
+pub fn material_sliding() {
+    let message = "Testing ice slides further than wood";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+    spawn_ps(
+        &mut world,
+        true,
+        Vec3::new(0.0, -0.5, 0.0),
+        Vec3::new(256.0, 1.0, 256.0),
+    );
+    let sliders: Vec<Entity> = [Material::Ice, Material::Wood]
+        .iter()
+        .enumerate()
+        .map(|(i, &material)| {
+            world
+                .spawn((
+                    Part::Brick,
+                    Physical,
+                    Position(Vec3::new(0.0, 0.5, i as f32 * 20.0)),
+                    StudInfo::FLAT,
+                    material,
+                ))
+                .id()
+        })
+        .collect();
+    sched_start.run(&mut world);
+
+    for &entity in &sliders {
+        let body = world.get::<BodyHandle>(entity).unwrap().0;
+        world.resource_mut::<PhysicsState>().rigid_bodies[body]
+            .set_linvel(vector![10.0, 0.0, 0.0], true);
+    }
+    for _ in 0..60 {
+        sched_update.run(&mut world);
+    }
+
+    let [ice, wood] = [sliders[0], sliders[1]].map(|e| world.get::<Position>(e).unwrap().x);
+    assert!(
+        ice > wood + 1.0,
+        "{} - Ice at {}, wood at {}",
+        message,
+        ice,
+        wood
+    );
+}
+
+#[test]
+pub fn material_scenes() {
+    let message = "Testing materials in scenes";
+    let scene = Scene {
+        parts: Material::ALL
+            .iter()
+            .map(|&material| PartDesc {
+                material,
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    };
+
+    let loaded = Scene::from_ron(&scene.to_ron().unwrap()).expect("Couldn't read scene");
+    assert_eq!(scene, loaded, "{} - Text", message);
+
+    let mut buffer = Vec::new();
+    write_binary(&scene, &mut buffer).unwrap();
+    let loaded = BinarySceneReader::new(buffer.as_slice())
+        .unwrap()
+        .into_scene()
+        .unwrap();
+    let materials: Vec<_> = loaded.parts.iter().map(|p| p.material).collect();
+    assert_eq!(materials, Material::ALL.to_vec(), "{} - Binary", message);
+
+    // Material enum values from a place file
+    let place = r#"<roblox version="4">
+        <Item class="Part" referent="RBX0">
+            <Properties>
+                <token name="Material">1088</token>
+            </Properties>
+        </Item>
+        <Item class="Part" referent="RBX1">
+            <Properties>
+                <token name="Material">288</token>
+            </Properties>
+        </Item>
+        <Item class="Part" referent="RBX2">
+            <Properties>
+                <token name="Material">816</token>
+            </Properties>
+        </Item>
+    </roblox>"#;
+    let import = parse_rbxlx(place).expect("Couldn't parse place");
+    let materials: Vec<_> = import.scene.parts.iter().map(|p| p.material).collect();
+    assert_eq!(
+        materials,
+        vec![Material::Metal, Material::Neon, Material::Plastic],
+        "{}",
+        message
+    );
+}
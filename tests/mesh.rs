@@ -0,0 +1,299 @@
+use bevy_ecs::prelude::*;
+use freebricks::{
+    common::{
+        asset_cache::{AssetCache, Handle},
+        mesh::Mesh,
+    },
+    ecs::{
+        common::{Position, Size},
+        parts::{MeshCollider, MeshRef, Part},
+        physics::ShapeHandle,
+    },
+    physics::PhysicsState,
+    render::mesh_batch::mesh_vertices,
+    scene::{BinarySceneReader, Scene, gltf::export_glb, print::build_model_mesh, write_binary},
+};
+use glam::Vec3;
+use rapier3d::prelude::ShapeType;
+use std::fs;
+mod test_utils;
+use crate::test_utils::*;
+
+/// Square pyramid, 2 wide and 4 tall, with a quad base and no normals
+const PYRAMID: &str = "
+# base
+v -1 0 -1
+v 1 0 -1
+v 1 0 1
+v -1 0 1
+v 0 4 0
+f 1 2 3 4
+f 1 5 2
+f 2 5 3
+f 3/1 5/1 4/1
+f -2 -1 -5
+";
+
+/// Bounding box of a mesh
+fn bounds(mesh: &Mesh) -> (Vec3, Vec3) {
+    mesh.positions
+        .iter()
+        .fold((Vec3::MAX, Vec3::MIN), |(min, max), &p| {
+            (min.min(p), max.max(p))
+        })
+}
+
+#[test]
+pub fn mesh_obj() {
+    let message = "Testing OBJ meshes";
+    let mesh = Mesh::from_obj(PYRAMID).expect("Couldn't parse pyramid");
+
+    // Quad fans into 2 triangles, no normals so every corner is its own vertex
+    assert_eq!(mesh.indices.len(), 6 * 3, "{}", message);
+    assert_eq!(mesh.positions.len(), 18, "{} - Flat normals", message);
+    assert_eq!(
+        bounds(&mesh),
+        (Vec3::splat(-0.5), Vec3::splat(0.5)),
+        "{} - Meshes are fitted to the unit cube",
+        message
+    );
+    // Counter-clockwise base faces away from the apex
+    let base_normal = mesh.normals[mesh.indices[0] as usize];
+    assert!(
+        base_normal.abs_diff_eq(Vec3::NEG_Y, 1e-5),
+        "{} - Base normal was {}",
+        message,
+        base_normal
+    );
+
+    // Given normals are kept and shared corners are merged
+    let quad = "v 0 0 0\nv 2 0 0\nv 2 0 1\nv 0 0 1\nvn 0 1 0\nf 1//1 4//1 3//1 2//1\n";
+    let mesh = Mesh::from_obj(quad).expect("Couldn't parse quad");
+    assert_eq!(mesh.positions.len(), 4, "{}", message);
+    assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3], "{}", message);
+    assert!(
+        mesh.normals.iter().all(|&n| n == Vec3::Y),
+        "{} - Normals were {:?}",
+        message,
+        mesh.normals
+    );
+
+    // The renderer winds clockwise
+    let (vertices, indices) = mesh_vertices(&mesh);
+    assert_eq!(vertices.len(), 4, "{}", message);
+    assert_eq!(indices, vec![0, 2, 1, 0, 3, 2], "{}", message);
+
+    assert!(Mesh::from_obj("v 0 0 0\nf 1 2 3").is_err(), "{}", message);
+    assert!(Mesh::from_obj("v 0 0\n").is_err(), "{}", message);
+    assert!(Mesh::from_obj("# nothing").is_err(), "{}", message);
+}
+
+#[test]
+pub fn mesh_glb() {
+    let message = "Testing glTF meshes";
+    let (mut world, _, _) = util_setup();
+    world.spawn((Part::Brick, Position(Vec3::ZERO), Size(Vec3::ONE)));
+    world.spawn((
+        Part::Brick,
+        Position(Vec3::new(3.0, 0.0, 0.0)),
+        Size(Vec3::ONE),
+    ));
+
+    let mut glb = Vec::new();
+    export_glb(&mut world, &mut glb).expect("Couldn't export glb");
+    let mesh = Mesh::from_glb(&glb).expect("Couldn't parse exported glb");
+
+    assert_eq!(mesh.indices.len(), 2 * 36, "{} - Both bricks", message);
+    let (min, max) = bounds(&mesh);
+    assert!(
+        min.abs_diff_eq(Vec3::splat(-0.5), 1e-5) && max.abs_diff_eq(Vec3::splat(0.5), 1e-5),
+        "{} - Bounds were {} {}",
+        message,
+        min,
+        max
+    );
+    // Node translations are applied, otherwise both bricks would overlap
+    let xs = mesh.positions.iter().filter(|p| p.x < -0.49).count();
+    assert!(xs > 0 && xs < mesh.positions.len(), "{}", message);
+
+    assert!(Mesh::from_glb(b"glTF").is_err(), "{}", message);
+    assert!(
+        Mesh::from_glb(&glb[..glb.len() / 2]).is_err(),
+        "{}",
+        message
+    );
+}
+
+/// Assets with a pyramid mesh and a broken one
+fn mesh_assets() -> AssetCache {
+    let dir = "target/mesh-assets";
+    let _ = fs::create_dir_all(format!("{}/meshes", dir));
+    fs::write(format!("{}/meshes/pyramid.obj", dir), PYRAMID).unwrap();
+    fs::write(format!("{}/meshes/broken.obj", dir), "f 1 2 3").unwrap();
+    AssetCache::init(dir).expect("Couldn't load assets")
+}
+
+/// World with a pyramid mesh asset and a part using it
+fn mesh_world(collider: MeshCollider, name: &str) -> (World, Entity) {
+    let (mut world, mut init, _) = util_setup();
+    world.insert_resource(mesh_assets());
+    let entity = world
+        .spawn((
+            MeshRef {
+                name: name.to_string(),
+                collider,
+            },
+            Position(Vec3::new(0.0, 10.0, 0.0)),
+            Size(Vec3::new(2.0, 4.0, 2.0)),
+        ))
+        .id();
+    init.run(&mut world);
+    (world, entity)
+}
+
+fn shape_type(world: &mut World, entity: Entity) -> ShapeType {
+    let handle = world.get::<ShapeHandle>(entity).expect("No collider").0;
+    world.resource::<PhysicsState>().colliders[handle]
+        .shape()
+        .shape_type()
+}
+
+#[test]
+pub fn mesh_part() {
+    let message = "Testing mesh parts";
+    let (mut world, entity) =
+        mesh_world(MeshCollider::ConvexHull, "mesh-assets/meshes/pyramid.obj");
+
+    assert_eq!(
+        world.get::<Part>(entity),
+        Some(&Part::Mesh),
+        "{} - MeshRef makes a mesh part",
+        message
+    );
+    guarantee(
+        &mut world, message, entity, false, false, false, false, true, true,
+    );
+    assert_eq!(
+        shape_type(&mut world, entity),
+        ShapeType::ConvexPolyhedron,
+        "{}",
+        message
+    );
+    let handle = world.get::<ShapeHandle>(entity).unwrap().0;
+    let aabb = world.resource::<PhysicsState>().colliders[handle].compute_aabb();
+    assert!(
+        (aabb.extents().y - 4.0).abs() < 1e-4,
+        "{} - Hull should be stretched to the part's size, was {}",
+        message,
+        aabb.extents()
+    );
+
+    let assets = world.resource::<AssetCache>();
+    let mesh = assets.get(&Handle::<Mesh>::new("mesh-assets/meshes/pyramid.obj"));
+    assert_eq!(mesh.indices.len(), 18, "{}", message);
+
+    let (mut world, entity) = mesh_world(MeshCollider::TriMesh, "mesh-assets/meshes/pyramid.obj");
+    assert_eq!(
+        shape_type(&mut world, entity),
+        ShapeType::TriMesh,
+        "{}",
+        message
+    );
+
+    // Broken meshes fall back to the brick cube
+    let (mut world, entity) = mesh_world(MeshCollider::ConvexHull, "mesh-assets/meshes/broken.obj");
+    assert!(
+        world
+            .resource::<AssetCache>()
+            .load::<Mesh>("mesh-assets/meshes/broken.obj")
+            .is_err(),
+        "{}",
+        message
+    );
+    assert_eq!(
+        shape_type(&mut world, entity),
+        ShapeType::ConvexPolyhedron,
+        "{}",
+        message
+    );
+}
+
+#[test]
+pub fn mesh_export() {
+    let message = "Testing export of mesh parts";
+    let (mut world, _) = mesh_world(MeshCollider::ConvexHull, "mesh-assets/meshes/pyramid.obj");
+
+    let mut glb = Vec::new();
+    export_glb(&mut world, &mut glb).expect("Couldn't export glb");
+    let mesh = Mesh::from_glb(&glb).expect("Couldn't parse exported glb");
+    assert_eq!(mesh.indices.len(), 18, "{} - Pyramid exported", message);
+
+    // Without assets the part still exports, as the cube it's drawn with
+    world.remove_resource::<AssetCache>();
+    let mut glb = Vec::new();
+    export_glb(&mut world, &mut glb).expect("Couldn't export glb without assets");
+    let mesh = Mesh::from_glb(&glb).unwrap();
+    assert_eq!(mesh.indices.len(), 36, "{} - Fallback cube", message);
+
+    // Printing adds the mesh as its own shell standing on the brick
+    let (mut world, mut init, _) = util_setup();
+    world.insert_resource(mesh_assets());
+    let brick = world
+        .spawn((Part::Brick, Position(Vec3::new(0.0, 7.5, 0.0))))
+        .id();
+    world.spawn((
+        MeshRef::new("mesh-assets/meshes/pyramid.obj"),
+        Position(Vec3::new(0.0, 10.0, 0.0)),
+        Size(Vec3::new(2.0, 4.0, 2.0)),
+    ));
+    init.run(&mut world);
+    let model = world
+        .get::<ChildOf>(brick)
+        .expect("Mesh part didn't snap")
+        .parent();
+    let printed = build_model_mesh(&mut world, model).expect("Couldn't print model");
+    let top = printed.vertices.iter().map(|v| v.y).fold(0.0, f32::max);
+    assert!(
+        (top - 5.0 * 9.6).abs() < 0.01,
+        "{} - Mesh left out, top at {}",
+        message,
+        top
+    );
+}
+
+#[test]
+pub fn mesh_scene() {
+    let message = "Testing mesh parts in saved scenes";
+    let (mut world, _) = mesh_world(MeshCollider::TriMesh, "mesh-assets/meshes/pyramid.obj");
+    let scene = Scene::from_world(&mut world);
+    let mesh = MeshRef {
+        name: "mesh-assets/meshes/pyramid.obj".to_string(),
+        collider: MeshCollider::TriMesh,
+    };
+    assert_eq!(scene.parts.len(), 1, "{}", message);
+    assert_eq!(scene.parts[0].mesh, Some(mesh.clone()), "{}", message);
+
+    let text = scene.to_ron().expect("Couldn't write text scene");
+    let from_text = Scene::from_ron(&text).expect("Couldn't read text scene");
+    assert_eq!(from_text, scene, "{} - Text round trip", message);
+
+    let mut buffer = Vec::new();
+    write_binary(&scene, &mut buffer).expect("Couldn't write binary scene");
+    let mut loaded = World::new();
+    let entities = BinarySceneReader::new(buffer.as_slice())
+        .unwrap()
+        .spawn(&mut loaded)
+        .expect("Couldn't read binary scene");
+    assert_eq!(
+        loaded.get::<MeshRef>(entities[0]),
+        Some(&mesh),
+        "{} - Binary round trip",
+        message
+    );
+    assert_eq!(
+        loaded.get::<Part>(entities[0]),
+        Some(&Part::Mesh),
+        "{}",
+        message
+    );
+}
@@ -1,7 +1,17 @@
 use bevy_ecs::prelude::*;
-use freebricks::ecs::model::Model;
-use glam::Vec3;
+use freebricks::{
+    common::model_graph::StudPitch,
+    ecs::{
+        common::{Position, Rotation, Size},
+        model::Model,
+        parts::{Part, StudInfo, StudType},
+        physics::{Anchored, BodyHandle, Physical, ShapeHandle},
+    },
+    physics::{AnchorMap, PhysicsState},
+};
+use glam::{Quat, Vec3};
 use rapier3d::prelude::*;
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI};
 mod test_utils;
 use crate::test_utils::*;
 #[test]
@@ -83,6 +93,108 @@ pub fn connected_bricks() {
     }
 }
 
+fn spawn_stud_faces(world: &mut World, position: Vec3, top: StudType, bottom: StudType) -> Entity {
+    world
+        .spawn((
+            Part::default(),
+            Physical,
+            Position(position),
+            StudInfo {
+                top,
+                bottom,
+                ..StudInfo::FLAT
+            },
+        ))
+        .id()
+}
+
+#[test]
+pub fn stacked_faces_bricks() {
+    let message = "Testing stacked bricks compare the faces that meet";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+
+    // Upper bottom into lower top, the other two faces can't snap. Spawned in both orders
+    let joined = vec![
+        spawn_stud_faces(
+            &mut world,
+            Vec3::new(0.0, 0.0, 0.0),
+            StudType::Outlet,
+            StudType::Flat,
+        ),
+        spawn_stud_faces(
+            &mut world,
+            Vec3::new(0.0, 1.0, 0.0),
+            StudType::Flat,
+            StudType::Inlet,
+        ),
+        spawn_stud_faces(
+            &mut world,
+            Vec3::new(10.0, 1.0, 0.0),
+            StudType::Flat,
+            StudType::Inlet,
+        ),
+        spawn_stud_faces(
+            &mut world,
+            Vec3::new(10.0, 0.0, 0.0),
+            StudType::Outlet,
+            StudType::Flat,
+        ),
+    ];
+
+    // The lower bottom and upper top would snap, but they don't meet
+    let separate = vec![
+        spawn_stud_faces(
+            &mut world,
+            Vec3::new(20.0, 0.0, 0.0),
+            StudType::Flat,
+            StudType::Outlet,
+        ),
+        spawn_stud_faces(
+            &mut world,
+            Vec3::new(20.0, 1.0, 0.0),
+            StudType::Inlet,
+            StudType::Flat,
+        ),
+        spawn_stud_faces(
+            &mut world,
+            Vec3::new(30.0, 1.0, 0.0),
+            StudType::Inlet,
+            StudType::Flat,
+        ),
+        spawn_stud_faces(
+            &mut world,
+            Vec3::new(30.0, 0.0, 0.0),
+            StudType::Flat,
+            StudType::Outlet,
+        ),
+    ];
+
+    sched_start.run(&mut world);
+    sched_update.run(&mut world);
+
+    for entity in joined {
+        guarantee(
+            &mut world, message, entity, false, false, true, false, true, false,
+        );
+    }
+    for entity in separate {
+        guarantee(
+            &mut world, message, entity, false, false, false, false, true, true,
+        );
+    }
+
+    let models = get_models(&mut world);
+    assert_eq!(
+        models.len(),
+        2,
+        "{} - Two stacked models don't exist",
+        message
+    );
+    for model_id in models {
+        guarantee_model(&mut world, message, model_id, 2, 0, 1);
+    }
+}
+
 #[test]
 pub fn model_touches_anchor() {
     let message = "Testing multiple separate bricks";
@@ -328,3 +440,533 @@ pub fn model_into_models_with_anchor() {
         }
     }
 }
+
+fn spawn_studs(world: &mut World, position: Vec3, studs: StudInfo) -> Entity {
+    world
+        .spawn((Part::default(), Physical, Position(position), studs))
+        .id()
+}
+
+#[test]
+pub fn side_connected_bricks() {
+    let message = "Testing bricks joined side to side";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+
+    let outlet = |axis: usize| {
+        let mut studs = StudInfo::FLAT;
+        *studs.face_mut(axis, true) = StudType::Outlet;
+        studs
+    };
+    let inlet = |axis: usize| {
+        let mut studs = StudInfo::FLAT;
+        *studs.face_mut(axis, false) = StudType::Inlet;
+        studs
+    };
+
+    // Right into left, front into back
+    let joined = vec![
+        spawn_studs(&mut world, Vec3::new(0.0, 0.0, 0.0), outlet(0)),
+        spawn_studs(&mut world, Vec3::new(4.0, 0.0, 0.0), inlet(0)),
+        spawn_studs(&mut world, Vec3::new(0.0, 0.0, 10.0), outlet(2)),
+        spawn_studs(&mut world, Vec3::new(0.0, 0.0, 12.0), inlet(2)),
+    ];
+
+    // Flat sides and the wrong pair of faces
+    let separate = vec![
+        spawn_p(&mut world, false, Vec3::new(20.0, 0.0, 0.0)),
+        spawn_p(&mut world, false, Vec3::new(24.0, 0.0, 0.0)),
+        spawn_studs(&mut world, Vec3::new(20.0, 0.0, 10.0), outlet(0)),
+        spawn_studs(&mut world, Vec3::new(24.0, 0.0, 10.0), outlet(0)),
+    ];
+
+    sched_start.run(&mut world);
+    sched_update.run(&mut world);
+
+    for entity in joined {
+        guarantee(
+            &mut world, message, entity, false, false, true, false, true, false,
+        );
+    }
+    for entity in separate {
+        guarantee(
+            &mut world, message, entity, false, false, false, false, true, true,
+        );
+    }
+
+    let models = get_models(&mut world);
+    assert_eq!(models.len(), 2, "{} - Two side models don't exist", message);
+    for model_id in models {
+        guarantee_model(&mut world, message, model_id, 2, 0, 1);
+    }
+}
+
+/// Stack a brick on a lower one, shifted sideways
+fn spawn_stack(world: &mut World, base: Vec3, shift: Vec3) -> [Entity; 2] {
+    [
+        spawn_p(world, false, base),
+        spawn_p(world, false, base + Vec3::Y + shift),
+    ]
+}
+
+#[test]
+pub fn stud_offset_bricks() {
+    let message = "Testing stacks whose studs don't line up";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+
+    let stacks = [
+        spawn_stack(
+            &mut world,
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.5, 0.0, 0.0),
+        ),
+        spawn_stack(
+            &mut world,
+            Vec3::new(10.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 0.5),
+        ),
+        spawn_stack(
+            &mut world,
+            Vec3::new(20.0, 0.0, 0.0),
+            Vec3::new(1.5, 0.0, 0.5),
+        ),
+        // Only the edges meet
+        spawn_stack(
+            &mut world,
+            Vec3::new(30.0, 0.0, 0.0),
+            Vec3::new(4.0, 0.0, 0.0),
+        ),
+    ];
+
+    sched_start.run(&mut world);
+    sched_update.run(&mut world);
+
+    for entity in stacks.into_iter().flatten() {
+        guarantee(
+            &mut world, message, entity, false, false, false, false, true, true,
+        );
+    }
+    assert_eq!(
+        get_models(&mut world).len(),
+        0,
+        "{} - Models exist",
+        message
+    );
+}
+
+#[test]
+pub fn stud_partial_bricks() {
+    let message = "Testing stacks sharing some of their studs";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+
+    let stacks = [
+        spawn_stack(
+            &mut world,
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+        ),
+        spawn_stack(
+            &mut world,
+            Vec3::new(10.0, 0.0, 0.0),
+            Vec3::new(-3.0, 0.0, 1.0),
+        ),
+        spawn_stack(
+            &mut world,
+            Vec3::new(20.0, 0.0, 0.0),
+            Vec3::new(2.0, 0.0, -1.0),
+        ),
+    ];
+
+    sched_start.run(&mut world);
+    sched_update.run(&mut world);
+
+    for entity in stacks.into_iter().flatten() {
+        guarantee(
+            &mut world, message, entity, false, false, true, false, true, false,
+        );
+    }
+    let models = get_models(&mut world);
+    assert_eq!(models.len(), 3, "{} - Three models don't exist", message);
+    for model_id in models {
+        guarantee_model(&mut world, message, model_id, 2, 0, 1);
+    }
+
+    // A wider pitch only fits every other stud
+    let (mut world, mut sched_start, _) = util_setup();
+    world.insert_resource(StudPitch(2.0));
+    let [a, b] = spawn_stack(
+        &mut world,
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(1.0, 0.0, 0.0),
+    );
+    let [c, d] = spawn_stack(
+        &mut world,
+        Vec3::new(10.0, 0.0, 0.0),
+        Vec3::new(2.0, 0.0, 0.0),
+    );
+    sched_start.run(&mut world);
+
+    for entity in [a, b] {
+        guarantee(
+            &mut world, message, entity, false, false, false, false, true, true,
+        );
+    }
+    for entity in [c, d] {
+        guarantee(
+            &mut world, message, entity, false, false, true, false, true, false,
+        );
+    }
+}
+
+#[test]
+pub fn stud_aligned_bricks() {
+    let message = "Testing stacks with every stud lined up";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+
+    let bottom = spawn_p(&mut world, false, Vec3::new(0.0, 0.0, 0.0));
+    let top = spawn_p(&mut world, false, Vec3::new(0.0, 1.0, 0.0));
+    // A 2x2 on one end and a 1x1 on the other
+    let small = world
+        .spawn((
+            Part::default(),
+            Physical,
+            Position(Vec3::new(-1.0, -1.0, 0.0)),
+            Size(Vec3::new(2.0, 1.0, 2.0)),
+        ))
+        .id();
+    let tiny = world
+        .spawn((
+            Part::default(),
+            Physical,
+            Position(Vec3::new(1.5, -1.0, 0.5)),
+            Size(Vec3::new(1.0, 1.0, 1.0)),
+        ))
+        .id();
+
+    sched_start.run(&mut world);
+    sched_update.run(&mut world);
+
+    for entity in [bottom, top, small, tiny] {
+        guarantee(
+            &mut world, message, entity, false, false, true, false, true, false,
+        );
+    }
+    let models = get_models(&mut world);
+    assert_eq!(models.len(), 1, "{} - Model doesn't exist", message);
+    guarantee_model(&mut world, message, models[0], 4, 0, 3);
+}
+
+fn spawn_turned(world: &mut World, position: Vec3, rotation: Quat, size: Vec3) -> Entity {
+    world
+        .spawn((
+            Part::default(),
+            Physical,
+            Position(position),
+            Rotation(rotation),
+            Size(size),
+        ))
+        .id()
+}
+
+#[test]
+pub fn rotated_bricks() {
+    let message = "Testing turned bricks";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+    let brick = Vec3::new(4.0, 1.0, 2.0);
+    let flipped = Quat::from_rotation_z(PI);
+
+    let joined = vec![
+        // Crossing a brick underneath
+        spawn_p(&mut world, false, Vec3::new(0.0, 0.0, 0.0)),
+        spawn_turned(
+            &mut world,
+            Vec3::new(0.0, 1.0, 0.0),
+            Quat::from_rotation_y(FRAC_PI_2),
+            brick,
+        ),
+        // Both upside down
+        spawn_turned(&mut world, Vec3::new(10.0, 0.0, 0.0), flipped, brick),
+        spawn_turned(&mut world, Vec3::new(10.0, -1.0, 0.0), flipped, brick),
+        // On its side, bottom into a side outlet
+        spawn_studs(
+            &mut world,
+            Vec3::new(20.0, 0.0, 0.0),
+            StudInfo {
+                right: StudType::Outlet,
+                ..Default::default()
+            },
+        ),
+        spawn_turned(
+            &mut world,
+            Vec3::new(22.5, 1.5, 0.0),
+            Quat::from_rotation_z(-FRAC_PI_2),
+            brick,
+        ),
+        // Turned the same odd angle, so the grids still line up
+        spawn_turned(
+            &mut world,
+            Vec3::new(30.0, 0.0, 0.0),
+            Quat::from_rotation_y(0.5),
+            brick,
+        ),
+        spawn_turned(
+            &mut world,
+            Vec3::new(30.0, 1.0, 0.0),
+            Quat::from_rotation_y(0.5),
+            brick,
+        ),
+    ];
+
+    let separate = vec![
+        // Studs meet studs
+        spawn_p(&mut world, false, Vec3::new(0.0, 0.0, 10.0)),
+        spawn_turned(&mut world, Vec3::new(0.0, 1.0, 10.0), flipped, brick),
+        // Faces meet but the grids cross at an angle
+        spawn_p(&mut world, false, Vec3::new(10.0, 0.0, 10.0)),
+        spawn_turned(
+            &mut world,
+            Vec3::new(10.0, 1.0, 10.0),
+            Quat::from_rotation_y(FRAC_PI_4),
+            brick,
+        ),
+        // Tilted, no faces are coplanar
+        spawn_p(&mut world, false, Vec3::new(20.0, 0.0, 10.0)),
+        spawn_turned(
+            &mut world,
+            Vec3::new(20.0, 1.5, 10.0),
+            Quat::from_rotation_x(0.3),
+            brick,
+        ),
+    ];
+
+    sched_start.run(&mut world);
+    sched_update.run(&mut world);
+
+    for entity in joined {
+        guarantee(
+            &mut world, message, entity, false, false, true, false, true, false,
+        );
+    }
+    for entity in separate {
+        guarantee(
+            &mut world, message, entity, false, false, false, false, true, true,
+        );
+    }
+
+    let models = get_models(&mut world);
+    assert_eq!(models.len(), 4, "{} - Four models don't exist", message);
+    for model_id in models {
+        guarantee_model(&mut world, message, model_id, 2, 0, 1);
+    }
+}
+
+#[test]
+pub fn right_angle_bricks() {
+    let message = "Testing every right angled turn";
+    let (mut world, mut sched_start, _) = util_setup();
+    let universal = StudInfo {
+        top: StudType::Universal,
+        bottom: StudType::Universal,
+        front: StudType::Universal,
+        back: StudType::Universal,
+        left: StudType::Universal,
+        right: StudType::Universal,
+    };
+
+    // Each face up, spun four ways
+    let faces = [
+        Quat::IDENTITY,
+        Quat::from_rotation_x(FRAC_PI_2),
+        Quat::from_rotation_x(PI),
+        Quat::from_rotation_x(-FRAC_PI_2),
+        Quat::from_rotation_z(FRAC_PI_2),
+        Quat::from_rotation_z(-FRAC_PI_2),
+    ];
+    let mut stacks = Vec::new();
+    for (i, face) in faces.iter().enumerate() {
+        for spin in 0..4 {
+            let rotation = Quat::from_rotation_y(spin as f32 * FRAC_PI_2) * *face;
+            let position = Vec3::new(i as f32 * 10.0, 0.0, spin as f32 * 10.0);
+            let bottom = spawn_p(&mut world, false, position);
+            let top = world
+                .spawn((
+                    Part::default(),
+                    Physical,
+                    Position(position + Vec3::new(0.0, 1.5, 0.0)),
+                    Rotation(rotation),
+                    Size(Vec3::new(2.0, 2.0, 2.0)),
+                    universal,
+                ))
+                .id();
+            stacks.push([bottom, top]);
+        }
+    }
+
+    sched_start.run(&mut world);
+
+    for entity in stacks.iter().flatten() {
+        guarantee(
+            &mut world, message, *entity, false, false, true, false, true, false,
+        );
+    }
+    assert_eq!(get_models(&mut world).len(), 24, "{}", message);
+}
+
+/// Collider's body and world position
+fn collider_of(world: &mut World, entity: Entity) -> (Option<RigidBodyHandle>, Vec3) {
+    let handle = world.get::<ShapeHandle>(entity).expect("No collider").0;
+    let collider = &world.resource::<PhysicsState>().colliders[handle];
+    let pos = collider.translation();
+    (collider.parent(), Vec3::new(pos.x, pos.y, pos.z))
+}
+
+#[test]
+pub fn join_model() {
+    let message = "Testing a brick placed on a model joins it";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+
+    let bottom = spawn_p(&mut world, false, Vec3::new(0.0, 0.0, 0.0));
+    let top = spawn_p(&mut world, false, Vec3::new(0.0, 1.0, 0.0));
+    sched_start.run(&mut world);
+    sched_update.run(&mut world);
+    let model_id = get_models(&mut world)[0];
+
+    // The model is falling, put it right on top of where it is now
+    let position = world.get::<Position>(top).unwrap().0 + Vec3::Y;
+    let new = spawn_p(&mut world, false, position);
+    sched_update.run(&mut world);
+
+    assert_eq!(get_models(&mut world).len(), 1, "{}", message);
+    guarantee_model(&mut world, message, model_id, 3, 0, 2);
+    guarantee(
+        &mut world, message, new, false, false, true, false, true, false,
+    );
+    let model_body = world.get::<BodyHandle>(model_id).unwrap().0;
+    let (parent, at) = collider_of(&mut world, new);
+    assert_eq!(parent, Some(model_body), "{} - Collider moved", message);
+    assert!(
+        at.abs_diff_eq(position, 0.01),
+        "{} - Collider stayed at {} instead of {}",
+        message,
+        at,
+        position
+    );
+    assert_eq!(
+        world.resource::<PhysicsState>().rigid_bodies.len(),
+        1,
+        "{} - Its own body is gone",
+        message
+    );
+
+    // Falls with the rest of the model
+    for _ in 0..30 {
+        sched_update.run(&mut world);
+    }
+    let [bottom, new] = [bottom, new].map(|e| world.get::<Position>(e).unwrap().0);
+    assert!(
+        (new - bottom).abs_diff_eq(Vec3::new(0.0, 2.0, 0.0), 0.01),
+        "{} - Moved apart",
+        message
+    );
+}
+
+#[test]
+pub fn join_loose_parts() {
+    let message = "Testing bricks placed on each other make a model";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+
+    let old = spawn_p(&mut world, false, Vec3::new(0.0, 0.0, 0.0));
+    let far = spawn_p(&mut world, false, Vec3::new(20.0, 0.0, 0.0));
+    sched_start.run(&mut world);
+    sched_update.run(&mut world);
+    assert!(get_models(&mut world).is_empty(), "{}", message);
+
+    // One on the old brick, and two new ones on each other
+    let position = world.get::<Position>(old).unwrap().0 + Vec3::Y;
+    let on_old = spawn_p(&mut world, false, position);
+    let pair = [
+        spawn_p(&mut world, false, Vec3::new(40.0, 0.0, 0.0)),
+        spawn_p(&mut world, false, Vec3::new(40.0, 1.0, 0.0)),
+    ];
+    sched_update.run(&mut world);
+
+    let models = get_models(&mut world);
+    assert_eq!(models.len(), 2, "{} - Two models don't exist", message);
+    for model_id in models {
+        guarantee_model(&mut world, message, model_id, 2, 0, 1);
+        body_check(&mut world, message, model_id, RigidBodyType::Dynamic);
+    }
+    for entity in [old, on_old, pair[0], pair[1]] {
+        guarantee(
+            &mut world, message, entity, false, false, true, false, true, false,
+        );
+    }
+    guarantee(
+        &mut world, message, far, false, false, false, false, true, true,
+    );
+    assert_eq!(
+        world.resource::<PhysicsState>().rigid_bodies.len(),
+        3,
+        "{} - Loose bodies are gone",
+        message
+    );
+}
+
+#[test]
+pub fn join_anchored() {
+    let message = "Testing bricks joining anchored models";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+
+    let plate = spawn_ps(
+        &mut world,
+        true,
+        Vec3::new(0.0, -1.0, 0.0),
+        Vec3::new(32.0, 1.0, 32.0),
+    );
+    // Two stacks on the plate, flat sides keep them apart
+    for x in [0.0, 4.0] {
+        spawn_p(&mut world, false, Vec3::new(x, 0.0, 0.0));
+        spawn_p(&mut world, false, Vec3::new(x, 1.0, 0.0));
+    }
+    sched_start.run(&mut world);
+    sched_update.run(&mut world);
+    assert_eq!(get_models(&mut world).len(), 2, "{}", message);
+
+    // A brick across both stacks, one straight on the plate, and a loose brick on a new anchor
+    let bridge = spawn_p(&mut world, false, Vec3::new(2.0, 2.0, 0.0));
+    let on_plate = spawn_p(&mut world, false, Vec3::new(10.0, 0.0, 0.0));
+    let loose = spawn_p(&mut world, false, Vec3::new(30.0, 0.0, 0.0));
+    let anchor = spawn_p(&mut world, true, Vec3::new(30.0, -1.0, 0.0));
+    sched_update.run(&mut world);
+
+    let models = get_models(&mut world);
+    assert_eq!(models.len(), 1, "{} - Stacks didn't merge", message);
+    guarantee_model(&mut world, message, models[0], 5, 1, 4);
+    body_check(&mut world, message, models[0], RigidBodyType::Fixed);
+    guarantee(
+        &mut world, message, bridge, false, false, true, false, true, false,
+    );
+
+    for (entity, source) in [(on_plate, plate), (loose, anchor)] {
+        guarantee(
+            &mut world, message, entity, true, false, false, false, true, true,
+        );
+        body_check(&mut world, message, entity, RigidBodyType::Fixed);
+        assert!(
+            world.get::<Anchored>(entity).unwrap().0.contains(&source),
+            "{}",
+            message
+        );
+        assert!(
+            world.resource::<AnchorMap>().anchors[&source].contains(&entity),
+            "{} - AnchorMap",
+            message
+        );
+    }
+
+    // Removing the new anchor goes through the AnchorMap like any other
+    world.despawn(anchor);
+    sched_update.run(&mut world);
+    guarantee(
+        &mut world, message, loose, false, false, false, false, true, true,
+    );
+}
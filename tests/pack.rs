@@ -0,0 +1,228 @@
+use freebricks::common::{
+    asset_cache::{AssetCache, AssetErrorCause, Image, Shader},
+    asset_pack::{AssetPack, PackEntry, build_pack},
+};
+use image::{ImageFormat, RgbaImage};
+use std::{fs, io::Cursor, path::Path};
+
+/// Small asset directory with a shader, an image and a file the cache ignores
+fn asset_dir(dir: &str) {
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(format!("{}/shaders", dir)).unwrap();
+    fs::create_dir_all(format!("{}/textures", dir)).unwrap();
+
+    let shader = "// Compresses well\n".repeat(64);
+    fs::write(format!("{}/shaders/bricks.wgsl", dir), shader).unwrap();
+    fs::write(format!("{}/notes.txt", dir), "ignored").unwrap();
+
+    let mut png = Vec::new();
+    RgbaImage::new(4, 12)
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .unwrap();
+    fs::write(format!("{}/textures/studs.png", dir), png).unwrap();
+}
+
+fn pack_bytes(dir: &str, compress: bool) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    build_pack(Path::new(dir), &mut bytes, compress).expect("Couldn't build archive");
+    bytes
+}
+
+#[test]
+pub fn pack_round_trip() {
+    let message = "Testing asset archives";
+    let dir = "target/pack-assets";
+    asset_dir(dir);
+    let loose = AssetCache::init(dir).expect("Couldn't load directory");
+
+    for compress in [false, true] {
+        let archive = format!("target/pack-assets-{}.fbpack", compress);
+        fs::write(&archive, pack_bytes(dir, compress)).unwrap();
+
+        let pack = AssetPack::open(Path::new(&archive)).expect("Couldn't open archive");
+        assert_eq!(
+            pack.entries.len(),
+            2,
+            "{} - Only assets are packed",
+            message
+        );
+        let shader = pack
+            .entries
+            .iter()
+            .find(|e| e.name == "shaders/bricks.wgsl")
+            .expect("Shader wasn't packed under its name inside the directory");
+        assert_eq!(
+            shader.compressed, compress,
+            "{} - Text should only deflate when asked to",
+            message
+        );
+
+        // Same contents as loading the directory, whose names keep the directory's own name
+        let packed = AssetCache::init(&archive).expect("Couldn't load archive");
+        assert_eq!(packed.map.len(), loose.map.len(), "{}", message);
+        for name in ["shaders/bricks.wgsl", "textures/studs.png"] {
+            assert!(
+                packed.map.contains_key(name),
+                "{} - Missing {}",
+                message,
+                name
+            );
+        }
+        let shader = packed
+            .load::<Shader>("shaders/bricks.wgsl")
+            .expect("Couldn't load packed shader");
+        assert_eq!(
+            Some(packed.get(&shader)),
+            loose
+                .get_shader("pack-assets/shaders/bricks.wgsl")
+                .map(String::as_str),
+            "{}",
+            message
+        );
+        let image = packed
+            .load::<Image>("textures/studs.png")
+            .expect("Couldn't load packed image");
+        assert_eq!(
+            Some(packed.get(&image)),
+            loose.get_image("pack-assets/textures/studs.png"),
+            "{}",
+            message
+        );
+    }
+
+    assert_eq!(
+        pack_bytes(dir, true),
+        pack_bytes(dir, true),
+        "{} - Packing should be deterministic",
+        message
+    );
+}
+
+#[test]
+pub fn pack_tampered() {
+    let message = "Testing asset archive integrity";
+    let dir = "target/tamper-assets";
+    asset_dir(dir);
+    let bytes = pack_bytes(dir, false);
+    let pack = AssetPack::from_bytes(Path::new("tamper.fbpack"), bytes.clone())
+        .expect("Couldn't read archive");
+
+    // Flip a byte inside the image, the shader still loads
+    let image = pack
+        .entries
+        .iter()
+        .find(|e| e.name.ends_with("studs.png"))
+        .unwrap();
+    let data_start = bytes.len() - pack.entries.iter().map(|e| e.stored_len).sum::<u64>() as usize;
+    let mut tampered = bytes.clone();
+    tampered[data_start + image.offset as usize + 20] ^= 0xFF;
+
+    let archive = "target/tamper-assets.fbpack";
+    fs::write(archive, &tampered).unwrap();
+    let assets = AssetCache::init(archive).expect("One bad entry shouldn't fail the archive");
+    let e = assets
+        .load::<Image>("textures/studs.png")
+        .expect_err("Tampered image loaded");
+    assert!(
+        matches!(e.cause, AssetErrorCause::Tampered(_)),
+        "{} - {}",
+        message,
+        e
+    );
+    assert!(
+        assets.load::<Shader>("shaders/bricks.wgsl").is_ok(),
+        "{} - Untouched entry should still load",
+        message
+    );
+
+    // Compressed entries are checked after inflating
+    let bytes = pack_bytes(dir, true);
+    let pack = AssetPack::from_bytes(Path::new("tamper.fbpack"), bytes.clone()).unwrap();
+    let index = pack.entries.iter().position(|e| e.compressed).unwrap();
+    let shader = &pack.entries[index];
+    let data_start = bytes.len() - pack.entries.iter().map(|e| e.stored_len).sum::<u64>() as usize;
+    let mut tampered = bytes.clone();
+    tampered[data_start + shader.offset as usize + shader.stored_len as usize / 2] ^= 0x01;
+    let pack = AssetPack::from_bytes(Path::new("tamper.fbpack"), tampered).unwrap();
+    assert!(
+        pack.read(&pack.entries[index]).is_err(),
+        "{} - Tampered compressed entry read back",
+        message
+    );
+
+    // Lengths that don't match the inflated entry fail without allocating them
+    let pack = AssetPack::from_bytes(Path::new("tamper.fbpack"), bytes.clone()).unwrap();
+    for len in [u64::MAX, 1] {
+        let entry = PackEntry {
+            len,
+            ..pack.entries[index].clone()
+        };
+        let e = pack
+            .read(&entry)
+            .expect_err("Entry with the wrong length read back");
+        assert!(
+            matches!(e.cause, AssetErrorCause::Tampered(_)),
+            "{} - {}",
+            message,
+            e
+        );
+    }
+
+    // Renaming an entry in the table of contents fails the whole archive
+    let mut tampered = bytes.clone();
+    let name = tampered.windows(7).position(|w| w == b"shaders").unwrap();
+    tampered[name] ^= 0x20;
+    assert!(
+        AssetPack::from_bytes(Path::new("tamper.fbpack"), tampered).is_err(),
+        "{} - Modified toc was accepted",
+        message
+    );
+
+    // Cut short
+    let truncated = bytes[..bytes.len() - 1].to_vec();
+    assert!(
+        AssetPack::from_bytes(Path::new("tamper.fbpack"), truncated).is_err(),
+        "{} - Truncated archive was accepted",
+        message
+    );
+    assert!(
+        AssetPack::from_bytes(Path::new("tamper.fbpack"), b"nope".to_vec()).is_err(),
+        "{}",
+        message
+    );
+}
+
+#[test]
+pub fn pack_nested_directory() {
+    let message = "Testing archives packed from a nested directory";
+    let dir = "target/pack-nested/game/assets";
+    asset_dir(dir);
+
+    // The same directory reached through a longer path
+    for path in [dir.to_string(), format!("./{}", dir)] {
+        let archive = "target/pack-nested.fbpack";
+        fs::write(archive, pack_bytes(&path, true)).unwrap();
+
+        let pack = AssetPack::open(Path::new(archive)).expect("Couldn't open archive");
+        let mut names: Vec<_> = pack.entries.iter().map(|e| e.name.as_str()).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec!["shaders/bricks.wgsl", "textures/studs.png"],
+            "{} - Names start inside {}",
+            message,
+            path
+        );
+
+        let assets = AssetCache::init(archive).expect("Couldn't load archive");
+        let shader = assets
+            .load::<Shader>("shaders/bricks.wgsl")
+            .expect("Couldn't load packed shader");
+        assert_eq!(
+            assets.get(&shader),
+            "// Compresses well\n".repeat(64),
+            "{}",
+            message
+        );
+    }
+}
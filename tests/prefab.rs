@@ -0,0 +1,204 @@
+use bevy_ecs::prelude::*;
+use freebricks::{
+    common::asset_cache::AssetCache,
+    ecs::{
+        common::{Color, Position, Rotation},
+        physics::BodyHandle,
+    },
+    physics::PhysicsState,
+    scene::prefab::Prefab,
+};
+use glam::{Quat, Vec3};
+use rapier3d::prelude::*;
+use std::{f32::consts::FRAC_PI_2, fs};
+mod test_utils;
+use crate::test_utils::*;
+
+#[test]
+pub fn prefab_capture() {
+    let message = "Testing capturing a model as a prefab";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+
+    let entities: Vec<_> = (0..3)
+        .map(|y| spawn_p(&mut world, false, Vec3::new(5.0, y as f32, 0.0)))
+        .collect();
+    world
+        .entity_mut(entities[2])
+        .insert(Color([255, 0, 0, 255]));
+    sched_start.run(&mut world);
+    sched_update.run(&mut world);
+
+    let models = get_models(&mut world);
+    let prefab = Prefab::from_model(&mut world, models[0]).expect("Couldn't capture prefab");
+    assert_eq!(prefab.parts.len(), 3, "{}", message);
+    assert_eq!(prefab.connections.len(), 2, "{} - Connections", message);
+
+    // Pivot is the bottom center
+    let mut heights: Vec<_> = prefab.parts.iter().map(|p| p.position).collect();
+    heights.sort_by(|a, b| a.y.total_cmp(&b.y));
+    assert!(
+        heights[0].abs_diff_eq(Vec3::new(0.0, 0.5, 0.0), 1e-3),
+        "{} - Pivot, got {}",
+        message,
+        heights[0]
+    );
+    assert!(
+        prefab.parts.iter().any(|p| p.color == [255, 0, 0, 255]),
+        "{}",
+        message
+    );
+
+    let source = prefab.to_ron().expect("Couldn't write prefab");
+    assert_eq!(
+        Prefab::from_ron(&source).expect("Couldn't read prefab"),
+        prefab,
+        "{} - Round trip",
+        message
+    );
+    assert!(Prefab::from_model(&mut world, entities[0]).is_err());
+}
+
+#[test]
+pub fn prefab_turned_pivot() {
+    let message = "Testing prefab pivots of models with turned parts";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+
+    // Second brick lies across the first and sticks out past its back
+    spawn_p(&mut world, false, Vec3::new(5.0, 0.0, 0.0));
+    let turned = spawn_p(&mut world, false, Vec3::new(5.0, 1.0, 2.0));
+    world
+        .entity_mut(turned)
+        .insert(Rotation(Quat::from_rotation_y(FRAC_PI_2)));
+    sched_start.run(&mut world);
+    sched_update.run(&mut world);
+
+    let models = get_models(&mut world);
+    assert_eq!(models.len(), 1, "{} - Bricks didn't snap", message);
+    let prefab = Prefab::from_model(&mut world, models[0]).expect("Couldn't capture prefab");
+
+    let mut min = Vec3::MAX;
+    let mut max = Vec3::MIN;
+    for part in &prefab.parts {
+        let half = (part.rotation * part.size).abs() / 2.0;
+        min = min.min(part.position - half);
+        max = max.max(part.position + half);
+    }
+    let pivot = Vec3::new((min.x + max.x) / 2.0, min.y, (min.z + max.z) / 2.0);
+    assert!(
+        pivot.abs_diff_eq(Vec3::ZERO, 1e-4),
+        "{} - Pivot off the bottom center by {}",
+        message,
+        pivot
+    );
+}
+
+#[test]
+pub fn prefab_spawn() {
+    let message = "Testing spawning prefabs";
+    let source = "(
+        version: 1,
+        parts: [
+            (position: (0.0, 0.5, 0.0)),
+            (position: (0.0, 1.5, 0.0)),
+            (position: (1.0, 2.5, 0.0), size: (2.0, 1.0, 2.0)),
+        ],
+        connections: [(0, 1), (1, 2)],
+    )";
+    let prefab = Prefab::from_ron(source).expect("Couldn't parse prefab");
+
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+    sched_start.run(&mut world);
+
+    let rotation = Quat::from_rotation_y(FRAC_PI_2);
+    let first = prefab
+        .spawn(&mut world, Vec3::new(10.0, 0.0, 0.0), rotation)
+        .expect("Couldn't spawn prefab");
+    let second = prefab
+        .spawn(&mut world, Vec3::new(-10.0, 0.0, 0.0), Quat::IDENTITY)
+        .expect("Couldn't spawn prefab");
+
+    for model in [first, second] {
+        guarantee_model(&mut world, message, model, 3, 0, 2);
+        body_check(&mut world, message, model, RigidBodyType::Dynamic);
+        let children: Vec<_> = world
+            .entity(model)
+            .get::<Children>()
+            .unwrap()
+            .iter()
+            .collect();
+        for child in children {
+            guarantee(
+                &mut world, message, child, false, false, true, false, true, false,
+            );
+            collider_check(&mut world, message, child);
+        }
+    }
+
+    // Offset part is turned with the prefab
+    let top = world.entity(first).get::<Children>().unwrap()[2];
+    let position = world.entity(top).get::<Position>().unwrap().0;
+    assert!(
+        position.abs_diff_eq(Vec3::new(10.0, 2.5, -1.0), 1e-4),
+        "{} - Rotated placement, got {}",
+        message,
+        position
+    );
+
+    // Update schedule mustn't give them another body
+    let body = *world.entity(first).get::<BodyHandle>().unwrap();
+    let bodies = world.resource::<PhysicsState>().rigid_bodies.len();
+    sched_update.run(&mut world);
+    assert_eq!(
+        world.entity(first).get::<BodyHandle>().unwrap().0,
+        body.0,
+        "{}",
+        message
+    );
+    assert_eq!(
+        world.resource::<PhysicsState>().rigid_bodies.len(),
+        bodies,
+        "{} - Extra bodies",
+        message
+    );
+
+    let broken = Prefab {
+        connections: vec![(0, 7)],
+        ..prefab.clone()
+    };
+    assert!(
+        broken
+            .spawn(&mut world, Vec3::ZERO, Quat::IDENTITY)
+            .is_err()
+    );
+    assert!(Prefab::from_ron("(version: 99, parts: [], connections: [])").is_err());
+}
+
+#[test]
+pub fn prefab_asset() {
+    let message = "Testing loading prefabs through the asset cache";
+    let dir = "target/prefab-assets";
+    fs::create_dir_all(dir).unwrap();
+    fs::write(
+        format!("{}/tower.prefab", dir),
+        "(version: 1, parts: [(), (position: (0.0, 1.0, 0.0))], connections: [(0, 1)])",
+    )
+    .unwrap();
+    fs::write(format!("{}/broken.prefab", dir), "(version: ").unwrap();
+
+    let assets = AssetCache::init(dir).expect("Couldn't load assets");
+    let prefab = assets
+        .get_prefab("prefab-assets/tower.prefab")
+        .expect("Prefab wasn't loaded");
+    assert_eq!(prefab.parts.len(), 2, "{}", message);
+    assert!(
+        assets.get_prefab("prefab-assets/broken.prefab").is_none(),
+        "{} - Broken prefab was loaded",
+        message
+    );
+
+    let (mut world, _, _) = util_setup();
+    let model = prefab
+        .spawn(&mut world, Vec3::ZERO, Quat::IDENTITY)
+        .expect("Couldn't spawn prefab");
+    guarantee_model(&mut world, message, model, 2, 0, 1);
+}
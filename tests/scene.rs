@@ -0,0 +1,370 @@
+use bevy_ecs::prelude::*;
+use freebricks::{
+    ecs::{
+        common::{Color, Position, Rotation, Size},
+        parts::{Part, StudInfo, StudType},
+        physics::{Anchor, Physical},
+    },
+    scene::{
+        BINARY_VERSION, BinarySceneReader, GRID_DIVISIONS, PartDesc, SCENE_VERSION,
+        SPAWN_BATCH_SIZE, Scene, build_scene, write_binary,
+    },
+};
+use glam::{EulerRot, Quat, Vec3};
+use rapier3d::prelude::*;
+use std::f32::consts::FRAC_PI_2;
+mod test_utils;
+use crate::test_utils::*;
+
+#[test]
+pub fn text_round_trip() {
+    let message = "Testing text scene round trip";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+
+    let positions = vec![
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(8.0, 0.0, 0.0),
+    ];
+    for position in positions {
+        spawn_p(&mut world, false, position);
+    }
+    spawn_p(&mut world, true, Vec3::new(0.0, -1.0, 0.0));
+
+    sched_start.run(&mut world);
+    sched_update.run(&mut world);
+
+    let scene = Scene::from_world(&mut world);
+    assert_eq!(
+        scene.parts.len(),
+        4,
+        "{} - Model children weren't saved",
+        message
+    );
+
+    let source = scene.to_ron().expect("Couldn't write scene");
+    let loaded = Scene::from_ron(&source).expect("Couldn't read scene");
+    assert_eq!(scene, loaded, "{} - Scenes don't match", message);
+
+    let (mut world, _, _) = util_setup();
+    loaded.load(&mut world);
+
+    let models = get_models(&mut world);
+    assert_eq!(models.len(), 1, "{} - Model wasn't rebuilt", message);
+    guarantee_model(&mut world, message, models[0], 2, 1, 1);
+    body_check(&mut world, message, models[0], RigidBodyType::Fixed);
+}
+
+#[test]
+pub fn text_defaults() {
+    let message = "Testing omitted fields in text scenes";
+    let source = format!(
+        "(version: {}, parts: [(position: (1.0, 2.0, 3.0), color: (255, 0, 0, 255), studs: (top: Flat, bottom: Inlet))])",
+        SCENE_VERSION
+    );
+    let scene = Scene::from_ron(&source).expect("Couldn't read scene");
+
+    let (mut world, _, _) = util_setup();
+    let entities = scene.load(&mut world);
+    assert_eq!(entities.len(), 1, "{} - Part wasn't spawned", message);
+
+    let entity = entities[0];
+    guarantee(
+        &mut world, message, entity, false, false, false, false, true, true,
+    );
+
+    let mut query = world.query::<(&Position, &Color, &StudInfo)>();
+    let (position, color, studs) = query.get(&world, entity).unwrap();
+    assert_eq!(position.0, Vec3::new(1.0, 2.0, 3.0), "{}", message);
+    assert_eq!(color.0, [255, 0, 0, 255], "{}", message);
+    assert_eq!(studs.top, StudType::Flat, "{}", message);
+    assert_eq!(scene.parts[0].size, PartDesc::default().size, "{}", message);
+}
+
+#[test]
+pub fn text_rejects_newer_version() {
+    let source = format!("(version: {}, parts: [])", SCENE_VERSION + 1);
+    assert!(Scene::from_ron(&source).is_err());
+}
+
+#[test]
+pub fn binary_round_trip() {
+    let message = "Testing binary scene round trip";
+    let mut world = World::new();
+
+    // More than one spawn batch worth of parts
+    let count = SPAWN_BATCH_SIZE + 100;
+    for i in 0..count {
+        let x = (i % 64) as f32 * 4.0;
+        let z = (i / 64) as f32 * 2.0;
+        let mut entity = world.spawn((
+            Part::default(),
+            Position(Vec3::new(x, 0.5, z + 0.5)),
+            Color([(i % 7) as u8 * 30, 0, 0, 255]),
+        ));
+        match i % 5 {
+            0 => {
+                entity.insert(Anchor);
+            }
+            1 => {
+                entity.insert(Rotation(Quat::from_rotation_y(FRAC_PI_2)));
+            }
+            2 => {
+                entity.insert(Rotation(Quat::from_euler(EulerRot::XYZ, 0.3, 1.1, -0.2)));
+            }
+            3 => {
+                entity.insert((
+                    Size(Vec3::new(1.0, 1.0 / 3.0, 2.0)),
+                    StudInfo {
+                        top: StudType::Flat,
+                        bottom: StudType::Inlet,
+                        ..StudInfo::FLAT
+                    },
+                ));
+            }
+            _ => {
+                entity.remove::<Physical>();
+            }
+        }
+    }
+
+    let scene = Scene::from_world(&mut world);
+    let mut buffer = Vec::new();
+    write_binary(&scene, &mut buffer).expect("Couldn't write binary scene");
+
+    let reader = BinarySceneReader::new(buffer.as_slice()).expect("Couldn't read header");
+    assert_eq!(reader.count() as usize, count, "{} - Header count", message);
+
+    let mut loaded_world = World::new();
+    let entities = reader
+        .spawn(&mut loaded_world)
+        .expect("Couldn't read binary scene");
+    assert_eq!(entities.len(), count, "{} - Spawned count", message);
+
+    let original = part_components(&mut world);
+    let loaded = part_components(&mut loaded_world);
+
+    for (a, b) in original.iter().zip(loaded.iter()) {
+        assert_eq!(a.0, b.0, "{} - Part kind", message);
+        assert!(a.1.abs_diff_eq(b.1, 1e-4), "{} - Position", message);
+        assert!(
+            a.2.angle_between(b.2) < 0.01,
+            "{} - Rotation {:?} {:?}",
+            message,
+            a.2,
+            b.2
+        );
+        assert!(a.3.abs_diff_eq(b.3, 1e-4), "{} - Size", message);
+        assert_eq!(a.4, b.4, "{} - Color", message);
+        assert_eq!(a.5, b.5, "{} - Studs", message);
+        assert_eq!(a.6, b.6, "{} - Anchor", message);
+        assert_eq!(a.7, b.7, "{} - Physical", message);
+    }
+
+    // Axis aligned rotations are stored exactly
+    let rotated = loaded
+        .iter()
+        .filter(|item| item.2.abs_diff_eq(Quat::from_rotation_y(FRAC_PI_2), 1e-6))
+        .count();
+    assert_eq!(rotated, count / 5, "{} - Axis rotations", message);
+}
+
+#[test]
+pub fn binary_quantizes_to_grid() {
+    let scene = Scene {
+        parts: vec![PartDesc {
+            position: Vec3::new(0.501, 1.0 / 3.0 + 0.002, -2.0),
+            size: Vec3::new(4.0, 0.334, 2.0),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    let mut buffer = Vec::new();
+    write_binary(&scene, &mut buffer).unwrap();
+    let loaded = BinarySceneReader::new(buffer.as_slice())
+        .unwrap()
+        .into_scene()
+        .unwrap();
+
+    let part = &loaded.parts[0];
+    assert_eq!(part.position * GRID_DIVISIONS, Vec3::new(6.0, 4.0, -24.0));
+    assert_eq!(part.size * GRID_DIVISIONS, Vec3::new(48.0, 4.0, 24.0));
+}
+
+#[test]
+pub fn binary_builds_models() {
+    let message = "Testing binary scene builds models";
+    let scene = Scene {
+        parts: vec![
+            PartDesc::default(),
+            PartDesc {
+                position: Vec3::new(0.0, 1.0, 0.0),
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    };
+    let mut buffer = Vec::new();
+    write_binary(&scene, &mut buffer).unwrap();
+
+    let (mut world, _, _) = util_setup();
+    BinarySceneReader::new(buffer.as_slice())
+        .unwrap()
+        .spawn(&mut world)
+        .unwrap();
+    build_scene(&mut world);
+
+    let models = get_models(&mut world);
+    assert_eq!(models.len(), 1, "{} - Model wasn't built", message);
+    guarantee_model(&mut world, message, models[0], 2, 0, 1);
+}
+
+#[test]
+pub fn binary_rejects_garbage() {
+    assert!(BinarySceneReader::new(&b"not a scene"[..]).is_err());
+
+    let mut buffer = Vec::new();
+    write_binary(&Scene::default(), &mut buffer).unwrap();
+    buffer[4] = 0xFF;
+    assert!(BinarySceneReader::new(buffer.as_slice()).is_err());
+}
+
+#[test]
+pub fn binary_palette_limit() {
+    let message = "Testing binary scene palette size";
+    let colors = |count: u32| Scene {
+        parts: (0..count)
+            .map(|i| PartDesc {
+                color: [(i >> 8) as u8, i as u8, (i >> 16) as u8, 255],
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    };
+
+    // Every color fits in a u16 index
+    let mut buffer = Vec::new();
+    write_binary(&colors(u16::MAX as u32), &mut buffer).expect("Full palette should fit");
+    assert_eq!(
+        u16::from_le_bytes([buffer[12], buffer[13]]),
+        u16::MAX,
+        "{} - Palette length",
+        message
+    );
+
+    // One more can't be counted in the header
+    assert!(
+        write_binary(&colors(u16::MAX as u32 + 1), &mut Vec::new()).is_err(),
+        "{} - Palette length overflowed",
+        message
+    );
+}
+
+#[test]
+pub fn binary_grid_limits() {
+    let message = "Testing binary scene position and size range";
+    let write = |position: Vec3, size: Vec3| {
+        let scene = Scene {
+            parts: vec![PartDesc {
+                position,
+                size,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut buffer = Vec::new();
+        write_binary(&scene, &mut buffer).map(|_| buffer)
+    };
+
+    // Largest size that fits in a u16 of grid units, and a far away position
+    let size = Vec3::new(u16::MAX as f32 / GRID_DIVISIONS, 1.0, 2.0);
+    let position = Vec3::new(100_000_000.0, -100_000_000.0, 0.0);
+    let buffer = write(position, size).expect("Couldn't write scene at the limits");
+    let loaded = BinarySceneReader::new(buffer.as_slice())
+        .unwrap()
+        .into_scene()
+        .unwrap();
+    assert!(
+        loaded.parts[0].size.abs_diff_eq(size, 1e-3),
+        "{} - Size {}",
+        message,
+        loaded.parts[0].size
+    );
+    assert_eq!(loaded.parts[0].position, position, "{}", message);
+
+    // Anything past that fails instead of saturating
+    let too_long = Vec3::new((u16::MAX as f32 + 1.0) / GRID_DIVISIONS, 1.0, 2.0);
+    assert!(write(Vec3::ZERO, too_long).is_err(), "{} - Size", message);
+    assert!(
+        write(Vec3::ZERO, Vec3::new(-1.0, 1.0, 1.0)).is_err(),
+        "{} - Negative size",
+        message
+    );
+    let too_far = Vec3::new(0.0, 200_000_000.0, 0.0);
+    assert!(write(too_far, Vec3::ONE).is_err(), "{} - Position", message);
+    assert!(
+        write(Vec3::new(f32::NAN, 0.0, 0.0), Vec3::ONE).is_err(),
+        "{} - NaN position",
+        message
+    );
+}
+
+#[test]
+pub fn binary_reads_version_3() {
+    let message = "Testing binary scenes from before labels";
+    let scene = Scene {
+        parts: vec![PartDesc {
+            position: Vec3::new(0.0, 1.0, 0.0),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let mut buffer = Vec::new();
+    write_binary(&scene, &mut buffer).unwrap();
+    assert_eq!(
+        u16::from_le_bytes([buffer[4], buffer[5]]),
+        BINARY_VERSION,
+        "{}",
+        message
+    );
+
+    // Version 3 has the same layout without labels, the flag bit meant nothing yet
+    buffer[4..6].copy_from_slice(&3u16.to_le_bytes());
+    let flags = 4 + 2 + 2 + 4 + 2 + 4 + 1;
+    buffer[flags] |= 0x08;
+    let loaded = BinarySceneReader::new(buffer.as_slice())
+        .expect("Couldn't read version 3 header")
+        .into_scene()
+        .expect("Couldn't read version 3 scene");
+    assert_eq!(loaded.parts.len(), 1, "{}", message);
+    let part = &loaded.parts[0];
+    assert_eq!(part.name, None, "{} - Labels read from version 3", message);
+    assert_eq!(part.position, scene.parts[0].position, "{}", message);
+}
+
+type PartComponents = (Part, Vec3, Quat, Vec3, [u8; 4], StudInfo, bool, bool);
+
+/// Owned part components sorted by position so two worlds can be compared
+fn part_components(world: &mut World) -> Vec<PartComponents> {
+    let mut query = world.query::<(
+        &Part,
+        &Position,
+        &Rotation,
+        &Size,
+        &Color,
+        &StudInfo,
+        Has<Anchor>,
+        Has<Physical>,
+    )>();
+    let mut parts: Vec<PartComponents> = query
+        .iter(world)
+        .map(|(part, pos, rot, size, color, studs, anchor, physical)| {
+            (
+                *part, pos.0, rot.0, size.0, color.0, *studs, anchor, physical,
+            )
+        })
+        .collect();
+    parts.sort_by_key(|item| (item.1.x as i32, item.1.z as i32));
+    parts
+}
@@ -0,0 +1,196 @@
+use bevy_ecs::prelude::*;
+use freebricks::{
+    common::selector::{QSelect, Selector},
+    ecs::{
+        common::{Color, Position, Rotation, Size, Tags},
+        parts::Part,
+    },
+    scene::{BinarySceneReader, PartDesc, Scene, write_binary},
+};
+use glam::{Quat, Vec3};
+use std::f32::consts::FRAC_PI_2;
+
+const RED: [u8; 4] = [255, 0, 0, 255];
+
+fn sorted(mut entities: Vec<Entity>) -> Vec<Entity> {
+    entities.sort();
+    entities
+}
+
+#[test]
+pub fn selector_queries() {
+    let message = "Testing part selectors";
+    let mut world = World::new();
+
+    let door = world
+        .spawn((
+            Part::Brick,
+            Name::new("Door"),
+            Tags::new(["house", "moving"]),
+            Color(RED),
+            Position(Vec3::new(0.0, 0.0, 0.0)),
+        ))
+        .id();
+    let wall = world
+        .spawn((
+            Part::Brick,
+            Name::new("Wall"),
+            Tags::new(["house"]),
+            Position(Vec3::new(10.0, 0.0, 0.0)),
+        ))
+        .id();
+    let ball = world
+        .spawn((Part::Ball, Color(RED), Position(Vec3::new(20.0, 0.0, 0.0))))
+        .id();
+    // Turned so its long side runs along X
+    let beam = world
+        .spawn((
+            Part::Brick,
+            Position(Vec3::new(30.0, 0.0, 0.0)),
+            Rotation(Quat::from_rotation_y(FRAC_PI_2)),
+            Size(Vec3::new(1.0, 1.0, 20.0)),
+        ))
+        .id();
+
+    assert_eq!(
+        Selector::new().named("Door").select(&mut world),
+        vec![door],
+        "{} - Name",
+        message
+    );
+    assert_eq!(
+        sorted(Selector::new().tagged("house").select(&mut world)),
+        vec![door, wall],
+        "{} - Tag",
+        message
+    );
+    assert_eq!(
+        Selector::new()
+            .tagged("house")
+            .tagged("moving")
+            .select(&mut world),
+        vec![door],
+        "{} - Every tag has to match",
+        message
+    );
+    assert_eq!(
+        sorted(Selector::new().colored(RED).select(&mut world)),
+        vec![door, ball],
+        "{} - Color",
+        message
+    );
+    assert_eq!(
+        Selector::new()
+            .colored(RED)
+            .part(Part::Ball)
+            .first(&mut world),
+        Some(ball),
+        "{} - Color and kind",
+        message
+    );
+    assert_eq!(
+        sorted(Selector::new().select(&mut world)),
+        vec![door, wall, ball, beam],
+        "{} - Empty selector matches everything",
+        message
+    );
+    assert!(
+        Selector::new().named("Roof").select(&mut world).is_empty(),
+        "{}",
+        message
+    );
+
+    // Regions overlap the part's turned bounds, not just its position
+    assert_eq!(
+        Selector::new()
+            .within(Vec3::new(38.0, -1.0, -0.2), Vec3::new(39.0, 1.0, 0.2))
+            .select(&mut world),
+        vec![beam],
+        "{} - Region",
+        message
+    );
+    assert_eq!(
+        sorted(
+            Selector::new()
+                .within(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(11.0, 1.0, 1.0))
+                .select(&mut world)
+        ),
+        vec![door, wall],
+        "{} - Region",
+        message
+    );
+
+    // Live in a system, parts tagged later are picked up on the next run
+    let mut schedule = Schedule::default();
+    schedule.add_systems(|mut commands: Commands, parts: Query<QSelect>| {
+        for part in Selector::new().tagged("doomed").iter(&parts) {
+            commands.entity(part.entity).despawn();
+        }
+    });
+    schedule.run(&mut world);
+    assert_eq!(world.entities().len(), 4, "{}", message);
+
+    world.entity_mut(wall).insert(Tags::new(["doomed"]));
+    schedule.run(&mut world);
+    assert!(world.get_entity(wall).is_err(), "{} - Live query", message);
+    assert_eq!(world.entities().len(), 3, "{}", message);
+}
+
+#[test]
+pub fn selector_scenes() {
+    let message = "Testing names and tags in scenes";
+    let scene = Scene {
+        parts: vec![
+            PartDesc {
+                name: Some("Door".to_string()),
+                tags: vec!["house".to_string(), "moving".to_string()],
+                ..Default::default()
+            },
+            PartDesc {
+                tags: vec!["house".to_string()],
+                ..Default::default()
+            },
+            PartDesc::default(),
+        ],
+        ..Default::default()
+    };
+
+    let text = scene.to_ron().unwrap();
+    assert!(
+        !text.contains("name: None"),
+        "{} - Unnamed parts stay short",
+        message
+    );
+    let loaded = Scene::from_ron(&text).expect("Couldn't read scene");
+    assert_eq!(scene, loaded, "{} - Text", message);
+
+    let mut buffer = Vec::new();
+    write_binary(&scene, &mut buffer).unwrap();
+    let loaded = BinarySceneReader::new(buffer.as_slice())
+        .unwrap()
+        .into_scene()
+        .unwrap();
+    assert_eq!(scene.parts, loaded.parts, "{} - Binary", message);
+
+    // Streamed straight into a world
+    let mut world = World::new();
+    BinarySceneReader::new(buffer.as_slice())
+        .unwrap()
+        .spawn(&mut world)
+        .unwrap();
+    assert!(
+        Selector::new()
+            .named("Door")
+            .tagged("moving")
+            .first(&mut world)
+            .is_some(),
+        "{}",
+        message
+    );
+    assert_eq!(
+        Scene::from_world(&mut world).parts,
+        scene.parts,
+        "{} - Saved again",
+        message
+    );
+}
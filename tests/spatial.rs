@@ -0,0 +1,149 @@
+use bevy_ecs::prelude::*;
+use freebricks::{
+    common::spatial::{SpatialIndex, part_bounds},
+    ecs::{
+        common::{Position, Rotation, Size},
+        model::Model,
+        parts::Part,
+    },
+};
+use glam::{Quat, Vec3};
+use std::f32::consts::FRAC_PI_2;
+mod test_utils;
+use crate::test_utils::*;
+
+#[test]
+pub fn spatial_queries() {
+    let message = "Testing spatial index queries";
+    let mut world = World::new();
+    let [a, b, c, far, plate] = [(); 5].map(|_| world.spawn_empty().id());
+    let mut index = SpatialIndex::default();
+
+    // Two bricks on a cell border, one stacked on b, one far away
+    index.insert(a, Vec3::new(0.0, 0.0, 0.0), Vec3::new(4.0, 1.0, 2.0));
+    index.insert(b, Vec3::new(4.0, 0.0, 0.0), Vec3::new(8.0, 1.0, 2.0));
+    index.insert(c, Vec3::new(4.0, 1.0, 0.0), Vec3::new(8.0, 2.0, 2.0));
+    index.insert(far, Vec3::new(100.0, 0.0, 0.0), Vec3::new(104.0, 1.0, 2.0));
+    assert_eq!(index.len(), 4, "{}", message);
+
+    assert_eq!(
+        index.neighbours(a),
+        vec![b, c],
+        "{} - Touching counts",
+        message
+    );
+    assert_eq!(index.neighbours(far), vec![], "{}", message);
+    assert_eq!(
+        index.query_point(Vec3::new(6.0, 1.5, 1.0)),
+        vec![c],
+        "{} - Point",
+        message
+    );
+    assert_eq!(
+        index.query(Vec3::new(-1000.0, -1.0, -1.0), Vec3::new(1000.0, 1.0, 1.0)),
+        vec![a, b, c, far],
+        "{} - Boxes over many cells",
+        message
+    );
+
+    // Moving replaces the old bounds
+    index.insert(c, Vec3::new(100.0, 1.0, 0.0), Vec3::new(104.0, 2.0, 2.0));
+    assert_eq!(index.neighbours(a), vec![b], "{} - Moved", message);
+    assert_eq!(index.neighbours(far), vec![c], "{} - Moved", message);
+
+    assert!(index.remove(b), "{}", message);
+    assert!(!index.remove(b), "{}", message);
+    assert_eq!(index.neighbours(a), vec![], "{} - Removed", message);
+
+    // Parts too big for the grid are still found
+    index.insert(
+        plate,
+        Vec3::new(-512.0, -1.0, -512.0),
+        Vec3::new(512.0, 0.0, 512.0),
+    );
+    assert_eq!(index.neighbours(plate), vec![a, far], "{}", message);
+    assert_eq!(index.neighbours(a), vec![plate], "{} - Oversized", message);
+
+    // Turned parts are indexed by their turned bounds
+    let (min, max) = part_bounds(
+        &Position(Vec3::ZERO),
+        &Rotation(Quat::from_rotation_y(FRAC_PI_2)),
+        &Size(Vec3::new(4.0, 1.0, 2.0)),
+    );
+    assert!(
+        min.abs_diff_eq(Vec3::new(-1.0, -0.5, -2.0), 1e-5)
+            && max.abs_diff_eq(Vec3::new(1.0, 0.5, 2.0), 1e-5),
+        "{} - Turned bounds",
+        message
+    );
+}
+
+#[test]
+pub fn spatial_world() {
+    let message = "Testing spatial index follows the world";
+    let (mut world, mut sched_start, _) = util_setup();
+    let mut update = Schedule::default();
+    update.add_systems(SpatialIndex::update);
+
+    let a = spawn_p(&mut world, false, Vec3::new(0.0, 0.0, 0.0));
+    let b = spawn_p(&mut world, false, Vec3::new(0.0, 1.0, 0.0));
+    sched_start.run(&mut world);
+    assert_eq!(
+        world.resource::<SpatialIndex>().neighbours(a),
+        vec![b],
+        "{} - Filled by build_models",
+        message
+    );
+
+    world.get_mut::<Position>(b).unwrap().0 = Vec3::new(50.0, 0.0, 0.0);
+    let c = world
+        .spawn((Part::Brick, Position(Vec3::new(2.0, 0.0, 0.0))))
+        .id();
+    update.run(&mut world);
+    assert_eq!(
+        world.resource::<SpatialIndex>().neighbours(a),
+        vec![c],
+        "{} - Moved and spawned",
+        message
+    );
+
+    world.despawn(c);
+    update.run(&mut world);
+    let index = world.resource::<SpatialIndex>();
+    assert!(index.neighbours(a).is_empty(), "{} - Despawned", message);
+    assert_eq!(index.len(), 2, "{}", message);
+}
+
+#[test]
+pub fn spatial_many_models() {
+    let message = "Testing build_models on a large scene";
+    let (mut world, mut sched_start, _) = util_setup();
+
+    // Stacks of three a stud apart, every stack is its own model
+    let (columns, rows) = (40, 30);
+    for x in 0..columns {
+        for z in 0..rows {
+            for y in 0..3 {
+                spawn_p(
+                    &mut world,
+                    false,
+                    Vec3::new(x as f32 * 5.0, y as f32, z as f32 * 3.0),
+                );
+            }
+        }
+    }
+    sched_start.run(&mut world);
+
+    let mut models = world.query::<(&Model, &Children)>();
+    assert_eq!(
+        models.iter(&world).count(),
+        columns * rows,
+        "{} - Model count",
+        message
+    );
+    assert!(
+        models.iter(&world).all(|(_, children)| children.len() == 3),
+        "{} - Stack size",
+        message
+    );
+}
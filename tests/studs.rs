@@ -0,0 +1,219 @@
+use freebricks::{
+    ecs::{
+        common::{Position, Size},
+        parts::{Part, StudInfo, StudType},
+        physics::Physical,
+    },
+    render::{bricks::WEDGE_STUD_MASK, texture::STUD_LAYERS},
+    scene::{BinarySceneReader, PartDesc, Scene, rbxlx::parse_rbxlx, write_binary},
+};
+use glam::Vec3;
+mod test_utils;
+use crate::test_utils::*;
+
+#[test]
+pub fn stud_layout() {
+    let message = "Testing stud layout encoding";
+    assert_eq!(
+        StudInfo::default().stud_layout(),
+        0x210000,
+        "{} - Outlet top and inlet bottom",
+        message
+    );
+
+    for top in StudType::ALL {
+        for bottom in StudType::ALL {
+            let layout = StudInfo {
+                top,
+                bottom,
+                ..StudInfo::FLAT
+            }
+            .stud_layout();
+            // Same decoding as bricks.wgsl, faces are groups of 4 vertices
+            let layer = |vertex: u32| (layout >> ((vertex / 4) * 4)) & 0xF;
+            for vertex in 0..16 {
+                assert_eq!(layer(vertex), 0, "{} - Sides are flat", message);
+            }
+            assert_eq!(layer(16), top as u32, "{}", message);
+            assert_eq!(layer(20), bottom as u32, "{}", message);
+            assert_eq!(layer(23), bottom as u32, "{}", message);
+            assert_eq!(
+                (layout & WEDGE_STUD_MASK) >> 16 & 0xF,
+                0,
+                "{} - Wedge slopes have no studs",
+                message
+            );
+        }
+    }
+
+    // Every side has its own layer, in the order of the face groups
+    let sides = StudInfo {
+        front: StudType::Outlet,
+        right: StudType::Inlet,
+        back: StudType::Universal,
+        left: StudType::Weld,
+        top: StudType::Glue,
+        bottom: StudType::Hinge,
+    };
+    assert_eq!(sides.stud_layout(), 0x654321, "{}", message);
+    assert_eq!(
+        sides.stud_layout() & WEDGE_STUD_MASK,
+        0x600001,
+        "{} - Wedges keep their front and bottom",
+        message
+    );
+
+    for (i, stud) in StudType::ALL.iter().enumerate() {
+        assert_eq!(StudType::from_u8(i as u8), Some(*stud), "{}", message);
+    }
+    assert_eq!(
+        StudType::from_u8(StudType::ALL.len() as u8),
+        None,
+        "{} - Free values aren't types yet",
+        message
+    );
+}
+
+#[test]
+pub fn stud_texture_layers() {
+    let message = "Testing the stud texture";
+    let image = image::open("assets/textures/studs.png").expect("Couldn't open studs.png");
+    assert_eq!(
+        image.height(),
+        image.width() * STUD_LAYERS,
+        "{} - A square layer for every stud type",
+        message
+    );
+}
+
+#[test]
+pub fn stud_connections() {
+    let message = "Testing which stud types connect";
+    use StudType::*;
+
+    let connects = [
+        (Outlet, Inlet),
+        (Outlet, Universal),
+        (Inlet, Universal),
+        (Universal, Universal),
+        (Weld, Flat),
+        (Glue, Smooth),
+        (Weld, Glue),
+    ];
+    let separate = [
+        (Outlet, Outlet),
+        (Inlet, Inlet),
+        (Flat, Flat),
+        (Smooth, Outlet),
+        (Hinge, Inlet),
+        (Motor, Weld),
+        (SteppingMotor, Glue),
+    ];
+    for (a, b) in connects {
+        assert!(
+            a.connects(b) && b.connects(a),
+            "{} - {:?} {:?}",
+            message,
+            a,
+            b
+        );
+    }
+    for (a, b) in separate {
+        assert!(
+            !a.connects(b) && !b.connects(a),
+            "{} - {:?} {:?}",
+            message,
+            a,
+            b
+        );
+    }
+
+    // Stacks of two, only the matching ones become models
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+    for (i, (a, b)) in connects.iter().chain(separate.iter()).enumerate() {
+        let x = i as f32 * 10.0;
+        for (y, top, bottom) in [(0.0, *a, Flat), (1.0, Flat, *b)] {
+            world.spawn((
+                Part::Brick,
+                Physical,
+                Position(Vec3::new(x, y, 0.0)),
+                Size(Vec3::new(4.0, 1.0, 2.0)),
+                StudInfo {
+                    top,
+                    bottom,
+                    ..StudInfo::FLAT
+                },
+            ));
+        }
+    }
+    sched_start.run(&mut world);
+    sched_update.run(&mut world);
+    assert_eq!(get_models(&mut world).len(), connects.len(), "{}", message);
+}
+
+#[test]
+pub fn stud_scenes() {
+    let message = "Testing stud types in scenes";
+    let scene = Scene {
+        parts: StudType::ALL
+            .iter()
+            .map(|&top| PartDesc {
+                studs: StudInfo {
+                    top,
+                    bottom: StudType::Universal,
+                    ..StudInfo::FLAT
+                },
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    };
+
+    let loaded = Scene::from_ron(&scene.to_ron().unwrap()).expect("Couldn't read scene");
+    assert_eq!(scene, loaded, "{} - Text", message);
+
+    let mut buffer = Vec::new();
+    write_binary(&scene, &mut buffer).unwrap();
+    let loaded = BinarySceneReader::new(buffer.as_slice())
+        .unwrap()
+        .into_scene()
+        .unwrap();
+    let studs: Vec<_> = loaded.parts.iter().map(|p| p.studs).collect();
+    let expected: Vec<_> = scene.parts.iter().map(|p| p.studs).collect();
+    assert_eq!(studs, expected, "{} - Binary", message);
+
+    // SurfaceType values from a place file
+    let place = r#"<roblox version="4">
+        <Item class="Part" referent="RBX0">
+            <Properties>
+                <token name="TopSurface">5</token>
+                <token name="BottomSurface">2</token>
+            </Properties>
+        </Item>
+        <Item class="Part" referent="RBX1">
+            <Properties>
+                <token name="TopSurface">7</token>
+                <token name="BottomSurface">10</token>
+            </Properties>
+        </Item>
+    </roblox>"#;
+    let import = parse_rbxlx(place).expect("Couldn't parse place");
+    let studs: Vec<_> = import.scene.parts.iter().map(|p| p.studs).collect();
+    assert_eq!(
+        studs,
+        vec![
+            StudInfo {
+                top: StudType::Universal,
+                bottom: StudType::Weld,
+                ..StudInfo::FLAT
+            },
+            StudInfo {
+                top: StudType::Motor,
+                bottom: StudType::Smooth,
+                ..StudInfo::FLAT
+            },
+        ],
+        "{}",
+        message
+    );
+}
@@ -0,0 +1,100 @@
+use freebricks::{
+    common::asset_cache::{Asset, AssetCache},
+    scene::terrain::{TerrainOptions, generate_terrain},
+};
+use glam::{Vec2, Vec3};
+use image::{GrayImage, ImageFormat, Luma};
+use std::io::Cursor;
+mod test_utils;
+use crate::test_utils::*;
+
+/// Asset cache holding a single greyscale png
+fn heightmap_cache(name: &str, pixels: &[(u32, u32, u8)]) -> AssetCache {
+    let mut image = GrayImage::new(2, 2);
+    for &(x, y, value) in pixels {
+        image.put_pixel(x, y, Luma([value]));
+    }
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .expect("Couldn't encode heightmap");
+
+    let mut assets = AssetCache::default();
+    assets.map.insert(name.to_string(), Asset::Image(png));
+    assets
+}
+
+#[test]
+pub fn terrain_columns() {
+    let message = "Testing heightmap terrain";
+    let assets = heightmap_cache(
+        "height.png",
+        &[(0, 0, 0), (1, 0, 128), (0, 1, 255), (1, 1, 64)],
+    );
+    let options = TerrainOptions {
+        heightmap: "height.png".to_string(),
+        footprint: Vec2::new(4.0, 4.0),
+        column_width: 2.0,
+        origin: Vec3::new(10.0, 0.0, 0.0),
+        ..Default::default()
+    };
+    let bands = options.bands.clone();
+    let scene = generate_terrain(&assets, &options).expect("Couldn't generate terrain");
+
+    // 1 brick for black, 8 bricks split by the first band, 16 split by both, 4 in one piece
+    let parts = &scene.parts;
+    assert_eq!(parts.len(), 7, "{} - Columns weren't coalesced", message);
+    assert!(parts.iter().all(|p| p.anchor), "{} - Anchored", message);
+
+    assert_eq!(parts[0].size, Vec3::new(2.0, 1.0, 2.0), "{}", message);
+    assert_eq!(parts[0].position, Vec3::new(11.0, 0.5, 1.0), "{}", message);
+    assert_eq!(parts[0].color, bands[0].color, "{}", message);
+
+    let tall: Vec<_> = parts[1..4].iter().map(|p| (p.size.y, p.color)).collect();
+    assert_eq!(
+        tall,
+        vec![
+            (6.0, bands[0].color),
+            (5.0, bands[1].color),
+            (5.0, bands[2].color)
+        ],
+        "{} - Color bands",
+        message
+    );
+    assert_eq!(parts[3].position, Vec3::new(11.0, 13.5, 3.0), "{}", message);
+
+    assert_eq!(parts[4].size.y + parts[5].size.y, 8.0, "{}", message);
+    assert_eq!(parts[6].size, Vec3::new(2.0, 4.0, 2.0), "{}", message);
+
+    let (mut world, _, _) = util_setup();
+    let entities = scene.load(&mut world);
+    assert_eq!(get_models(&mut world).len(), 0, "{}", message);
+    guarantee(
+        &mut world,
+        message,
+        entities[0],
+        false,
+        true,
+        false,
+        false,
+        true,
+        false,
+    );
+}
+
+#[test]
+pub fn terrain_missing_heightmap() {
+    let assets = heightmap_cache("height.png", &[]);
+    let options = TerrainOptions {
+        heightmap: "missing.png".to_string(),
+        ..Default::default()
+    };
+    assert!(generate_terrain(&assets, &options).is_err());
+
+    let options = TerrainOptions {
+        heightmap: "height.png".to_string(),
+        column_width: 0.0,
+        ..Default::default()
+    };
+    assert!(generate_terrain(&assets, &options).is_err());
+}
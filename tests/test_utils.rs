@@ -15,15 +15,6 @@ use freebricks::{
 use glam::Vec3;
 use rapier3d::prelude::RigidBodyType;
 
-#[derive(Component)]
-struct Tag1;
-
-#[derive(Component)]
-struct Tag2;
-
-#[derive(Component)]
-struct Tag3;
-
 #[allow(dead_code)]
 pub fn util_setup() -> (World, Schedule, Schedule) {
     let mut world = World::new();
@@ -0,0 +1,122 @@
+use bevy_ecs::prelude::*;
+use freebricks::{
+    ecs::{
+        common::{Color, Position, Rotation, Size},
+        material::Material,
+        parts::{Part, StudInfo},
+    },
+    render::{
+        bricks::BrickUniform,
+        scene_tree::remove_brick_slots,
+        transparent::{MeshKey, TransparentSet, is_transparent},
+    },
+};
+use glam::{Mat4, Vec3};
+
+fn glass(position: Vec3) -> BrickUniform {
+    Part::Brick.to_uniform(
+        &Position(position),
+        &Rotation::default(),
+        &Size::default(),
+        &Color([200, 220, 255, 128]),
+        &StudInfo::default(),
+        &Material::Glass,
+    )
+}
+
+#[test]
+pub fn transparent_sort() {
+    let message = "Testing transparent parts sort back to front";
+    let mut world = World::new();
+    let mut set = TransparentSet::default();
+
+    // Spread along the camera's line of sight, out of order
+    let eye = Vec3::new(0.0, 0.0, 50.0);
+    let depths = [10.0, -30.0, 40.0, 0.0, -5.0];
+    let entities: Vec<Entity> = depths
+        .iter()
+        .map(|&z| {
+            let entity = world.spawn_empty().id();
+            let key = if z < 0.0 {
+                MeshKey::Primitive(Part::Ball)
+            } else {
+                MeshKey::Brick
+            };
+            set.push(entity, key, glass(Vec3::new(0.0, 0.0, z)));
+            entity
+        })
+        .collect();
+
+    set.sort(Mat4::look_at_rh(eye, Vec3::ZERO, Vec3::Y));
+    let sorted: Vec<f32> = set
+        .order
+        .iter()
+        .map(|&slot| set.instances[slot as usize].model[3][2])
+        .collect();
+    assert_eq!(
+        sorted,
+        vec![-30.0, -5.0, 0.0, 10.0, 40.0],
+        "{} - Furthest first",
+        message
+    );
+
+    // Neighbours sharing a mesh are drawn together, in the sorted order
+    let runs: Vec<(MeshKey, u32, u32)> = set
+        .runs()
+        .iter()
+        .map(|(key, range)| (key.clone(), range.start, range.end))
+        .collect();
+    assert_eq!(
+        runs,
+        vec![
+            (MeshKey::Primitive(Part::Ball), 0, 2),
+            (MeshKey::Brick, 2, 5)
+        ],
+        "{}",
+        message
+    );
+
+    // Turning the camera around flips it
+    set.sort(Mat4::look_at_rh(-eye, Vec3::ZERO, Vec3::Y));
+    assert_eq!(
+        set.instances[set.order[0] as usize].model[3][2], 40.0,
+        "{} - Camera moved",
+        message
+    );
+
+    // Removing swaps the last part in, like the opaque batches
+    assert_eq!(set.remove(1), Some(entities[4]), "{}", message);
+    assert!(set.owns(entities[4], 1), "{}", message);
+    assert!(!set.owns(entities[1], 1), "{}", message);
+    set.sort(Mat4::look_at_rh(eye, Vec3::ZERO, Vec3::Y));
+    assert_eq!(set.order.len(), 4, "{}", message);
+
+    assert!(is_transparent([0, 0, 0, 254]), "{}", message);
+    assert!(!is_transparent([0, 0, 0, 255]), "{}", message);
+}
+
+#[test]
+pub fn transparent_then_despawn() {
+    let message = "Testing opaque slots after a part turns opaque again and another is removed";
+    let brick = |x: f32| glass(Vec3::new(x, 0.0, 0.0));
+    let x = |uniform: &BrickUniform| uniform.model[3][0];
+
+    // Entities a, b, c spawn in that order, b's alpha drops and its slot is cleaned up
+    let mut bricks = vec![brick(0.0), brick(1.0), brick(2.0)];
+    let mut a = 0;
+    let mut c = 2;
+    let shift = remove_brick_slots(&mut bricks, &[1]);
+    (a, c) = (shift(a), shift(c));
+
+    // b turns opaque again and goes to the end, behind c
+    bricks.push(brick(1.0));
+    let mut b = bricks.len() as u32 - 1;
+    assert_eq!((a, b, c), (0, 2, 1), "{}", message);
+
+    // a is despawned, b and c keep their own uniforms instead of swapping
+    let shift = remove_brick_slots(&mut bricks, &[a]);
+    (b, c) = (shift(b), shift(c));
+    assert_eq!(bricks.len(), 2, "{}", message);
+    assert_eq!(x(&bricks[b as usize]), 1.0, "{} - b", message);
+    assert_eq!(x(&bricks[c as usize]), 2.0, "{} - c", message);
+}
@@ -0,0 +1,221 @@
+use bevy_ecs::prelude::*;
+use freebricks::{
+    common::mesh::Mesh,
+    ecs::{
+        common::{Position, Size},
+        parts::Part,
+        physics::{Anchor, Physical, ShapeHandle},
+    },
+    physics::PhysicsState,
+    render::bricks::{INDICES, VERTICES, wedge_vertices},
+    scene::{BinarySceneReader, Scene, gltf::export_glb, rbxlx::parse_rbxlx, write_binary},
+};
+use glam::Vec3;
+use rapier3d::prelude::ShapeType;
+mod test_utils;
+use crate::test_utils::*;
+
+fn spawn_wedge(world: &mut World, position: Vec3) -> Entity {
+    world
+        .spawn((Part::Wedge, Physical, Position(position)))
+        .id()
+}
+
+#[test]
+pub fn wedge_mesh() {
+    let message = "Testing the wedge mesh";
+    let vertices = wedge_vertices();
+    assert_eq!(
+        vertices.len(),
+        VERTICES.len(),
+        "{} - Same layout as the brick",
+        message
+    );
+
+    // Nothing is left above the back edge
+    assert!(
+        vertices
+            .iter()
+            .all(|v| !(v.position[1] > 0.0 && v.position[2] < 0.0)),
+        "{}",
+        message
+    );
+
+    let mut areas = [0.0; 6];
+    for triangle in INDICES.chunks(3) {
+        let [a, b, c] =
+            [0, 1, 2].map(|i| Vec3::from_array(vertices[triangle[i] as usize].position));
+        let cross = (b - a).cross(c - a);
+        let face = triangle[0] as usize / 4;
+        areas[face] += cross.length() / 2.0;
+        if cross.length() < 1e-6 {
+            continue;
+        }
+        // Clockwise from outside, like the brick
+        let normal = Vec3::from_array(vertices[triangle[0] as usize].normals);
+        assert!(
+            (-cross.normalize()).abs_diff_eq(normal, 1e-5),
+            "{} - Face {} is wound against its normal {}",
+            message,
+            face,
+            normal
+        );
+    }
+
+    let slope = 2.0_f32.sqrt();
+    let expected = [1.0, 0.5, 0.0, 0.5, slope, 1.0];
+    for (face, (area, expected)) in areas.iter().zip(expected).enumerate() {
+        assert!(
+            (area - expected).abs() < 1e-5,
+            "{} - Face {} has area {}, expected {}",
+            message,
+            face,
+            area,
+            expected
+        );
+    }
+}
+
+#[test]
+pub fn wedge_collider() {
+    let message = "Testing wedge colliders";
+    let (mut world, mut sched_start, _) = util_setup();
+    let wedge = spawn_wedge(&mut world, Vec3::new(0.0, 5.0, 0.0));
+    let anchored = world
+        .spawn((Part::Wedge, Anchor, Position(Vec3::ZERO)))
+        .id();
+    sched_start.run(&mut world);
+
+    for entity in [wedge, anchored] {
+        let handle = world.get::<ShapeHandle>(entity).expect("No collider").0;
+        let collider = &world.resource::<PhysicsState>().colliders[handle];
+        assert_eq!(
+            collider.shape().shape_type(),
+            ShapeType::ConvexPolyhedron,
+            "{}",
+            message
+        );
+        let extents = collider.compute_aabb().extents();
+        assert!(
+            extents.x == 4.0 && extents.y == 1.0 && extents.z == 2.0,
+            "{} - Hull should fill the part's size, was {}",
+            message,
+            extents
+        );
+    }
+}
+
+#[test]
+pub fn wedge_snapping() {
+    let message = "Testing wedge snapping";
+    let (mut world, mut sched_start, mut sched_update) = util_setup();
+
+    // Wedge on a brick snaps with its flat bottom
+    let brick = spawn_p(&mut world, false, Vec3::ZERO);
+    let wedge = spawn_wedge(&mut world, Vec3::new(0.0, 1.0, 0.0));
+
+    // Nothing snaps onto a slope
+    let slope = spawn_wedge(&mut world, Vec3::new(10.0, 0.0, 0.0));
+    let loose = spawn_p(&mut world, false, Vec3::new(10.0, 1.0, 0.0));
+
+    sched_start.run(&mut world);
+    sched_update.run(&mut world);
+
+    let models = get_models(&mut world);
+    assert_eq!(models.len(), 1, "{} - Only one model", message);
+    guarantee_model(&mut world, message, models[0], 2, 0, 1);
+    for entity in [brick, wedge] {
+        guarantee(
+            &mut world, message, entity, false, false, true, false, true, false,
+        );
+    }
+    for entity in [slope, loose] {
+        guarantee(
+            &mut world, message, entity, false, false, false, false, true, true,
+        );
+    }
+}
+
+#[test]
+pub fn wedge_scenes() {
+    let message = "Testing wedges in scenes";
+    let mut world = World::new();
+    world.spawn((Part::Wedge, Position(Vec3::new(0.0, 0.5, 0.0))));
+    world.spawn((
+        Part::Brick,
+        Position(Vec3::new(0.0, 1.5, 0.0)),
+        Size(Vec3::new(2.0, 1.0, 2.0)),
+    ));
+
+    let scene = Scene::from_world(&mut world);
+    let loaded = Scene::from_ron(&scene.to_ron().unwrap()).expect("Couldn't read scene");
+    assert_eq!(
+        loaded
+            .parts
+            .iter()
+            .filter(|p| p.part == Part::Wedge)
+            .count(),
+        1,
+        "{} - Text",
+        message
+    );
+
+    let mut buffer = Vec::new();
+    write_binary(&scene, &mut buffer).unwrap();
+    let loaded = BinarySceneReader::new(buffer.as_slice())
+        .unwrap()
+        .into_scene()
+        .unwrap();
+    assert_eq!(
+        loaded
+            .parts
+            .iter()
+            .filter(|p| p.part == Part::Wedge)
+            .count(),
+        1,
+        "{} - Binary",
+        message
+    );
+
+    // Exported wedges keep their slope, nothing at the top of the back edge
+    let mut world = World::new();
+    world.spawn(Part::Wedge);
+    let mut glb = Vec::new();
+    export_glb(&mut world, &mut glb).expect("Couldn't export glb");
+    let mesh = Mesh::from_glb(&glb).expect("Couldn't parse exported glb");
+    let top = mesh.positions.iter().map(|p| p.y).fold(f32::MIN, f32::max);
+    let back = mesh.positions.iter().map(|p| p.z).fold(f32::MAX, f32::min);
+    assert!(
+        !mesh
+            .positions
+            .iter()
+            .any(|p| p.y > top - 1e-4 && p.z < back + 1e-4),
+        "{} - glTF",
+        message
+    );
+
+    // Turned wedges keep their rotation, the slope has to face the right way
+    let place = r#"<roblox version="4">
+        <Item class="WedgePart" referent="RBX0">
+            <Properties>
+                <CoordinateFrame name="CFrame">
+                    <X>0</X><Y>0.6</Y><Z>0</Z>
+                    <R00>-1</R00><R01>0</R01><R02>0</R02>
+                    <R10>0</R10><R11>1</R11><R12>0</R12>
+                    <R20>0</R20><R21>0</R21><R22>-1</R22>
+                </CoordinateFrame>
+                <Vector3 name="size"><X>4</X><Y>1.2</Y><Z>2</Z></Vector3>
+            </Properties>
+        </Item>
+    </roblox>"#;
+    let import = parse_rbxlx(place).expect("Couldn't parse place");
+    assert!(import.unsupported_classes.is_empty(), "{}", message);
+    let wedge = &import.scene.parts[0];
+    assert_eq!(wedge.part, Part::Wedge, "{}", message);
+    assert!(
+        (wedge.rotation * Vec3::Z).abs_diff_eq(Vec3::NEG_Z, 1e-5),
+        "{} - Rotation was baked away",
+        message
+    );
+    assert_eq!(wedge.size, Vec3::new(4.0, 1.0, 2.0), "{}", message);
+}